@@ -0,0 +1,49 @@
+use amethyst_bsp::{
+    bsp::Bsp, face_groups, test_support::FixtureBuilder, BspExtensions, FaceGroup, ImportOptions,
+};
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::io::Cursor;
+
+/// About 200,000 vertices, as many as the world of a large map.
+const FLOOR_TILES: usize = 128;
+
+fn fixture() -> Bsp {
+    let bytes = FixtureBuilder::new().with_floor_tiles(FLOOR_TILES).build();
+    Bsp::read(Cursor::new(bytes)).unwrap()
+}
+
+/// How bounds were computed before `FaceGroup::bounds`, to compare against.
+fn fold_bounds(group: &FaceGroup) -> Option<([f32; 3], [f32; 3])> {
+    let mut points = group.positions.iter().cloned();
+    let first = points.next()?;
+
+    Some(points.fold((first, first), |(mut mins, mut maxs), p| {
+        for i in 0..3 {
+            mins[i] = mins[i].min(p[i]);
+            maxs[i] = maxs[i].max(p[i]);
+        }
+        (mins, maxs)
+    }))
+}
+
+fn vertices(c: &mut Criterion) {
+    let bsp = fixture();
+    let options = ImportOptions::<()>::default();
+    let extensions = BspExtensions::default();
+    let groups = face_groups(&bsp, &extensions, &options);
+
+    c.bench_function("face_groups", move |b| {
+        b.iter(|| face_groups(&bsp, &extensions, &options))
+    });
+
+    let folded = groups.clone();
+    c.bench_function("bounds_fold", move |b| {
+        b.iter(|| folded.iter().map(fold_bounds).collect::<Vec<_>>())
+    });
+    c.bench_function("bounds", move |b| {
+        b.iter(|| groups.iter().map(FaceGroup::bounds).collect::<Vec<_>>())
+    });
+}
+
+criterion_group!(benches, vertices);
+criterion_main!(benches);
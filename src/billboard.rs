@@ -0,0 +1,167 @@
+use crate::{mesh::FaceGroup, shader::Shader};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::{
+        nalgebra::{Matrix3, Rotation3, UnitQuaternion, Vector3},
+        GlobalTransform, Transform,
+    },
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, Join, ReadStorage, System, WriteStorage},
+    renderer::Camera,
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// A quad from a `deformVertexes autosprite` or `autosprite2` surface, kept facing the camera by
+/// `BillboardSystem`. Its mesh lies in the entity's XY plane, facing +Z, with its longest side
+/// along Y.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct Billboard {
+    /// For `autosprite2`, the world space axis the quad only turns around. `autosprite` quads
+    /// turn freely and keep Y up.
+    pub axis: Option<[f32; 3]>,
+}
+
+impl Component for Billboard {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Whether a shader turns its surfaces into billboards, and if so, whether they're `autosprite2`.
+pub(crate) fn autosprite(shader: &Shader) -> Option<bool> {
+    shader
+        .directive("deformvertexes")
+        .filter_map(|d| d.args.first())
+        .filter_map(|kind| match kind.to_lowercase().as_str() {
+            "autosprite" => Some(false),
+            "autosprite2" => Some(true),
+            _ => None,
+        })
+        .next()
+}
+
+fn vector([x, y, z]: [f32; 3]) -> Vector3<f32> {
+    Vector3::new(x, y, z)
+}
+
+/// Split a face group into a group per quad, each moved into the local space of a `Billboard`,
+/// along with the translation and rotation that put it back where it was. Quads are expected to
+/// be stored as two triangles, and groups that aren't made of them are not split.
+pub(crate) fn split_sprites(
+    group: &FaceGroup,
+    axial: bool,
+) -> Option<Vec<(FaceGroup, Billboard, Transform)>> {
+    const QUAD: usize = 6;
+
+    if group.vertex_count() == 0 || group.vertex_count() % QUAD != 0 {
+        return None;
+    }
+
+    let sprites = (0..group.vertex_count())
+        .step_by(QUAD)
+        .map(|start| {
+            let range = start..start + QUAD;
+            let positions = group.positions[range.clone()]
+                .iter()
+                .map(|&p| vector(p))
+                .collect::<Vec<_>>();
+
+            let centre = positions.iter().sum::<Vector3<f32>>() / QUAD as f32;
+
+            // Of the first triangle's edges, the longest is the quad's diagonal, and the longer
+            // of the other two is the quad's long side.
+            let mut edges = [
+                positions[1] - positions[0],
+                positions[2] - positions[1],
+                positions[0] - positions[2],
+            ];
+            edges.sort_by(|a, b| {
+                a.norm()
+                    .partial_cmp(&b.norm())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let normal = vector(group.normals[start]).normalize();
+            let up = (edges[1] - normal * edges[1].dot(&normal)).normalize();
+            let right = up.cross(&normal);
+
+            let frame = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(
+                Matrix3::from_columns(&[right, up, normal]),
+            ));
+            let to_local = frame.inverse();
+
+            let sprite = FaceGroup {
+                model: group.model,
+                cluster: group.cluster,
+                texture: group.texture,
+                texture_name: group.texture_name.clone(),
+                lightmap_page: group.lightmap_page,
+                styles: group.styles,
+                face_count: 1,
+                positions: positions
+                    .iter()
+                    .map(|p| (to_local * (p - centre)).into())
+                    .collect(),
+                normals: group.normals[range.clone()]
+                    .iter()
+                    .map(|&n| (to_local * vector(n)).into())
+                    .collect(),
+                tex_coords: group.tex_coords[range.clone()].to_vec(),
+                lightmap_coords: group.lightmap_coords[range.clone()].to_vec(),
+                colors: group.colors[range].to_vec(),
+            };
+
+            let mut transform = Transform::default();
+            transform.set_position(centre);
+            transform.set_rotation(frame);
+
+            let billboard = Billboard {
+                axis: if axial { Some(up.into()) } else { None },
+            };
+
+            (sprite, billboard, transform)
+        })
+        .collect();
+
+    Some(sprites)
+}
+
+/// Turns `Billboard`s towards the first camera. Only the local rotation is set, so billboards
+/// should not be parented to rotated entities.
+#[derive(Default)]
+pub struct BillboardSystem;
+
+impl<'a> System<'a> for BillboardSystem {
+    type SystemData = (
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Billboard>,
+        ReadStorage<'a, GlobalTransform>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(&mut self, (cameras, billboards, globals, mut transforms): Self::SystemData) {
+        let camera = match (&cameras, &globals).join().next() {
+            Some((_, global)) => Vector3::new(global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]),
+            None => return,
+        };
+
+        for (billboard, global, transform) in (&billboards, &globals, &mut transforms).join() {
+            let position = Vector3::new(global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]);
+            let to_camera = camera - position;
+
+            let (facing, up) = match billboard.axis {
+                Some(axis) => {
+                    let axis = vector(axis);
+                    (to_camera - axis * to_camera.dot(&axis), axis)
+                }
+                None => (to_camera, Vector3::y()),
+            };
+
+            // Looking straight along the axis leaves no direction to face.
+            if facing.norm() < 1e-4 || facing.cross(&up).norm() < 1e-4 {
+                continue;
+            }
+
+            transform.set_rotation(UnitQuaternion::new_observer_frame(&facing, &up));
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{
+    flags::{self, CONTENTS_ORIGIN},
+    geometry::{ConvexHull, Plane},
+    to_world_space,
+};
+use bsp::Bsp;
+
+pub(crate) fn range<T>(items: &[T], start: i32, count: i32) -> &[T] {
+    if start < 0 || count <= 0 {
+        return &[];
+    }
+
+    let start = start as usize;
+    items.get(start..start + count as usize).unwrap_or(&[])
+}
+
+pub(crate) fn model_brushes<'a>(bsp: &'a Bsp, model: &bsp::Model) -> &'a [bsp::Brush] {
+    range(&bsp.brushes, model.brush, model.n_brushes)
+}
+
+pub(crate) fn brush_sides<'a>(bsp: &'a Bsp, brush: &bsp::Brush) -> &'a [bsp::BrushSide] {
+    range(&bsp.brush_sides, brush.brush_side, brush.n_brush_sides)
+}
+
+pub(crate) fn brush_texture<'a>(bsp: &'a Bsp, brush: &bsp::Brush) -> Option<&'a bsp::Texture> {
+    bsp.texture(brush.texture as usize)
+}
+
+/// The planes bounding a brush, converted to world space.
+pub(crate) fn brush_planes(bsp: &Bsp, brush: &bsp::Brush) -> Vec<Plane> {
+    brush_sides(bsp, brush)
+        .iter()
+        .filter_map(|side| bsp.planes.get(side.plane as usize))
+        .map(|plane| Plane {
+            normal: to_world_space(plane.normal),
+            dist: plane.dist,
+        })
+        .collect()
+}
+
+pub(crate) fn brush_hull(bsp: &Bsp, brush: &bsp::Brush) -> Option<ConvexHull> {
+    ConvexHull::from_planes(brush_planes(bsp, brush))
+}
+
+/// The centre of a model's origin brush, in world space. q3map removes origin brushes and
+/// stores the model relative to them, but other compilers leave them in the model.
+pub(crate) fn origin_brush_centre(bsp: &Bsp, model: &bsp::Model) -> Option<[f32; 3]> {
+    model_brushes(bsp, model)
+        .iter()
+        .filter(|brush| {
+            brush_texture(bsp, brush).map_or(false, |t| flags::contents(t) & CONTENTS_ORIGIN != 0)
+        })
+        .filter_map(|brush| brush_hull(bsp, brush))
+        .map(|hull| {
+            let mut centre = [0.0; 3];
+            for i in 0..3 {
+                centre[i] = (hull.mins[i] + hull.maxs[i]) / 2.0;
+            }
+            centre
+        })
+        .next()
+}
@@ -0,0 +1,113 @@
+use crate::light_styles::LightStyles;
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, Entity, HashMapStorage, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Seek, SeekFrom};
+
+const LUMPS: usize = 17;
+const BSPX_NAME_LEN: usize = 24;
+
+/// A lump of the BSPX extension, which some community compilers append after the standard lumps.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BspxLump {
+    pub name: String,
+    pub offset: u32,
+    pub len: u32,
+}
+
+/// The extensions found after the standard lumps of a map, attached to the map's root.
+/// `LMSTYLE` gives the light styles of each face, which bare IBSP faces don't store, and is the
+/// only lump the importer applies. Every other lump, including `LMSHIFT`'s lightmap scale and
+/// `RGBLIGHTING`, is listed in `bspx` but otherwise ignored: Quake 3 faces carry their own
+/// lightmap coordinates, so the importer never works out lightmap sizes from a scale. Tools that
+/// need them can read the lumps at the listed offsets.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct BspExtensions {
+    pub bspx: Vec<BspxLump>,
+    /// The styles of each face from `LMSTYLE`, in face lump order, or empty without one.
+    #[serde(skip)]
+    pub face_styles: Vec<LightStyles>,
+}
+
+impl Component for BspExtensions {
+    type Storage = HashMapStorage<Self>;
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+impl BspExtensions {
+    pub fn has(&self, name: &str) -> bool {
+        self.bspx
+            .iter()
+            .any(|lump| lump.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Look for extensions after the standard lumps of a map, leaving the reader where it was.
+    /// Maps without any give an empty list rather than an error.
+    pub fn read<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+        let start = reader.seek(SeekFrom::Current(0))?;
+        let out = Self::read_from(reader, start);
+        reader.seek(SeekFrom::Start(start))?;
+        out
+    }
+
+    fn read_from<R: Read + Seek>(reader: &mut R, start: u64) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(start + 8))?;
+
+        let mut end = 0;
+        for _ in 0..LUMPS {
+            let (offset, len) = (read_u32(reader)?, read_u32(reader)?);
+            end = end.max(offset as u64 + len as u64);
+        }
+
+        // The BSPX header is aligned to 4 bytes after the last lump.
+        reader.seek(SeekFrom::Start(start + (end + 3) / 4 * 4))?;
+        let mut magic = [0; 4];
+        if reader.read_exact(&mut magic).is_err() || &magic != b"BSPX" {
+            return Ok(Self::default());
+        }
+
+        let count = read_u32(reader)?;
+        let mut bspx = vec![];
+        for _ in 0..count {
+            let mut name = [0; BSPX_NAME_LEN];
+            reader.read_exact(&mut name)?;
+            let name_len = name.iter().position(|&b| b == 0).unwrap_or(BSPX_NAME_LEN);
+
+            bspx.push(BspxLump {
+                name: String::from_utf8_lossy(&name[..name_len]).into_owned(),
+                offset: read_u32(reader)?,
+                len: read_u32(reader)?,
+            });
+        }
+
+        let mut face_styles = vec![];
+        if let Some(lump) = bspx
+            .iter()
+            .find(|lump| lump.name.eq_ignore_ascii_case("LMSTYLE"))
+        {
+            // Read through `take` so a corrupt length can't allocate more than the file holds.
+            let mut bytes = vec![];
+            reader.seek(SeekFrom::Start(start + lump.offset as u64))?;
+            reader
+                .by_ref()
+                .take(lump.len as u64)
+                .read_to_end(&mut bytes)?;
+            if bytes.len() < lump.len as usize {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            face_styles = LightStyles::from_lmstyle(&bytes);
+        }
+
+        Ok(BspExtensions { bspx, face_styles })
+    }
+}
@@ -0,0 +1,72 @@
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, Entity, HashMapStorage, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What it costs to draw one cluster of the world, or the faces of one brush model, counted
+/// like Quake 3's `r_speeds`. Each face group is one draw call.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ClusterBudget {
+    pub model: usize,
+    /// The cluster, for faces of the world model.
+    pub cluster: Option<i32>,
+    pub draw_calls: usize,
+    pub vertices: usize,
+    /// Face groups are drawn sorted by texture, so this is also the number of texture switches.
+    pub textures: usize,
+    pub lightmap_pages: usize,
+}
+
+impl fmt::Display for ClusterBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cluster {
+            Some(cluster) => write!(f, "cluster {}", cluster)?,
+            None => write!(f, "model {}", self.model)?,
+        }
+        write!(
+            f,
+            ": {} draws, {} verts, {} textures, {} lightmaps",
+            self.draw_calls, self.vertices, self.textures, self.lightmap_pages
+        )
+    }
+}
+
+/// The `ClusterBudget` of everything imported, attached to the map's root when
+/// `ImportOptions::budget_report` is set. This is only a count of what the prefab contains, so
+/// it doesn't account for visibility or for renderers that batch draws.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct MapBudget {
+    pub clusters: Vec<ClusterBudget>,
+}
+
+impl Component for MapBudget {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl MapBudget {
+    /// The `count` most expensive clusters and models, by draw calls and then by vertices.
+    pub fn heaviest(&self, count: usize) -> Vec<&ClusterBudget> {
+        let mut out = self.clusters.iter().collect::<Vec<_>>();
+        out.sort_by_key(|budget| std::cmp::Reverse((budget.draw_calls, budget.vertices)));
+        out.truncate(count);
+        out
+    }
+}
+
+impl fmt::Display for MapBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (draws, verts) = self.clusters.iter().fold((0, 0), |(d, v), budget| {
+            (d + budget.draw_calls, v + budget.vertices)
+        });
+        write!(f, "{} draws, {} verts in total", draws, verts)?;
+        for budget in self.heaviest(self.clusters.len()) {
+            write!(f, "\n  {}", budget)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use crate::mesh::FaceGroup;
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, HashMapStorage, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The vertices and indices of every face group in a map, in one buffer each. This is attached
+/// to the root entity of maps imported with `ImportOptions::shared_geometry`, and face group
+/// entities get a `DrawRange` into it instead of a mesh of their own.
+///
+/// The renderer in amethyst 0.10 can't draw part of a mesh, so this needs a custom pass (or the
+/// `rendy` feature's `MapGeometry::rendy_mesh`) to be drawn.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct MapGeometry {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub lightmap_coords: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    #[serde(skip)]
+    vertex_ids: HashMap<[u32; 14], u32>,
+}
+
+impl Component for MapGeometry {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// The indices of a face group within its map's `MapGeometry`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct DrawRange {
+    /// The first index of the range, into `MapGeometry::indices`.
+    pub offset: u32,
+    pub count: u32,
+}
+
+impl Component for DrawRange {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl MapGeometry {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Append a face group, returning the range to draw it with. Vertices that are identical in
+    /// every attribute are only stored once, which is most of them since BSP faces are stored as
+    /// triangle lists.
+    pub fn push(&mut self, group: &FaceGroup) -> DrawRange {
+        let offset = self.indices.len() as u32;
+
+        for i in 0..group.vertex_count() {
+            let (position, normal, tex_coord, lightmap_coord, color) = (
+                group.positions[i],
+                group.normals[i],
+                group.tex_coords[i],
+                group.lightmap_coords[i],
+                group.colors[i],
+            );
+
+            let mut key = [0; 14];
+            for (k, v) in key.iter_mut().zip(
+                position
+                    .iter()
+                    .chain(&normal)
+                    .chain(&tex_coord)
+                    .chain(&lightmap_coord)
+                    .chain(&color),
+            ) {
+                *k = v.to_bits();
+            }
+
+            let next = self.positions.len() as u32;
+            let index = *self.vertex_ids.entry(key).or_insert(next);
+            if index == next {
+                self.positions.push(position);
+                self.normals.push(normal);
+                self.tex_coords.push(tex_coord);
+                self.lightmap_coords.push(lightmap_coord);
+                self.colors.push(color);
+            }
+
+            self.indices.push(index);
+        }
+
+        DrawRange {
+            offset,
+            count: self.indices.len() as u32 - offset,
+        }
+    }
+
+    /// Drop the lookup used to share vertices between pushes, so it isn't cloned along with the
+    /// prefab.
+    pub(crate) fn finish(mut self) -> Self {
+        self.vertex_ids = HashMap::new();
+        self
+    }
+
+    /// The indices of a range.
+    pub fn range(&self, range: DrawRange) -> &[u32] {
+        let start = range.offset as usize;
+        &self.indices[start..start + range.count as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LightStyles;
+
+    fn quad(texture: usize, x: f32) -> FaceGroup {
+        let corners = [
+            [x, 0.0, 0.0],
+            [x + 1.0, 0.0, 0.0],
+            [x + 1.0, 1.0, 0.0],
+            [x, 1.0, 0.0],
+        ];
+        let positions = [0, 1, 2, 0, 2, 3]
+            .iter()
+            .map(|&i| corners[i])
+            .collect::<Vec<_>>();
+        let n = positions.len();
+
+        FaceGroup {
+            model: 0,
+            cluster: Some(0),
+            texture,
+            texture_name: String::new(),
+            lightmap_page: None,
+            styles: LightStyles::default(),
+            face_count: 1,
+            positions,
+            normals: vec![[0.0, 0.0, 1.0]; n],
+            tex_coords: vec![[0.0; 2]; n],
+            lightmap_coords: vec![[0.0; 2]; n],
+            colors: vec![[1.0; 4]; n],
+        }
+    }
+
+    #[test]
+    fn shares_vertices_between_groups() {
+        let mut geometry = MapGeometry::default();
+
+        let first = geometry.push(&quad(0, 0.0));
+        // Shares an edge with the first quad.
+        let second = geometry.push(&quad(1, 1.0));
+
+        assert_eq!((first.offset, first.count), (0, 6));
+        assert_eq!((second.offset, second.count), (6, 6));
+        assert_eq!(geometry.vertex_count(), 6);
+        assert_eq!(geometry.range(second)[0], geometry.range(first)[1]);
+    }
+}
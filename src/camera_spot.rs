@@ -0,0 +1,117 @@
+use crate::{
+    entities::{self, MapEntity},
+    to_world_space, transform,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::{
+        nalgebra::{UnitQuaternion, Vector3},
+        Transform,
+    },
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum CameraSpotKind {
+    /// `info_player_intermission`, where Quake 3 puts the camera between matches.
+    Intermission,
+    /// `info_spectator_start`, where spectators join the game.
+    Spectator,
+}
+
+impl CameraSpotKind {
+    pub(crate) fn from_classname(classname: &str) -> Option<Self> {
+        match classname {
+            "info_player_intermission" => Some(CameraSpotKind::Intermission),
+            "info_spectator_start" => Some(CameraSpotKind::Spectator),
+            _ => None,
+        }
+    }
+}
+
+impl Default for CameraSpotKind {
+    fn default() -> Self {
+        CameraSpotKind::Intermission
+    }
+}
+
+/// A viewpoint placed by the mapper, for menu fly-bys, end of match cameras and spectators.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct CameraSpot {
+    pub kind: CameraSpotKind,
+    /// Where the camera goes, in world space relative to the map's root.
+    pub origin: [f32; 3],
+    /// The pitch, yaw and roll of the camera in degrees, as Quake gives them. Like Quake 3, spots
+    /// with a `target` look towards it instead of using their own angles.
+    pub angles: [f32; 3],
+}
+
+impl Component for CameraSpot {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl CameraSpot {
+    pub(crate) fn from_entity(kind: CameraSpotKind, entity: &MapEntity, bsp: &Bsp) -> Self {
+        let origin = entity.get_vec3("origin").unwrap_or_default();
+        let target = entity.get("target").and_then(|target| {
+            entities::parse_entities(entities::entity_string(bsp))
+                .into_iter()
+                .find(|e| e.get("targetname") == Some(target))?
+                .get_vec3("origin")
+        });
+
+        let angles = match target {
+            Some(target) => look_at(origin, target),
+            None => transform::entity_angles(entity).unwrap_or_default(),
+        };
+
+        CameraSpot {
+            kind,
+            origin: to_world_space(origin),
+            angles,
+        }
+    }
+
+    /// The camera's rotation in world space.
+    pub fn rotation(&self) -> UnitQuaternion<f32> {
+        transform::angles_to_rotation(self.angles)
+    }
+
+    pub fn transform(&self) -> Transform {
+        let [x, y, z] = self.origin;
+        let mut transform = Transform::default();
+        transform.set_position(Vector3::new(x, y, z));
+        transform.set_rotation(self.rotation());
+        transform
+    }
+}
+
+/// The angles looking from `from` towards `to`, both in BSP space, like Quake 3's
+/// `vectoangles`.
+fn look_at(from: [f32; 3], to: [f32; 3]) -> [f32; 3] {
+    let [x, y, z] = [to[0] - from[0], to[1] - from[1], to[2] - from[2]];
+    let yaw = y.atan2(x).to_degrees();
+    // Quake's pitch is positive looking down.
+    let pitch = -z.atan2((x * x + y * y).sqrt()).to_degrees();
+
+    [pitch, yaw, 0.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_at_targets() {
+        assert_eq!(look_at([0.0; 3], [10.0, 0.0, 0.0]), [0.0, 0.0, 0.0]);
+        assert_eq!(look_at([0.0; 3], [0.0, 10.0, 0.0]), [0.0, 90.0, 0.0]);
+
+        let [pitch, _, _] = look_at([0.0, 0.0, 10.0], [10.0, 0.0, 0.0]);
+        assert!((pitch - 45.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{BspPrefabElement, Extension};
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader, Prefab, ProgressCounter},
+    core::Parent,
+    ecs::{Component, Entities, Entity, HashMapStorage, Join, System, Write, WriteStorage},
+    shrev::EventChannel,
+};
+use std::{collections::VecDeque, marker::PhantomData};
+
+type MapPrefab<E> = Prefab<BspPrefabElement<E>>;
+
+/// A map imported with `import_bsp_chunked`, split into the prefab for its root entity (with
+/// the map's entities and brush models) and one prefab for each cluster.
+pub struct MapChunks<E: Extension = ()> {
+    pub root: MapPrefab<E>,
+    pub clusters: Vec<MapPrefab<E>>,
+}
+
+impl<E: Extension> MapChunks<E> {
+    /// Load every prefab, returning the handle to give the map's root entity along with the
+    /// `PendingChunks` component to add to it.
+    pub fn load(
+        self,
+        loader: &Loader,
+        storage: &AssetStorage<MapPrefab<E>>,
+        progress: &mut ProgressCounter,
+    ) -> (Handle<MapPrefab<E>>, PendingChunks<E>) {
+        let root = loader.load_from_data(self.root, &mut *progress, storage);
+        let chunks = self
+            .clusters
+            .into_iter()
+            .map(|cluster| loader.load_from_data(cluster, &mut *progress, storage))
+            .collect();
+
+        (root, PendingChunks { chunks })
+    }
+}
+
+/// Cluster prefabs still to be instantiated under the map's root entity.
+pub struct PendingChunks<E: Extension = ()> {
+    pub chunks: VecDeque<Handle<MapPrefab<E>>>,
+}
+
+impl<E: Extension> Component for PendingChunks<E> {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Sent once every cluster of a chunked map has been handed to the prefab loader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapInstantiated {
+    pub map: Entity,
+}
+
+/// Spawns the clusters in each map's `PendingChunks`, `per_frame` at a time, parented to the map.
+/// Once a map has no chunks left its `PendingChunks` is removed and a `MapInstantiated` is sent.
+pub struct ChunkedInstantiationSystem<E: Extension = ()> {
+    pub per_frame: usize,
+    marker: PhantomData<E>,
+}
+
+impl<E: Extension> ChunkedInstantiationSystem<E> {
+    pub fn new(per_frame: usize) -> Self {
+        ChunkedInstantiationSystem {
+            per_frame,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<E: Extension> Default for ChunkedInstantiationSystem<E> {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl<'a, E: Extension> System<'a> for ChunkedInstantiationSystem<E> {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, PendingChunks<E>>,
+        WriteStorage<'a, Handle<MapPrefab<E>>>,
+        WriteStorage<'a, Parent>,
+        Write<'a, EventChannel<MapInstantiated>>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, mut pending, mut handles, mut parents, mut events): Self::SystemData,
+    ) {
+        let mut finished = vec![];
+
+        for (map, pending) in (&entities, &mut pending).join() {
+            let count = self.per_frame.min(pending.chunks.len());
+
+            for handle in pending.chunks.drain(..count) {
+                let chunk = entities.create();
+
+                // Inserting can only fail for dead entities, and this one was just created.
+                let _ = handles.insert(chunk, handle);
+                let _ = parents.insert(chunk, Parent { entity: map });
+            }
+
+            if pending.chunks.is_empty() {
+                finished.push(map);
+            }
+        }
+
+        for map in finished {
+            pending.remove(map);
+            events.single_write(MapInstantiated { map });
+        }
+    }
+}
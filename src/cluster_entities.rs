@@ -0,0 +1,62 @@
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    ecs::{Entity, Write},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// The face group entities of every cluster of every loaded map, keyed by the `map_id` the map
+/// was imported with and the cluster id, so that gameplay code can find what is drawn in a
+/// cluster without joining over every `Cluster`. Filled in as maps are instantiated, and kept
+/// up to date by `despawn_map` and `MapReloadSystem`.
+#[derive(Debug, Default)]
+pub struct ClusterEntities(pub HashMap<(usize, i32), Vec<Entity>>);
+
+impl ClusterEntities {
+    pub fn get(&self, map: usize, cluster: i32) -> &[Entity] {
+        self.0
+            .get(&(map, cluster))
+            .map_or(&[], |entities| &entities[..])
+    }
+
+    pub(crate) fn remove_all(&mut self, removed: &HashSet<Entity>) {
+        for entities in self.0.values_mut() {
+            entities.retain(|entity| !removed.contains(entity));
+        }
+        self.0.retain(|_, entities| !entities.is_empty());
+    }
+}
+
+/// Adds a face group's entity to `ClusterEntities` when it is instantiated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ClusterMember {
+    pub map: usize,
+    pub cluster: i32,
+}
+
+impl<'a> PrefabData<'a> for ClusterMember {
+    type SystemData = Write<'a, ClusterEntities>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        clusters: &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        let entities = clusters.0.entry((self.map, self.cluster)).or_default();
+        if !entities.contains(&entity) {
+            entities.push(entity);
+        }
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        _: &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
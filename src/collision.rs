@@ -0,0 +1,254 @@
+use crate::{
+    brushes::{brush_hull, brush_sides, brush_texture, model_brushes, range},
+    flags::{self, CONTENTS_MONSTERCLIP, CONTENTS_PLAYERCLIP, CONTENTS_SOLID},
+    geometry::ConvexHull,
+    patch,
+    shader::ShaderLibrary,
+    surface::SurfaceMaterial,
+    to_world_space,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, Entity, HashMapStorage, WriteStorage},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+
+/// What a collision brush blocks. Clip brushes have no visible faces and only block movement of
+/// certain kinds of object, so consumers will usually want to put them in their own collision
+/// groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum CollisionKind {
+    Solid,
+    PlayerClip,
+    MonsterClip,
+    /// Blocks both players and monsters, like `common/fullclip`.
+    FullClip,
+}
+
+impl CollisionKind {
+    fn from_contents(contents: u32) -> Option<Self> {
+        let player = contents & CONTENTS_PLAYERCLIP != 0;
+        let monster = contents & CONTENTS_MONSTERCLIP != 0;
+
+        if contents & CONTENTS_SOLID != 0 {
+            Some(CollisionKind::Solid)
+        } else if player && monster {
+            Some(CollisionKind::FullClip)
+        } else if player {
+            Some(CollisionKind::PlayerClip)
+        } else if monster {
+            Some(CollisionKind::MonsterClip)
+        } else {
+            None
+        }
+    }
+
+    pub fn blocks_players(self) -> bool {
+        self != CollisionKind::MonsterClip
+    }
+
+    pub fn blocks_monsters(self) -> bool {
+        self != CollisionKind::PlayerClip
+    }
+}
+
+/// The texture of one side of a `CollisionBrush`, to tell what was hit.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CollisionSide {
+    pub texture: String,
+    pub surface_flags: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CollisionBrush {
+    pub hull: ConvexHull,
+    pub kind: CollisionKind,
+    pub contents: u32,
+    pub surface: SurfaceMaterial,
+    /// The index of the model this brush belongs to, where `0` is the world.
+    pub model: usize,
+    /// The index of the brush in the BSP's brush lump.
+    pub brush: usize,
+    /// The brush's own texture, which decides its contents.
+    pub texture: String,
+    pub surface_flags: u32,
+    /// The side for each of `hull.planes`, in the same order.
+    pub sides: Vec<CollisionSide>,
+}
+
+impl CollisionBrush {
+    /// The side that a hit on `hull.planes[plane]` struck, for impact effects and footsteps.
+    pub fn side(&self, plane: usize) -> Option<&CollisionSide> {
+        self.sides.get(plane)
+    }
+}
+
+/// A triangle mesh approximating a curved surface.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CollisionMesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+    pub kind: CollisionKind,
+    pub contents: u32,
+    pub surface: SurfaceMaterial,
+    pub model: usize,
+    /// The index of the patch in the BSP's face lump.
+    pub face: usize,
+    pub texture: String,
+    pub surface_flags: u32,
+}
+
+/// Convex collision geometry for a map, in world space, attached to the map's root entity.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct CollisionGeometry {
+    pub brushes: Vec<CollisionBrush>,
+    pub patches: Vec<CollisionMesh>,
+}
+
+impl Component for CollisionGeometry {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl Scale for CollisionGeometry {
+    fn scale(&mut self, scale: f32) {
+        for brush in &mut self.brushes {
+            brush.hull.scale(scale);
+        }
+        for patch in &mut self.patches {
+            patch.vertices.scale(scale);
+        }
+    }
+}
+
+impl CollisionGeometry {
+    /// Extract collision geometry. Patches are tessellated at `patch_level` subdivisions per
+    /// 3x3 sub-patch, which can be much coarser than the visual tessellation. `shaders` are used
+    /// to classify each brush's `SurfaceMaterial`.
+    pub fn new(bsp: &Bsp, patch_level: usize, shaders: Option<&ShaderLibrary>) -> Self {
+        let surface = |texture: &bsp::Texture| {
+            SurfaceMaterial::of_texture(texture, shaders.and_then(|s| s.get(&texture.name)))
+        };
+
+        let mut brushes = vec![];
+        let mut patches = vec![];
+
+        for (model_index, model) in bsp.models().enumerate() {
+            for (i, brush) in model_brushes(bsp, &model).iter().enumerate() {
+                let texture = match brush_texture(bsp, brush) {
+                    Some(texture) => texture,
+                    None => continue,
+                };
+                let (contents, surface) = (flags::contents(texture), surface(texture));
+                let kind = match CollisionKind::from_contents(contents) {
+                    Some(kind) => kind,
+                    None => continue,
+                };
+
+                // Skipping the same sides as `brush_planes`, so that they line up with the hull.
+                let sides = brush_sides(bsp, brush)
+                    .iter()
+                    .filter(|side| bsp.planes.get(side.plane as usize).is_some())
+                    .map(|side| match bsp.texture(side.texture as usize) {
+                        Some(texture) => CollisionSide {
+                            texture: texture.name.to_string(),
+                            surface_flags: flags::surface_flags(texture),
+                        },
+                        None => CollisionSide {
+                            texture: String::new(),
+                            surface_flags: 0,
+                        },
+                    })
+                    .collect();
+
+                if let Some(hull) = brush_hull(bsp, brush) {
+                    brushes.push(CollisionBrush {
+                        hull,
+                        kind,
+                        contents,
+                        surface,
+                        model: model_index,
+                        brush: model.brush as usize + i,
+                        texture: texture.name.to_string(),
+                        surface_flags: flags::surface_flags(texture),
+                        sides,
+                    });
+                }
+            }
+
+            let faces = range(&bsp.faces, model.face, model.n_faces);
+            for (i, face) in faces.iter().enumerate() {
+                let face = bsp::Handle::new(bsp, face);
+                if face.face_type != bsp::FaceType::Patch {
+                    continue;
+                }
+
+                let texture = match face.texture() {
+                    Some(texture) => texture,
+                    None => continue,
+                };
+                let (contents, surface) = (flags::contents(texture), surface(texture));
+                let kind = match CollisionKind::from_contents(contents) {
+                    Some(kind) => kind,
+                    None => continue,
+                };
+
+                let control = range(&bsp.vertices, face.vertex, face.n_vertexes)
+                    .iter()
+                    .map(|v| to_world_space(v.position))
+                    .collect::<Vec<_>>();
+                let (vertices, indices) = patch::tessellate(
+                    &control,
+                    face.size[0].max(0) as usize,
+                    face.size[1].max(0) as usize,
+                    patch_level,
+                );
+
+                if !indices.is_empty() {
+                    patches.push(CollisionMesh {
+                        vertices,
+                        indices,
+                        kind,
+                        contents,
+                        surface,
+                        model: model_index,
+                        face: model.face as usize + i,
+                        texture: texture.name.to_string(),
+                        surface_flags: flags::surface_flags(texture),
+                    });
+                }
+            }
+        }
+
+        CollisionGeometry { brushes, patches }
+    }
+
+    pub fn iter_kind<'a>(
+        &'a self,
+        kind: CollisionKind,
+    ) -> impl Iterator<Item = &'a CollisionBrush> + 'a {
+        self.brushes.iter().filter(move |b| b.kind == kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_kinds() {
+        assert_eq!(
+            CollisionKind::from_contents(CONTENTS_PLAYERCLIP | CONTENTS_MONSTERCLIP),
+            Some(CollisionKind::FullClip)
+        );
+        assert_eq!(
+            CollisionKind::from_contents(CONTENTS_MONSTERCLIP),
+            Some(CollisionKind::MonsterClip)
+        );
+        assert_eq!(CollisionKind::from_contents(0), None);
+        assert!(!CollisionKind::PlayerClip.blocks_monsters());
+    }
+}
@@ -0,0 +1,59 @@
+use amethyst::Error;
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// How a map file is compressed, as told by its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+}
+
+impl Compression {
+    fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(BZIP2_MAGIC) {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decompress a gzip or bzip2 compressed map, such as a `.bsp.gz`, leaving uncompressed maps
+/// as they are. No BSP dialect starts with either magic, so this can't mistake a map for an
+/// archive.
+pub(crate) fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let mut out = vec![];
+    match Compression::detect(&bytes) {
+        Some(Compression::Gzip) => GzDecoder::new(&bytes[..]).read_to_end(&mut out),
+        Some(Compression::Bzip2) => BzDecoder::new(&bytes[..]).read_to_end(&mut out),
+        None => return Ok(bytes),
+    }
+    .map_err(|e| Error::new(e))?;
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{write::GzEncoder, Compression as Level};
+    use std::io::Write;
+
+    #[test]
+    fn decompresses_gzip() {
+        let map = b"IBSP\x2e\0\0\0".to_vec();
+        let mut encoder = GzEncoder::new(vec![], Level::default());
+        encoder.write_all(&map).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decompress(compressed).unwrap(), map);
+        assert_eq!(decompress(map.clone()).unwrap(), map);
+    }
+}
@@ -0,0 +1,254 @@
+//! Wireframe drawing of a map's internal structure using amethyst's `DebugLines`, for diagnosing
+//! culling and collision problems. Add `BspDebugSystem` to the dispatcher along with the
+//! renderer's `DrawDebugLines` pass, then turn on what to draw in the `BspDebug` resource.
+
+use crate::{
+    geometry::{bounds_of, cross, dot},
+    to_bsp_space, to_world_space, vis, CollisionGeometry, MapVis,
+};
+use amethyst::{
+    core::{nalgebra::Point3, GlobalTransform},
+    ecs::{Join, Read, ReadStorage, System, Write},
+    renderer::{Camera, DebugLines, Rgba},
+};
+
+/// What `BspDebugSystem` draws. Everything is off by default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BspDebug {
+    /// Outline the bounds of every leaf outside solid, highlighting the leaf containing the
+    /// camera.
+    pub leaf_bounds: bool,
+    /// Draw the splitting plane of each node on the path from the root of the tree to the
+    /// camera's leaf, as a square of `plane_size` units centred under the camera.
+    pub split_planes: bool,
+    pub plane_size: f32,
+    /// Outline every brush in the map's `CollisionGeometry`, coloured by `CollisionKind`. Brushes
+    /// are drawn where they were compiled, even if their entity has since moved.
+    pub brushes: bool,
+    /// Outline the bounds of every cluster, coloured by whether it is visible from the camera's
+    /// cluster, to check the vis data against what `VisibilitySystem` hides.
+    pub pvs: bool,
+}
+
+impl Default for BspDebug {
+    fn default() -> Self {
+        BspDebug {
+            leaf_bounds: false,
+            split_planes: false,
+            plane_size: 256.0,
+            brushes: false,
+            pvs: false,
+        }
+    }
+}
+
+const LEAF_COLOR: Rgba = Rgba(0.2, 0.4, 1.0, 1.0);
+const CURRENT_LEAF_COLOR: Rgba = Rgba(1.0, 1.0, 0.0, 1.0);
+const PLANE_COLOR: Rgba = Rgba(1.0, 0.0, 1.0, 1.0);
+const CURRENT_CLUSTER_COLOR: Rgba = Rgba(1.0, 1.0, 0.0, 1.0);
+const VISIBLE_CLUSTER_COLOR: Rgba = Rgba(0.0, 1.0, 0.0, 1.0);
+const HIDDEN_CLUSTER_COLOR: Rgba = Rgba(0.6, 0.0, 0.0, 1.0);
+
+fn point(v: [f32; 3]) -> Point3<f32> {
+    Point3::new(v[0], v[1], v[2])
+}
+
+fn scale(v: [f32; 3], s: f32) -> [f32; 3] {
+    [v[0] * s, v[1] * s, v[2] * s]
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    scale(v, 1.0 / dot(v, v).sqrt())
+}
+
+/// Draw an axis-aligned box. World space is a swizzle of BSP space, so boxes stay axis-aligned
+/// when converted.
+fn draw_box(lines: &mut DebugLines, mins: [f32; 3], maxs: [f32; 3], color: Rgba) {
+    let corner = |i: usize| {
+        to_world_space([
+            if i & 1 == 0 { mins[0] } else { maxs[0] },
+            if i & 2 == 0 { mins[1] } else { maxs[1] },
+            if i & 4 == 0 { mins[2] } else { maxs[2] },
+        ])
+    };
+
+    for i in 0..8 {
+        for &bit in &[1, 2, 4] {
+            if i & bit == 0 {
+                lines.draw_line(point(corner(i)), point(corner(i | bit)), color);
+            }
+        }
+    }
+}
+
+fn draw_plane(lines: &mut DebugLines, normal: [f32; 3], dist: f32, around: [f32; 3], size: f32) {
+    let axis = if normal[2].abs() < 0.9 {
+        [0.0, 0.0, 1.0]
+    } else {
+        [1.0, 0.0, 0.0]
+    };
+    let u = scale(normalize(cross(normal, axis)), size / 2.0);
+    let v = scale(normalize(cross(normal, u)), size / 2.0);
+    let centre = add(around, scale(normal, dist - dot(normal, around)));
+
+    let corners = [
+        add(centre, add(u, v)),
+        add(centre, add(u, scale(v, -1.0))),
+        add(centre, add(scale(u, -1.0), scale(v, -1.0))),
+        add(centre, add(scale(u, -1.0), v)),
+    ];
+    for i in 0..4 {
+        lines.draw_line(
+            point(to_world_space(corners[i])),
+            point(to_world_space(corners[(i + 1) % 4])),
+            PLANE_COLOR,
+        );
+    }
+    lines.draw_line(
+        point(to_world_space(centre)),
+        point(to_world_space(add(centre, scale(normal, size / 8.0)))),
+        PLANE_COLOR,
+    );
+}
+
+/// The union of the bounds of the leaves in each cluster, in BSP space.
+fn cluster_bounds(vis: &MapVis) -> Vec<Option<([f32; 3], [f32; 3])>> {
+    let mut bounds: Vec<Option<([f32; 3], [f32; 3])>> = vec![];
+
+    for leaf in vis.leaves.iter().filter(|l| l.cluster >= 0) {
+        let cluster = leaf.cluster as usize;
+        if bounds.len() <= cluster {
+            bounds.resize(cluster + 1, None);
+        }
+
+        bounds[cluster] = bounds_of(
+            bounds[cluster]
+                .iter()
+                .flat_map(|&(mins, maxs)| vec![mins, maxs])
+                .chain(vec![leaf.mins, leaf.maxs]),
+        );
+    }
+
+    bounds
+}
+
+fn brush_color(brush: &crate::CollisionBrush) -> Rgba {
+    use crate::CollisionKind::*;
+
+    match brush.kind {
+        Solid => Rgba(0.0, 1.0, 0.0, 1.0),
+        PlayerClip => Rgba(1.0, 0.5, 0.0, 1.0),
+        MonsterClip => Rgba(0.0, 1.0, 1.0, 1.0),
+        FullClip => Rgba(1.0, 0.0, 0.0, 1.0),
+    }
+}
+
+/// Draws the parts of each map enabled in `BspDebug`, relative to the first camera.
+#[derive(Default)]
+pub struct BspDebugSystem;
+
+impl<'a> System<'a> for BspDebugSystem {
+    type SystemData = (
+        Read<'a, BspDebug>,
+        Write<'a, DebugLines>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, MapVis>,
+        ReadStorage<'a, CollisionGeometry>,
+    );
+
+    fn run(&mut self, (debug, mut lines, cameras, globals, maps, collision): Self::SystemData) {
+        let camera = (&cameras, &globals)
+            .join()
+            .next()
+            .map(|(_, global)| [global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]]);
+
+        for (vis, root) in (&maps, globals.maybe()).join() {
+            let camera = camera.map(|camera| vis::to_map_space(root, camera));
+            let current = camera.and_then(|camera| vis.leaf_at(camera));
+
+            if debug.leaf_bounds {
+                for (i, leaf) in vis.leaves.iter().enumerate() {
+                    if leaf.cluster < 0 {
+                        continue;
+                    }
+
+                    let color = if Some(i) == current {
+                        CURRENT_LEAF_COLOR
+                    } else {
+                        LEAF_COLOR
+                    };
+                    draw_box(&mut lines, leaf.mins, leaf.maxs, color);
+                }
+            }
+
+            if let (true, Some(camera)) = (debug.pvs, camera) {
+                let current = vis.cluster_at(camera);
+                let visible = vis.visible_clusters(camera);
+
+                for (cluster, bounds) in cluster_bounds(vis).into_iter().enumerate() {
+                    let (mins, maxs) = match bounds {
+                        Some(bounds) => bounds,
+                        None => continue,
+                    };
+
+                    let color = if current == Some(cluster as i32) {
+                        CURRENT_CLUSTER_COLOR
+                    } else if visible.as_ref().and_then(|v| v.get(cluster)) == Some(&false) {
+                        HIDDEN_CLUSTER_COLOR
+                    } else {
+                        VISIBLE_CLUSTER_COLOR
+                    };
+                    draw_box(&mut lines, mins, maxs, color);
+                }
+            }
+
+            if let (true, Some(camera)) = (debug.split_planes, camera) {
+                let camera = to_bsp_space(camera);
+                let mut index = 0i32;
+
+                while index >= 0 {
+                    let node = match vis.nodes.get(index as usize) {
+                        Some(node) => node,
+                        None => break,
+                    };
+                    let plane = match vis.planes.get(node.plane) {
+                        Some(plane) => plane,
+                        None => break,
+                    };
+
+                    draw_plane(
+                        &mut lines,
+                        plane.normal,
+                        plane.dist,
+                        camera,
+                        debug.plane_size,
+                    );
+
+                    index = if plane.distance(camera) >= 0.0 {
+                        node.children[0]
+                    } else {
+                        node.children[1]
+                    };
+                }
+            }
+        }
+
+        if debug.brushes {
+            for geometry in collision.join() {
+                for brush in &geometry.brushes {
+                    let color = brush_color(brush);
+                    let vertices = &brush.hull.vertices;
+
+                    for (a, b) in brush.hull.edges() {
+                        lines.draw_line(point(vertices[a]), point(vertices[b]), color);
+                    }
+                }
+            }
+        }
+    }
+}
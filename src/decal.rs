@@ -0,0 +1,193 @@
+use crate::{
+    flags::{self, SURF_NOMARKS},
+    geometry::{bounds_of, bounds_overlap, cross, dot, Plane},
+    to_world_space,
+};
+use amethyst::{
+    assets::Handle,
+    renderer::{MeshData, PosNormTex, Texture},
+};
+use bsp::Bsp;
+
+/// How far decals are pushed off the surfaces they're projected onto, to avoid z-fighting.
+const DECAL_OFFSET: f32 = 0.25;
+
+/// Faces at a steeper angle than this to the decal, as the cosine of the angle between their
+/// normals, are left unmarked like in Quake 3.
+const MIN_FACING: f32 = 0.1;
+
+/// A decal clipped to the world faces it covers, in the map's space. The texture is stretched
+/// over the whole square given to `project_decal`, so it is cut off where the surface ends.
+#[derive(Debug, Clone)]
+pub struct Decal {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub texture: Handle<Texture>,
+}
+
+impl Decal {
+    pub fn mesh(&self) -> MeshData {
+        self.positions
+            .iter()
+            .zip(&self.normals)
+            .zip(&self.tex_coords)
+            .map(|((&position, &normal), &tex_coord)| PosNormTex {
+                position: position.into(),
+                normal: normal.into(),
+                tex_coord: tex_coord.into(),
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn normalize(a: [f32; 3]) -> Option<[f32; 3]> {
+    let len = dot(a, a).sqrt();
+    if len < 1e-6 {
+        None
+    } else {
+        Some(scale(a, 1.0 / len))
+    }
+}
+
+/// Keep the part of a convex polygon in front of `plane`.
+fn clip(polygon: Vec<[f32; 3]>, plane: &Plane) -> Vec<[f32; 3]> {
+    let mut out = vec![];
+
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let (da, db) = (plane.distance(a), plane.distance(b));
+
+        if da >= 0.0 {
+            out.push(a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            out.push(add(a, scale(sub(b, a), da / (da - db))));
+        }
+    }
+
+    out
+}
+
+/// Project a square decal of `size` units onto the world faces around `origin`, facing along
+/// `normal`, for bullet holes, scorch marks and other marks that should follow the shape of the
+/// world. Both are in the map's space. Faces are marked up to `size / 2` units in front of or
+/// behind the decal, except for those with `SURF_NOMARKS`, such as sky and liquids. Returns
+/// `None` if the decal doesn't touch anything.
+pub fn project_decal(
+    bsp: &Bsp,
+    origin: [f32; 3],
+    normal: [f32; 3],
+    size: f32,
+    texture: Handle<Texture>,
+) -> Option<Decal> {
+    let normal = normalize(normal)?;
+    let reference = if normal[1].abs() < 0.9 {
+        [0.0, 1.0, 0.0]
+    } else {
+        [1.0, 0.0, 0.0]
+    };
+    let right = normalize(cross(reference, normal))?;
+    let up = cross(normal, right);
+    let half = size / 2.0;
+
+    // Facing inwards, so that `clip` keeps the inside of the decal's box.
+    let side = |axis: [f32; 3], sign: f32| Plane {
+        normal: scale(axis, -sign),
+        dist: -sign * dot(axis, origin) - half,
+    };
+    let planes = [
+        side(right, 1.0),
+        side(right, -1.0),
+        side(up, 1.0),
+        side(up, -1.0),
+        side(normal, 1.0),
+        side(normal, -1.0),
+    ];
+    let corners = [-1.0, 1.0].iter().flat_map(|&r| {
+        [-1.0, 1.0].iter().flat_map(move |&u| {
+            [-1.0, 1.0].iter().map(move |&n| {
+                add(
+                    origin,
+                    scale(
+                        add(add(scale(right, r), scale(up, u)), scale(normal, n)),
+                        half,
+                    ),
+                )
+            })
+        })
+    });
+    let bounds = bounds_of(corners)?;
+
+    let mut decal = Decal {
+        positions: vec![],
+        normals: vec![],
+        tex_coords: vec![],
+        texture,
+    };
+
+    let world = bsp.models().next()?;
+    for face in world.faces() {
+        match face.texture() {
+            Some(texture) if flags::surface_flags(texture) & SURF_NOMARKS == 0 => {}
+            _ => continue,
+        }
+
+        let vertices = face
+            .vertices()
+            .map(|v| (to_world_space(v.position), to_world_space(v.normal)))
+            .collect::<Vec<_>>();
+
+        for triangle in vertices.chunks_exact(3) {
+            let positions = [triangle[0].0, triangle[1].0, triangle[2].0];
+            if !bounds_of(positions.iter().cloned()).map_or(false, |b| bounds_overlap(b, bounds)) {
+                continue;
+            }
+
+            let facing = triangle
+                .iter()
+                .fold([0.0; 3], |sum, &(_, normal)| add(sum, normal));
+            if normalize(facing).map_or(true, |facing| dot(facing, normal) < MIN_FACING) {
+                continue;
+            }
+
+            let polygon = planes
+                .iter()
+                .fold(positions.to_vec(), |polygon, plane| clip(polygon, plane));
+            if polygon.len() < 3 {
+                continue;
+            }
+
+            let tex_coord = |p: [f32; 3]| {
+                let d = sub(p, origin);
+                [0.5 + dot(d, right) / size, 0.5 - dot(d, up) / size]
+            };
+            for pair in polygon[1..].windows(2) {
+                for &p in &[polygon[0], pair[0], pair[1]] {
+                    decal.positions.push(add(p, scale(normal, DECAL_OFFSET)));
+                    decal.normals.push(normal);
+                    decal.tex_coords.push(tex_coord(p));
+                }
+            }
+        }
+    }
+
+    if decal.positions.is_empty() {
+        None
+    } else {
+        Some(decal)
+    }
+}
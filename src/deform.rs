@@ -0,0 +1,175 @@
+use crate::{
+    scale::Scale,
+    shader::{Directive, Shader},
+    tcmod::Wave,
+    to_bsp_space, to_world_space,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// One `deformVertexes` directive that moves vertices over time. Directions are in world space.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum Deform {
+    /// Moves vertices along their normals, with the phase offset by their position so the
+    /// surface ripples. `spread` is the reciprocal of the shader's `div`.
+    Wave { spread: f32, wave: Wave },
+    /// Moves every vertex the same way.
+    Move { direction: [f32; 3], wave: Wave },
+    /// Moves vertices along their normals in a sine wave travelling along the texture's S axis.
+    Bulge { width: f32, height: f32, speed: f32 },
+}
+
+impl Deform {
+    fn parse(directive: &Directive) -> Option<Self> {
+        let arg = |i: usize| directive.arg_f32(i);
+
+        match directive.args.first()?.to_lowercase().as_str() {
+            "wave" => {
+                let div = arg(1)?;
+                Some(Deform::Wave {
+                    spread: if div == 0.0 { 0.0 } else { 1.0 / div },
+                    wave: Wave::parse(&directive.args[2..])?,
+                })
+            }
+            "move" => Some(Deform::Move {
+                direction: to_world_space([arg(1)?, arg(2)?, arg(3)?]),
+                wave: Wave::parse(&directive.args[4..])?,
+            }),
+            "bulge" => Some(Deform::Bulge {
+                width: arg(1)?,
+                height: arg(2)?,
+                speed: arg(3)?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// This deform's offset for a vertex at `time` seconds.
+    pub fn offset(
+        &self,
+        position: [f32; 3],
+        normal: [f32; 3],
+        tex_coord: [f32; 2],
+        time: f32,
+    ) -> [f32; 3] {
+        let along = |v: [f32; 3], scale: f32| [v[0] * scale, v[1] * scale, v[2] * scale];
+
+        match *self {
+            Deform::Wave { spread, wave } => {
+                // Quake 3 offsets the phase by the sum of the position's components in BSP space.
+                let [x, y, z] = to_bsp_space(position);
+                let wave = Wave {
+                    phase: wave.phase + (x + y + z) * spread,
+                    ..wave
+                };
+                along(normal, wave.value(time))
+            }
+            Deform::Move { direction, wave } => along(direction, wave.value(time)),
+            Deform::Bulge {
+                width,
+                height,
+                speed,
+            } => along(normal, (tex_coord[0] * width + time * speed).sin() * height),
+        }
+    }
+}
+
+impl Scale for Deform {
+    fn scale(&mut self, scale: f32) {
+        // Waves are distances, while the phase spreads along positions that are now scaled.
+        let scale_wave = |wave: &mut Wave| {
+            wave.base *= scale;
+            wave.amplitude *= scale;
+        };
+
+        match self {
+            Deform::Wave { spread, wave } => {
+                *spread /= scale;
+                scale_wave(wave);
+            }
+            Deform::Move { wave, .. } => scale_wave(wave),
+            Deform::Bulge { height, .. } => *height *= scale,
+        }
+    }
+}
+
+/// The `deformVertexes` of a face group's shader that animate its vertices. These need a
+/// vertex shader, or a system rewriting the mesh, to have any effect. `autosprite` deforms are
+/// handled by `Billboard` instead, and the rest are not supported.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct VertexDeform {
+    pub deforms: Vec<Deform>,
+}
+
+impl Component for VertexDeform {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Scale for VertexDeform {
+    fn scale(&mut self, scale: f32) {
+        self.deforms.scale(scale);
+    }
+}
+
+impl VertexDeform {
+    pub(crate) fn from_shader(shader: &Shader) -> Option<Self> {
+        let deforms = shader
+            .directive("deformvertexes")
+            .filter_map(Deform::parse)
+            .collect::<Vec<_>>();
+
+        if deforms.is_empty() {
+            None
+        } else {
+            Some(VertexDeform { deforms })
+        }
+    }
+
+    /// Where a vertex should be drawn at `time` seconds, with every deform applied in order.
+    pub fn deform(
+        &self,
+        position: [f32; 3],
+        normal: [f32; 3],
+        tex_coord: [f32; 2],
+        time: f32,
+    ) -> [f32; 3] {
+        self.deforms.iter().fold(position, |p, deform| {
+            let offset = deform.offset(p, normal, tex_coord, time);
+            [p[0] + offset[0], p[1] + offset[1], p[2] + offset[2]]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderLibrary;
+
+    #[test]
+    fn parses_deforms() {
+        let library = ShaderLibrary::new().with_script(
+            r#"
+textures/base/flag
+{
+    deformVertexes wave 100 sin 0 3 0 0.5
+    deformVertexes move 0 0 1 square 2 0 0 1
+    deformVertexes autosprite
+}
+"#,
+        );
+        let deform = VertexDeform::from_shader(library.get("textures/base/flag").unwrap()).unwrap();
+
+        assert_eq!(deform.deforms.len(), 2);
+        // The wave is at zero, and the move is up by its base.
+        assert_eq!(
+            deform.deform([0.0; 3], [1.0, 0.0, 0.0], [0.0; 2], 0.0),
+            [0.0, 2.0, 0.0]
+        );
+    }
+}
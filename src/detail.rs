@@ -0,0 +1,101 @@
+use crate::{
+    flags::{self, CONTENTS_DETAIL},
+    mesh::FaceGroup,
+    shader::Shader,
+    vis,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::GlobalTransform,
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entities, Entity, Join, ReadStorage, System, WriteStorage},
+    renderer::{Camera, Hidden},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// A face group of detail surfaces, such as trims, bolts and grates, which can be hidden from
+/// far away by `DetailCullingSystem`. The bounds are in the space of the entity's
+/// `GlobalTransform`, or of the map if it has none, like the face group's vertices.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct DetailGeometry {
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+impl Component for DetailGeometry {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl DetailGeometry {
+    /// Faces are detail if their shader has `surfaceparm detail`, or if they were compiled from
+    /// detail brushes.
+    pub(crate) fn new(
+        group: &FaceGroup,
+        texture: &bsp::Texture,
+        shader: Option<&Shader>,
+    ) -> Option<Self> {
+        let detail = flags::contents(texture) & CONTENTS_DETAIL != 0
+            || shader.map_or(false, |shader| shader.has_surfaceparm("detail"));
+        if !detail {
+            return None;
+        }
+
+        let (mins, maxs) = group.bounds()?;
+        Some(DetailGeometry { mins, maxs })
+    }
+
+    /// The distance from a point to the nearest point of the bounds.
+    pub fn distance(&self, point: [f32; 3]) -> f32 {
+        let mut sum = 0.0;
+        for i in 0..3 {
+            let d = (self.mins[i] - point[i])
+                .max(point[i] - self.maxs[i])
+                .max(0.0);
+            sum += d * d;
+        }
+        sum.sqrt()
+    }
+}
+
+/// Hides `DetailGeometry` further than `distance` from the first camera, and shows it again once
+/// the camera is close enough. This uses `Hidden` rather than `HiddenPropagate`, so that it
+/// doesn't fight `VisibilitySystem` over clusters.
+pub struct DetailCullingSystem {
+    pub distance: f32,
+}
+
+impl DetailCullingSystem {
+    pub fn new(distance: f32) -> Self {
+        DetailCullingSystem { distance }
+    }
+}
+
+impl<'a> System<'a> for DetailCullingSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, DetailGeometry>,
+        WriteStorage<'a, Hidden>,
+    );
+
+    fn run(&mut self, (entities, cameras, globals, details, mut hidden): Self::SystemData) {
+        let camera = match (&cameras, &globals).join().next() {
+            Some((_, global)) => [global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]],
+            None => return,
+        };
+
+        for (entity, detail, global) in (&entities, &details, globals.maybe()).join() {
+            let camera = vis::to_map_space(global, camera);
+
+            if detail.distance(camera) <= self.distance {
+                hidden.remove(entity);
+            } else if !hidden.contains(entity) {
+                // Inserting can only fail for dead entities, which `join` never yields.
+                let _ = hidden.insert(entity, Hidden);
+            }
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use amethyst::Error;
+use bsp::Bsp;
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+/// A BSP format, as told apart by the magic and version at the start of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BspDialect {
+    /// `IBSP` version 46.
+    Quake3,
+    /// `IBSP` version 47. Return to Castle Wolfenstein and Enemy Territory use the same header,
+    /// so files with it are detected as this unless `ImportOptions::dialect` says otherwise.
+    QuakeLive,
+    /// `IBSP` version 47, as compiled for Return to Castle Wolfenstein or Enemy Territory.
+    Wolfenstein,
+    /// `RBSP` version 1, used by Jedi Knight II, Jedi Academy and Soldier of Fortune II.
+    Raven,
+    /// `IBSP` version 38.
+    Quake2,
+    /// Version 29, which has no magic.
+    Quake1,
+    /// Version 30, which has no magic.
+    HalfLife,
+    /// `VBSP`, any version.
+    Source,
+}
+
+impl BspDialect {
+    /// Identify a map from at least its first 8 bytes.
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.len() < 8 {
+            return None;
+        }
+
+        let magic = &header[0..4];
+        let version = i32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+        let dialect = match (magic, version) {
+            (b"IBSP", 46) => BspDialect::Quake3,
+            (b"IBSP", 47) => BspDialect::QuakeLive,
+            (b"IBSP", 38) => BspDialect::Quake2,
+            (b"RBSP", 1) => BspDialect::Raven,
+            (b"VBSP", _) => BspDialect::Source,
+            _ => match i32::from_le_bytes([magic[0], magic[1], magic[2], magic[3]]) {
+                29 => BspDialect::Quake1,
+                30 => BspDialect::HalfLife,
+                _ => return None,
+            },
+        };
+
+        Some(dialect)
+    }
+
+    /// Whether maps of this dialect can be imported. Quake Live and the Wolfenstein games only
+    /// changed the version number of the Quake 3 format.
+    pub fn is_supported(self) -> bool {
+        match self {
+            BspDialect::Quake3 | BspDialect::QuakeLive | BspDialect::Wolfenstein => true,
+            _ => false,
+        }
+    }
+}
+
+/// Why a map couldn't be read as any supported dialect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DialectError {
+    Unknown { magic: [u8; 4] },
+    Unsupported(BspDialect),
+}
+
+impl fmt::Display for DialectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DialectError::Unknown { magic } => {
+                write!(f, "unknown BSP format {:?}", String::from_utf8_lossy(magic))
+            }
+            DialectError::Unsupported(dialect) => write!(f, "unsupported BSP format {:?}", dialect),
+        }
+    }
+}
+
+impl std::error::Error for DialectError {}
+
+const QUAKE3_VERSION: [u8; 4] = [46, 0, 0, 0];
+
+/// A reader that shows the Quake 3 version in place of the real one, for dialects that only
+/// differ from Quake 3 in their version.
+struct AsQuake3<R> {
+    inner: R,
+    start: u64,
+}
+
+impl<R: Read + Seek> Read for AsQuake3<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let at = self.inner.seek(SeekFrom::Current(0))? - self.start;
+        let read = self.inner.read(buf)?;
+
+        for (i, byte) in buf[..read].iter_mut().enumerate() {
+            let offset = at + i as u64;
+            if (4..8).contains(&offset) {
+                *byte = QUAKE3_VERSION[offset as usize - 4];
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for AsQuake3<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => SeekFrom::Start(self.start + pos),
+            pos => pos,
+        };
+        Ok(self.inner.seek(pos)? - self.start)
+    }
+}
+
+/// Read a map, detecting its dialect unless `dialect` is given.
+pub(crate) fn read_bsp<R: Read + Seek>(
+    mut reader: R,
+    dialect: Option<BspDialect>,
+) -> Result<Bsp, Error> {
+    let mut header = [0; 8];
+    let start = (|| {
+        let start = reader.seek(SeekFrom::Current(0))?;
+        reader.read_exact(&mut header)?;
+        reader.seek(SeekFrom::Start(start))
+    })()
+    .map_err(|e: io::Error| Error::new(e))?;
+
+    let dialect = match dialect.or_else(|| BspDialect::detect(&header)) {
+        Some(dialect) => dialect,
+        None => {
+            let mut magic = [0; 4];
+            magic.copy_from_slice(&header[0..4]);
+            return Err(Error::new(DialectError::Unknown { magic }));
+        }
+    };
+
+    match dialect {
+        BspDialect::Quake3 => Bsp::read(reader),
+        BspDialect::QuakeLive | BspDialect::Wolfenstein => Bsp::read(AsQuake3 {
+            inner: reader,
+            start,
+        }),
+        other => return Err(Error::new(DialectError::Unsupported(other))),
+    }
+    .map_err(|e| Error::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_dialects() {
+        assert_eq!(
+            BspDialect::detect(b"IBSP\x2e\0\0\0"),
+            Some(BspDialect::Quake3)
+        );
+        assert_eq!(
+            BspDialect::detect(b"IBSP\x2f\0\0\0"),
+            Some(BspDialect::QuakeLive)
+        );
+        assert_eq!(
+            BspDialect::detect(b"\x1d\0\0\0\0\0\0\0"),
+            Some(BspDialect::Quake1)
+        );
+        assert_eq!(BspDialect::detect(b"PK\x03\x04\0\0\0\0"), None);
+    }
+}
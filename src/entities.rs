@@ -0,0 +1,244 @@
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+
+/// A single `{ "key" "value" ... }` block from the entity lump.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct MapEntity {
+    pub keyvalues: Vec<(String, String)>,
+}
+
+impl MapEntity {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.keyvalues
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn classname(&self) -> Option<&str> {
+        self.get("classname")
+    }
+
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.get(key)?.trim().parse().ok()
+    }
+
+    /// A value of the form `"x y z"`, such as `origin`. This is not converted to world space.
+    pub fn get_vec3(&self, key: &str) -> Option<[f32; 3]> {
+        let mut parts = self.get(key)?.split_whitespace().map(|p| p.parse::<f32>());
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(x)), Some(Ok(y)), Some(Ok(z))) => Some([x, y, z]),
+            _ => None,
+        }
+    }
+
+    /// An integer, which can also be written as a float like Quake's `atoi` allows.
+    pub fn get_i32(&self, key: &str) -> Option<i32> {
+        let value = self.get(key)?.trim();
+        value
+            .parse()
+            .ok()
+            .or_else(|| value.parse::<f32>().ok().map(|f| f as i32))
+    }
+
+    /// A flag written as a number, where anything but `0` is true.
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get_i32(key).map(|i| i != 0)
+    }
+
+    /// A colour such as `_color`, from `0.0` to `1.0`. Colours written from `0` to `255` by some
+    /// editors are scaled down.
+    pub fn get_color(&self, key: &str) -> Option<[f32; 3]> {
+        let color = self.get_vec3(key)?;
+
+        if color.iter().any(|&c| c > 1.0) {
+            Some([color[0] / 255.0, color[1] / 255.0, color[2] / 255.0])
+        } else {
+            Some(color)
+        }
+    }
+
+    /// The pitch, yaw and roll in degrees from `angles`, or from `angle` as the yaw, where `-1`
+    /// and `-2` mean straight up and straight down.
+    pub fn get_angles(&self) -> Option<[f32; 3]> {
+        crate::transform::entity_angles(self)
+    }
+
+    pub fn spawnflags(&self) -> u32 {
+        self.get_f32("spawnflags").map_or(0, |f| f as u32)
+    }
+
+    /// The index of the brush model used by this entity, from a `model` key of the form `*N`.
+    pub fn model_index(&self) -> Option<usize> {
+        let model = self.get("model")?;
+
+        if model.starts_with('*') {
+            model[1..].parse().ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// The key/value pairs of the map entity an entity was imported from, with the same typed
+/// getters as `MapEntity`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct EntityKeyValues(pub MapEntity);
+
+impl Component for EntityKeyValues {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Deref for EntityKeyValues {
+    type Target = MapEntity;
+
+    fn deref(&self) -> &MapEntity {
+        &self.0
+    }
+}
+
+pub(crate) fn entity_string(bsp: &Bsp) -> &str {
+    &bsp.entities.entities
+}
+
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            self.rest = self.rest.trim_start();
+
+            if self.rest.starts_with("//") {
+                let end = self.rest.find('\n').unwrap_or(self.rest.len());
+                self.rest = &self.rest[end..];
+            } else {
+                break;
+            }
+        }
+
+        let mut chars = self.rest.char_indices();
+
+        match chars.next()? {
+            (_, '"') => {
+                let body = &self.rest[1..];
+                let end = body.find('"').unwrap_or(body.len());
+                let out = &body[..end];
+                self.rest = body.get(end + 1..).unwrap_or("");
+                Some(out)
+            }
+            (_, c @ '{') | (_, c @ '}') => {
+                let out = &self.rest[..c.len_utf8()];
+                self.rest = &self.rest[c.len_utf8()..];
+                Some(out)
+            }
+            _ => {
+                let end = self
+                    .rest
+                    .find(|c: char| c.is_whitespace() || c == '{' || c == '}' || c == '"')
+                    .unwrap_or(self.rest.len());
+                let out = &self.rest[..end];
+                self.rest = &self.rest[end..];
+                Some(out)
+            }
+        }
+    }
+}
+
+/// Parse the entity lump. Malformed trailing data is ignored rather than treated as an error,
+/// since the engines these maps were built for are similarly lenient.
+pub fn parse_entities(source: &str) -> Vec<MapEntity> {
+    let mut tokens = Tokens { rest: source };
+    let mut out = vec![];
+
+    while let Some(token) = tokens.next() {
+        if token != "{" {
+            continue;
+        }
+
+        let mut entity = MapEntity::default();
+
+        loop {
+            let key = match tokens.next() {
+                Some("}") | None => break,
+                Some(key) => key,
+            };
+            let value = match tokens.next() {
+                Some(value) => value,
+                None => break,
+            };
+
+            entity.keyvalues.push((key.to_string(), value.to_string()));
+        }
+
+        out.push(entity);
+    }
+
+    out
+}
+
+pub(crate) fn worldspawn(entities: &[MapEntity]) -> Option<&MapEntity> {
+    entities
+        .iter()
+        .find(|e| e.classname() == Some("worldspawn"))
+}
+
+/// The first `info_player_start`, or the first `info_player_deathmatch` in maps without one.
+pub(crate) fn player_spawn(entities: &[MapEntity]) -> Option<&MapEntity> {
+    let find = |classname| entities.iter().find(|e| e.classname() == Some(classname));
+    find("info_player_start").or_else(|| find("info_player_deathmatch"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entity_lump() {
+        let entities = parse_entities(
+            r#"{
+"classname" "worldspawn"
+"message" "The Longest Yard"
+}
+// comment
+{
+"classname" "info_player_deathmatch"
+"origin" "-64 128 24"
+}"#,
+        );
+
+        assert_eq!(entities.len(), 2);
+        assert_eq!(
+            worldspawn(&entities).and_then(|w| w.get("message")),
+            Some("The Longest Yard")
+        );
+        assert_eq!(entities[1].get_vec3("origin"), Some([-64.0, 128.0, 24.0]));
+    }
+
+    #[test]
+    fn reads_quirky_values() {
+        let entity = MapEntity {
+            keyvalues: vec![
+                ("_color".to_string(), "255 128 0".to_string()),
+                ("wait".to_string(), "2.5".to_string()),
+                ("angle".to_string(), "-1".to_string()),
+            ],
+        };
+
+        assert_eq!(entity.get_color("_color"), Some([1.0, 128.0 / 255.0, 0.0]));
+        assert_eq!(entity.get_i32("wait"), Some(2));
+        assert_eq!(entity.get_angles(), Some([-90.0, 0.0, 0.0]));
+    }
+}
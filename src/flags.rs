@@ -0,0 +1,52 @@
+//! Quake 3 content and surface flags, as found on BSP textures.
+
+pub const CONTENTS_SOLID: u32 = 0x1;
+pub const CONTENTS_LAVA: u32 = 0x8;
+pub const CONTENTS_SLIME: u32 = 0x10;
+pub const CONTENTS_WATER: u32 = 0x20;
+pub const CONTENTS_FOG: u32 = 0x40;
+pub const CONTENTS_AREAPORTAL: u32 = 0x8000;
+pub const CONTENTS_PLAYERCLIP: u32 = 0x10000;
+pub const CONTENTS_MONSTERCLIP: u32 = 0x20000;
+pub const CONTENTS_TELEPORTER: u32 = 0x40000;
+pub const CONTENTS_JUMPPAD: u32 = 0x80000;
+pub const CONTENTS_CLUSTERPORTAL: u32 = 0x100000;
+pub const CONTENTS_DONOTENTER: u32 = 0x200000;
+pub const CONTENTS_BOTCLIP: u32 = 0x400000;
+pub const CONTENTS_MOVER: u32 = 0x800000;
+pub const CONTENTS_ORIGIN: u32 = 0x1000000;
+pub const CONTENTS_BODY: u32 = 0x2000000;
+pub const CONTENTS_CORPSE: u32 = 0x4000000;
+pub const CONTENTS_DETAIL: u32 = 0x8000000;
+pub const CONTENTS_STRUCTURAL: u32 = 0x10000000;
+pub const CONTENTS_TRANSLUCENT: u32 = 0x20000000;
+pub const CONTENTS_TRIGGER: u32 = 0x40000000;
+pub const CONTENTS_NODROP: u32 = 0x80000000;
+
+pub const SURF_NODAMAGE: u32 = 0x1;
+pub const SURF_SLICK: u32 = 0x2;
+pub const SURF_SKY: u32 = 0x4;
+pub const SURF_LADDER: u32 = 0x8;
+pub const SURF_NOIMPACT: u32 = 0x10;
+pub const SURF_NOMARKS: u32 = 0x20;
+pub const SURF_FLESH: u32 = 0x40;
+pub const SURF_NODRAW: u32 = 0x80;
+pub const SURF_HINT: u32 = 0x100;
+pub const SURF_SKIP: u32 = 0x200;
+pub const SURF_NOLIGHTMAP: u32 = 0x400;
+pub const SURF_POINTLIGHT: u32 = 0x800;
+pub const SURF_METALSTEPS: u32 = 0x1000;
+pub const SURF_NOSTEPS: u32 = 0x2000;
+pub const SURF_NONSOLID: u32 = 0x4000;
+pub const SURF_LIGHTFILTER: u32 = 0x8000;
+pub const SURF_ALPHASHADOW: u32 = 0x10000;
+pub const SURF_NODLIGHT: u32 = 0x20000;
+pub const SURF_DUST: u32 = 0x40000;
+
+pub(crate) fn contents(texture: &bsp::Texture) -> u32 {
+    texture.contents.bits() as u32
+}
+
+pub(crate) fn surface_flags(texture: &bsp::Texture) -> u32 {
+    texture.flags.bits() as u32
+}
@@ -0,0 +1,170 @@
+use crate::{
+    entities::MapEntity,
+    flags::{self, SURF_SKY},
+    options::ImportOptions,
+    shader::{Directive, Shader},
+    Extension,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    ecs::{Entity, Write},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Distance fog from a shader's `fogparms ( r g b ) depth`. Opaque geometry is fully fogged at
+/// `depth_for_opaque` units from the viewer.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct FogParms {
+    pub color: [f32; 3],
+    pub depth_for_opaque: f32,
+}
+
+impl FogParms {
+    /// Parse the arguments of a `fogparms` directive, which may or may not have spaces around
+    /// the brackets.
+    pub fn from_directive(directive: &Directive) -> Option<Self> {
+        let mut numbers = directive
+            .args
+            .iter()
+            .flat_map(|arg| arg.split(|c| c == '(' || c == ')'))
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.parse::<f32>());
+
+        match (
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+        ) {
+            (Some(Ok(r)), Some(Ok(g)), Some(Ok(b)), Some(Ok(depth_for_opaque))) => Some(FogParms {
+                color: [r, g, b],
+                depth_for_opaque,
+            }),
+            _ => None,
+        }
+    }
+
+    fn from_shader(shader: &Shader) -> Option<Self> {
+        shader
+            .directive("fogparms")
+            .filter_map(FogParms::from_directive)
+            .next()
+    }
+}
+
+/// The global fog of each map that has one, keyed by the root entity of the map.
+#[derive(Default)]
+pub struct MapFog {
+    pub maps: HashMap<Entity, FogParms>,
+}
+
+impl MapFog {
+    pub fn get(&self, map: Entity) -> Option<&FogParms> {
+        self.maps.get(&map)
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a FogParms> + 'a {
+        self.maps.values()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MapFogPrefab {
+    pub fog: FogParms,
+}
+
+impl MapFogPrefab {
+    /// The global fog of a map, with its depth scaled by `ImportOptions::scale`. Worldspawn's
+    /// `_fog` key takes priority, and can either name a shader or give the colour and depth
+    /// directly as `"r g b depth"`. Otherwise the first sky shader with `fogparms` is used.
+    pub(crate) fn new<E: Extension>(
+        bsp: &Bsp,
+        worldspawn: Option<&MapEntity>,
+        options: &ImportOptions<E>,
+    ) -> Option<Self> {
+        let explicit = worldspawn.and_then(|w| w.get("_fog")).and_then(|value| {
+            let numbers = value
+                .split_whitespace()
+                .map(|n| n.parse::<f32>().ok())
+                .collect::<Option<Vec<_>>>();
+
+            match numbers.as_ref().map(Vec::as_slice) {
+                Some(&[r, g, b, depth_for_opaque]) => Some(FogParms {
+                    color: [r, g, b],
+                    depth_for_opaque,
+                }),
+                _ => FogParms::from_shader(options.shader(value.trim())?),
+            }
+        });
+
+        let fog = explicit.or_else(|| {
+            (0..)
+                .map(|i| bsp.texture(i))
+                .take_while(Option::is_some)
+                .flatten()
+                .filter(|texture| flags::surface_flags(texture) & SURF_SKY != 0)
+                .filter_map(|texture| options.shader(&texture.name))
+                .filter_map(FogParms::from_shader)
+                .next()
+        })?;
+
+        Some(MapFogPrefab {
+            fog: FogParms {
+                depth_for_opaque: fog.depth_for_opaque * options.scale,
+                ..fog
+            },
+        })
+    }
+}
+
+impl<'a> PrefabData<'a> for MapFogPrefab {
+    type SystemData = Write<'a, MapFog>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        fog: &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        fog.maps.insert(entity, self.fog);
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        _: &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderLibrary;
+
+    #[test]
+    fn parses_fogparms() {
+        let library = ShaderLibrary::new().with_script(
+            "textures/fog/a { fogparms ( 0.5 0.25 1 ) 512 }\ntextures/fog/b { fogparms (1 1 1) 64 }",
+        );
+
+        assert_eq!(
+            FogParms::from_shader(library.get("textures/fog/a").unwrap()),
+            Some(FogParms {
+                color: [0.5, 0.25, 1.0],
+                depth_for_opaque: 512.0,
+            })
+        );
+        assert_eq!(
+            FogParms::from_shader(library.get("textures/fog/b").unwrap())
+                .map(|fog| fog.depth_for_opaque),
+            Some(64.0)
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use crate::entities::MapEntity;
+
+// Quake 1 and 2 `spawnflags` for entities left out on some skill levels or in deathmatch.
+const NOT_EASY: u32 = 256;
+const NOT_MEDIUM: u32 = 512;
+const NOT_HARD: u32 = 1024;
+const NOT_DEATHMATCH: u32 = 2048;
+
+/// The Quake 3 game types, with the names used by Team Arena's `gametype` key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GameType {
+    FreeForAll,
+    Tournament,
+    SinglePlayer,
+    Team,
+    CaptureTheFlag,
+    OneFlag,
+    Obelisk,
+    Harvester,
+}
+
+impl GameType {
+    pub fn name(self) -> &'static str {
+        match self {
+            GameType::FreeForAll => "ffa",
+            GameType::Tournament => "tournament",
+            GameType::SinglePlayer => "single",
+            GameType::Team => "team",
+            GameType::CaptureTheFlag => "ctf",
+            GameType::OneFlag => "oneflag",
+            GameType::Obelisk => "obelisk",
+            GameType::Harvester => "harvester",
+        }
+    }
+
+    pub fn is_team_game(self) -> bool {
+        match self {
+            GameType::FreeForAll | GameType::Tournament | GameType::SinglePlayer => false,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Skill {
+    Easy,
+    Medium,
+    Hard,
+}
+
+/// The game a map is being loaded for, to leave out entities that wouldn't spawn in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameMode {
+    pub game_type: GameType,
+    /// Check the skill and deathmatch bits of `spawnflags`, as Quake 1 and 2 maps use them.
+    /// Quake 3 maps use those bits for other things, so this should be `None` for them.
+    pub skill: Option<Skill>,
+}
+
+impl GameMode {
+    pub fn new(game_type: GameType) -> Self {
+        GameMode {
+            game_type,
+            skill: None,
+        }
+    }
+
+    /// Whether the game would spawn `entity`, going by the `notsingle`, `notteam` and `notfree`
+    /// keys, Team Arena's `gametype` list and, if `skill` is set, the skill and deathmatch
+    /// spawn flags.
+    pub fn spawns(&self, entity: &MapEntity) -> bool {
+        let flag = |key| entity.get_bool(key).unwrap_or(false);
+
+        let excluded = match self.game_type {
+            GameType::SinglePlayer => flag("notsingle"),
+            game_type if game_type.is_team_game() => flag("notteam"),
+            _ => flag("notfree"),
+        };
+        if excluded {
+            return false;
+        }
+
+        if let Some(game_types) = entity.get("gametype") {
+            if !game_types
+                .split_whitespace()
+                .any(|name| name.eq_ignore_ascii_case(self.game_type.name()))
+            {
+                return false;
+            }
+        }
+
+        if let Some(skill) = self.skill {
+            let flags = entity.spawnflags();
+            let skill_flag = match skill {
+                Skill::Easy => NOT_EASY,
+                Skill::Medium => NOT_MEDIUM,
+                Skill::Hard => NOT_HARD,
+            };
+            let deathmatch = self.game_type != GameType::SinglePlayer;
+
+            if flags & skill_flag != 0 || (deathmatch && flags & NOT_DEATHMATCH != 0) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(keyvalues: &[(&str, &str)]) -> MapEntity {
+        MapEntity {
+            keyvalues: keyvalues
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn filters_by_game_type() {
+        let ffa = GameMode::new(GameType::FreeForAll);
+        let ctf = GameMode::new(GameType::CaptureTheFlag);
+
+        let not_team = entity(&[("classname", "item_quad"), ("notteam", "1")]);
+        assert!(ffa.spawns(&not_team));
+        assert!(!ctf.spawns(&not_team));
+
+        let flag = entity(&[
+            ("classname", "team_CTF_redflag"),
+            ("gametype", "ctf oneflag"),
+        ]);
+        assert!(!ffa.spawns(&flag));
+        assert!(ctf.spawns(&flag));
+    }
+
+    #[test]
+    fn only_checks_skill_flags_when_asked() {
+        let hard_only = entity(&[("classname", "monster_army"), ("spawnflags", "768")]);
+
+        assert!(GameMode::new(GameType::SinglePlayer).spawns(&hard_only));
+        assert!(!GameMode {
+            game_type: GameType::SinglePlayer,
+            skill: Some(Skill::Easy),
+        }
+        .spawns(&hard_only));
+        assert!(GameMode {
+            game_type: GameType::SinglePlayer,
+            skill: Some(Skill::Hard),
+        }
+        .spawns(&hard_only));
+    }
+}
@@ -0,0 +1,237 @@
+use serde::{Deserialize, Serialize};
+
+const EPSILON: f32 = 0.01;
+
+pub(crate) fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub(crate) fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub(crate) fn bounds_overlap(a: ([f32; 3], [f32; 3]), b: ([f32; 3], [f32; 3])) -> bool {
+    (0..3).all(|i| a.0[i] <= b.1[i] && b.0[i] <= a.1[i])
+}
+
+pub(crate) fn bounds_of<I: IntoIterator<Item = [f32; 3]>>(
+    points: I,
+) -> Option<([f32; 3], [f32; 3])> {
+    let mut points = points.into_iter();
+    let first = points.next()?;
+
+    Some(points.fold((first, first), |(mut mins, mut maxs), p| {
+        for i in 0..3 {
+            mins[i] = mins[i].min(p[i]);
+            maxs[i] = maxs[i].max(p[i]);
+        }
+        (mins, maxs)
+    }))
+}
+
+/// The same as `bounds_of`, for points in a slice. This keeps a running minimum and maximum for
+/// each of several points at a time, and compares without `f32::min`'s NaN handling, so that the
+/// compiler can vectorise it for the hundreds of thousands of vertices of a large map.
+pub(crate) fn slice_bounds(points: &[[f32; 3]]) -> Option<([f32; 3], [f32; 3])> {
+    const LANES: usize = 4;
+
+    let first = *points.first()?;
+    let mut mins = [first; LANES];
+    let mut maxs = [first; LANES];
+
+    let chunks = points.chunks_exact(LANES);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        for lane in 0..LANES {
+            for axis in 0..3 {
+                let v = chunk[lane][axis];
+                mins[lane][axis] = if v < mins[lane][axis] {
+                    v
+                } else {
+                    mins[lane][axis]
+                };
+                maxs[lane][axis] = if v > maxs[lane][axis] {
+                    v
+                } else {
+                    maxs[lane][axis]
+                };
+            }
+        }
+    }
+
+    bounds_of(mins.iter().chain(&maxs).chain(rest).cloned())
+}
+
+/// A plane where points `p` with `dot(normal, p) == dist` lie on the plane.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Plane {
+    pub normal: [f32; 3],
+    pub dist: f32,
+}
+
+impl Plane {
+    pub fn distance(&self, point: [f32; 3]) -> f32 {
+        dot(self.normal, point) - self.dist
+    }
+}
+
+/// A convex volume bounded by outward-facing planes, like a brush.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ConvexHull {
+    pub planes: Vec<Plane>,
+    pub vertices: Vec<[f32; 3]>,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+fn intersect(a: &Plane, b: &Plane, c: &Plane) -> Option<[f32; 3]> {
+    let bc = cross(b.normal, c.normal);
+    let denom = dot(a.normal, bc);
+
+    if denom.abs() < 1e-6 {
+        return None;
+    }
+
+    let ca = cross(c.normal, a.normal);
+    let ab = cross(a.normal, b.normal);
+
+    let mut out = [0.0; 3];
+    for i in 0..3 {
+        out[i] = (a.dist * bc[i] + b.dist * ca[i] + c.dist * ab[i]) / denom;
+    }
+
+    Some(out)
+}
+
+impl ConvexHull {
+    /// Build the hull by intersecting every triple of planes and keeping the points that are
+    /// inside all of them. Returns `None` if the planes don't enclose a volume.
+    pub fn from_planes(planes: Vec<Plane>) -> Option<Self> {
+        let mut vertices: Vec<[f32; 3]> = vec![];
+
+        for (i, a) in planes.iter().enumerate() {
+            for (j, b) in planes.iter().enumerate().skip(i + 1) {
+                for c in planes.iter().skip(j + 1) {
+                    let point = match intersect(a, b, c) {
+                        Some(point) => point,
+                        None => continue,
+                    };
+
+                    if planes.iter().any(|p| p.distance(point) > EPSILON) {
+                        continue;
+                    }
+
+                    let duplicate = vertices
+                        .iter()
+                        .any(|v| (0..3).all(|i| (v[i] - point[i]).abs() < EPSILON));
+                    if !duplicate {
+                        vertices.push(point);
+                    }
+                }
+            }
+        }
+
+        if vertices.len() < 4 {
+            return None;
+        }
+
+        let (mins, maxs) = bounds_of(vertices.iter().cloned())?;
+
+        Some(ConvexHull {
+            planes,
+            vertices,
+            mins,
+            maxs,
+        })
+    }
+
+    pub fn size(&self) -> [f32; 3] {
+        [
+            self.maxs[0] - self.mins[0],
+            self.maxs[1] - self.mins[1],
+            self.maxs[2] - self.mins[2],
+        ]
+    }
+
+    pub fn contains(&self, point: [f32; 3]) -> bool {
+        self.planes.iter().all(|p| p.distance(point) <= 0.0)
+    }
+
+    /// The outline of the hull, as pairs of indices into `vertices`. Two vertices are joined by an
+    /// edge if they both lie on at least two of the same planes.
+    pub fn edges(&self) -> Vec<(usize, usize)> {
+        let on_plane = |v: usize, p: &Plane| p.distance(self.vertices[v]).abs() < EPSILON;
+
+        let mut edges = vec![];
+        for a in 0..self.vertices.len() {
+            for b in a + 1..self.vertices.len() {
+                let shared = self
+                    .planes
+                    .iter()
+                    .filter(|p| on_plane(a, p) && on_plane(b, p))
+                    .count();
+
+                if shared >= 2 {
+                    edges.push((a, b));
+                }
+            }
+        }
+
+        edges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube(size: f32) -> Vec<Plane> {
+        let mut planes = vec![];
+        for axis in 0..3 {
+            for &sign in &[1.0, -1.0] {
+                let mut normal = [0.0; 3];
+                normal[axis] = sign;
+                planes.push(Plane { normal, dist: size });
+            }
+        }
+        planes
+    }
+
+    #[test]
+    fn hull_from_cube() {
+        let hull = ConvexHull::from_planes(cube(16.0)).unwrap();
+
+        assert_eq!(hull.vertices.len(), 8);
+        assert_eq!(hull.mins, [-16.0; 3]);
+        assert_eq!(hull.size(), [32.0; 3]);
+        assert!(hull.contains([0.0, 15.0, -15.0]));
+        assert!(!hull.contains([0.0, 17.0, 0.0]));
+        assert_eq!(hull.edges().len(), 12);
+    }
+
+    #[test]
+    fn open_planes_have_no_hull() {
+        let mut planes = cube(16.0);
+        planes.truncate(5);
+
+        assert!(ConvexHull::from_planes(planes).is_none());
+    }
+
+    #[test]
+    fn slice_bounds_match_bounds_of() {
+        // Not a multiple of the lane count, with the extremes in the remainder and the lanes.
+        let points = (0..11)
+            .map(|i| {
+                let i = i as f32;
+                [i * 3.0 % 7.0, -i, (i - 5.0) * (i - 5.0)]
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(slice_bounds(&points), bounds_of(points.iter().cloned()));
+        assert_eq!(slice_bounds(&[]), None);
+    }
+}
@@ -0,0 +1,325 @@
+//! Export of the converted geometry to glTF 2.0, for inspecting what the importer produces in
+//! other tools. Meshes are grouped the same way as the prefab, and entities become nodes placed
+//! with their `origin` and `angles`. Positions and origins are scaled by `ImportOptions::scale`,
+//! like the prefab's.
+
+use crate::{
+    bspx::BspExtensions,
+    entities::{self, MapEntity},
+    mesh::face_groups,
+    scale::Scale,
+    to_world_space, transform, Extension, FaceGroup, ImportOptions,
+};
+use bsp::Bsp;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// The container to write an exported map in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GltfContainer {
+    /// A `.gltf` JSON file, with the geometry embedded as a base64 data URI.
+    Gltf,
+    /// A binary `.glb` file.
+    Glb,
+}
+
+const FLOAT: u32 = 5126;
+const ARRAY_BUFFER: u32 = 34962;
+
+#[derive(Default)]
+struct Builder {
+    buffer: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+    materials: Vec<Value>,
+    textures: Vec<Value>,
+    images: Vec<Value>,
+    material_ids: HashMap<String, usize>,
+}
+
+impl Builder {
+    fn accessor<T: AsRef<[f32]>>(&mut self, values: &[T], kind: &str, bounds: bool) -> usize {
+        let offset = self.buffer.len();
+        let components = values.first().map_or(0, |v| v.as_ref().len());
+
+        let mut mins = vec![std::f32::INFINITY; components];
+        let mut maxs = vec![std::f32::NEG_INFINITY; components];
+
+        for value in values {
+            for (i, &c) in value.as_ref().iter().enumerate() {
+                self.buffer.extend_from_slice(&c.to_le_bytes());
+                mins[i] = mins[i].min(c);
+                maxs[i] = maxs[i].max(c);
+            }
+        }
+
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.buffer.len() - offset,
+            "target": ARRAY_BUFFER,
+        }));
+
+        let mut accessor = json!({
+            "bufferView": self.views.len() - 1,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": kind,
+        });
+        // glTF requires bounds on positions, and they are not useful on anything else.
+        if bounds {
+            accessor["min"] = json!(mins);
+            accessor["max"] = json!(maxs);
+        }
+        self.accessors.push(accessor);
+
+        self.accessors.len() - 1
+    }
+
+    fn material<E: Extension>(&mut self, options: &ImportOptions<E>, texture_name: &str) -> usize {
+        if let Some(&id) = self.material_ids.get(texture_name) {
+            return id;
+        }
+
+        // The image's extension isn't known until it is loaded, so this is the same extensionless
+        // path given to `DetectTextureFormat`. Tools will usually show the material by name anyway.
+        self.images
+            .push(json!({ "uri": options.texture_path(texture_name) }));
+        self.textures
+            .push(json!({ "source": self.images.len() - 1 }));
+        self.materials.push(json!({
+            "name": texture_name,
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": self.textures.len() - 1 },
+                "metallicFactor": 0.0,
+            },
+        }));
+
+        let id = self.materials.len() - 1;
+        self.material_ids.insert(texture_name.to_string(), id);
+        id
+    }
+
+    fn primitive<E: Extension>(&mut self, options: &ImportOptions<E>, group: &FaceGroup) -> Value {
+        let mut attributes = json!({
+            "POSITION": self.accessor(&group.positions, "VEC3", true),
+            "NORMAL": self.accessor(&group.normals, "VEC3", false),
+            "TEXCOORD_0": self.accessor(&group.tex_coords, "VEC2", false),
+        });
+        if group.lightmap_page.is_some() {
+            attributes["TEXCOORD_1"] = json!(self.accessor(&group.lightmap_coords, "VEC2", false));
+        } else {
+            attributes["COLOR_0"] = json!(self.accessor(&group.colors, "VEC4", false));
+        }
+
+        json!({
+            "attributes": attributes,
+            "material": self.material(options, &group.texture_name),
+        })
+    }
+}
+
+fn entity_node(entity: &MapEntity, mesh: Option<usize>, scale: f32) -> Value {
+    let classname = entity.classname().unwrap_or_default();
+    let mut node = json!({
+        "name": match entity.get("targetname") {
+            Some(targetname) => format!("{} ({})", classname, targetname),
+            None => classname.to_string(),
+        },
+    });
+
+    if let Some(origin) = entity.get_vec3("origin") {
+        let mut translation = to_world_space(origin);
+        translation.scale(scale);
+        node["translation"] = json!(translation);
+    }
+    if let Some(angles) = transform::entity_angles(entity) {
+        let q = transform::angles_to_rotation(angles);
+        node["rotation"] = json!([q.i, q.j, q.k, q.w]);
+    }
+    if let Some(mesh) = mesh {
+        node["mesh"] = json!(mesh);
+    }
+
+    node
+}
+
+fn document<E: Extension>(
+    bsp: &Bsp,
+    extensions: &BspExtensions,
+    options: &ImportOptions<E>,
+) -> (Value, Vec<u8>) {
+    let mut builder = Builder::default();
+
+    let mut primitives = HashMap::<usize, Vec<Value>>::new();
+    for group in face_groups(bsp, extensions, options) {
+        let primitive = builder.primitive(options, &group);
+        primitives.entry(group.model).or_default().push(primitive);
+    }
+
+    let mut models = primitives.into_iter().collect::<Vec<_>>();
+    models.sort_by_key(|&(model, _)| model);
+
+    let mut meshes = vec![];
+    let mut model_meshes = HashMap::new();
+    for (model, primitives) in models {
+        meshes.push(json!({
+            "name": format!("*{}", model),
+            "primitives": primitives,
+        }));
+        model_meshes.insert(model, meshes.len() - 1);
+    }
+
+    let mut nodes = vec![];
+    if let Some(&mesh) = model_meshes.get(&0) {
+        nodes.push(json!({ "name": "worldspawn", "mesh": mesh }));
+    }
+
+    for entity in entities::parse_entities(entities::entity_string(bsp)) {
+        if entity.classname() == Some("worldspawn") {
+            continue;
+        }
+
+        let mesh = entity
+            .model_index()
+            .and_then(|model| model_meshes.remove(&model));
+        nodes.push(entity_node(&entity, mesh, options.scale));
+    }
+
+    // Brush models that no entity refers to are still exported, so nothing goes missing.
+    let mut orphans = model_meshes
+        .into_iter()
+        .filter(|&(model, _)| model != 0)
+        .collect::<Vec<_>>();
+    orphans.sort();
+    for (model, mesh) in orphans {
+        nodes.push(json!({ "name": format!("*{}", model), "mesh": mesh }));
+    }
+
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": "amethyst-bsp" },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": builder.materials,
+        "textures": builder.textures,
+        "images": builder.images,
+        "accessors": builder.accessors,
+        "bufferViews": builder.views,
+        "buffers": [{ "byteLength": builder.buffer.len() }],
+    });
+    if builder.buffer.is_empty() {
+        gltf["buffers"] = json!([]);
+    }
+
+    // Top-level arrays may not be empty in glTF, so ones with nothing in them are left out.
+    if let Value::Object(fields) = &mut gltf {
+        let empty = fields
+            .iter()
+            .filter(|(_, value)| value.as_array().map_or(false, Vec::is_empty))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in empty {
+            fields.remove(&key);
+        }
+    }
+
+    (gltf, builder.buffer)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).cloned().unwrap_or(0),
+            chunk.get(2).cloned().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8], pad: u8) -> io::Result<()> {
+    let padding = (4 - data.len() % 4) % 4;
+
+    out.write_all(&((data.len() + padding) as u32).to_le_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&vec![pad; padding])
+}
+
+/// Write the geometry and entities of a map as glTF. Meshes are grouped the same way as
+/// `face_groups`, so the face filter, texture remapping and stripped prefixes in `options` apply.
+pub fn bsp_to_gltf<E: Extension, W: Write>(
+    bsp: &Bsp,
+    extensions: &BspExtensions,
+    options: &ImportOptions<E>,
+    container: GltfContainer,
+    mut out: W,
+) -> io::Result<()> {
+    let (mut gltf, buffer) = document(bsp, extensions, options);
+
+    match container {
+        GltfContainer::Gltf => {
+            if !buffer.is_empty() {
+                gltf["buffers"][0]["uri"] = json!(format!(
+                    "data:application/octet-stream;base64,{}",
+                    base64(&buffer)
+                ));
+            }
+
+            serde_json::to_writer(out, &gltf).map_err(io::Error::from)
+        }
+        GltfContainer::Glb => {
+            let json = serde_json::to_vec(&gltf).map_err(io::Error::from)?;
+            let chunk_len = |len: usize| 8 + len + (4 - len % 4) % 4;
+            let bin_len = if buffer.is_empty() {
+                0
+            } else {
+                chunk_len(buffer.len())
+            };
+            let total = 12 + chunk_len(json.len()) + bin_len;
+
+            out.write_all(b"glTF")?;
+            out.write_all(&2u32.to_le_bytes())?;
+            out.write_all(&(total as u32).to_le_bytes())?;
+            write_chunk(&mut out, b"JSON", &json, b' ')?;
+            if !buffer.is_empty() {
+                write_chunk(&mut out, b"BIN\0", &buffer, 0)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64_with_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
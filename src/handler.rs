@@ -0,0 +1,45 @@
+use crate::{entities::MapEntity, BspPrefabElement, Extension};
+use amethyst::core::Transform;
+use bsp::Bsp;
+
+/// Information about the entity being translated by an `EntityHandler`.
+pub struct EntityContext<'a> {
+    pub bsp: &'a Bsp,
+    /// The index of the entity in the entity lump.
+    pub index: usize,
+    /// The brush model used by the entity, if any.
+    pub model: Option<usize>,
+    /// The transform derived from the entity's `origin` and `angles`, if it has them, with its
+    /// translation already scaled by `scale`.
+    pub transform: Option<&'a Transform>,
+    /// `ImportOptions::scale`, for handlers adding positions or sizes read from the entity.
+    pub scale: f32,
+}
+
+/// Translates map entities into prefab data during import, so that games can turn their own
+/// entity classes into components. Handlers are tried in the order they were registered and the
+/// first to return `Some` is used. If the returned element has no transform, the transform
+/// derived from the entity's keys is used.
+pub trait EntityHandler<E: Extension = ()>: Send + Sync {
+    fn handle(
+        &self,
+        classname: &str,
+        keyvalues: &MapEntity,
+        ctx: &EntityContext,
+    ) -> Option<BspPrefabElement<E>>;
+}
+
+impl<E, F> EntityHandler<E> for F
+where
+    E: Extension,
+    F: Fn(&str, &MapEntity, &EntityContext) -> Option<BspPrefabElement<E>> + Send + Sync,
+{
+    fn handle(
+        &self,
+        classname: &str,
+        keyvalues: &MapEntity,
+        ctx: &EntityContext,
+    ) -> Option<BspPrefabElement<E>> {
+        self(classname, keyvalues, ctx)
+    }
+}
@@ -1,26 +1,171 @@
 pub use bsp;
 
+pub use crate::{
+    billboard::{Billboard, BillboardSystem},
+    bspx::{BspExtensions, BspxLump},
+    budget::{ClusterBudget, MapBudget},
+    buffer::{DrawRange, MapGeometry},
+    camera_spot::{CameraSpot, CameraSpotKind},
+    chunks::{ChunkedInstantiationSystem, MapChunks, MapInstantiated, PendingChunks},
+    cluster_entities::{ClusterEntities, ClusterMember},
+    collision::{CollisionBrush, CollisionGeometry, CollisionKind, CollisionMesh, CollisionSide},
+    decal::{project_decal, Decal},
+    deform::{Deform, VertexDeform},
+    detail::{DetailCullingSystem, DetailGeometry},
+    dialect::{BspDialect, DialectError},
+    entities::{parse_entities, EntityKeyValues, MapEntity},
+    fog::{FogParms, MapFog, MapFogPrefab},
+    game_mode::{GameMode, GameType, Skill},
+    geometry::{ConvexHull, Plane},
+    handler::{EntityContext, EntityHandler},
+    light_styles::{LightStyleSystem, LightStyleValues, LightStyles},
+    lightmap::{
+        FaceLightmap, FaceLightmapSystem, LightmapCoords, LightmapPages, LightmapPagesPrefab,
+        VertexColors, LIGHTMAP_SIZE,
+    },
+    loading::TextureLoadSystem,
+    lod::{LodLevel, LodOptions, LodSystem},
+    material::{MaterialDescription, MaterialMap},
+    mesh::{face_groups, FaceGroup},
+    minimap::{minimap_slices, MinimapSlice},
+    missing::{MissingTexture, MissingTextures, MissingTexturesPrefab},
+    models::ExternalModel,
+    movers::{MoverSystem, Pendulum, Rotator},
+    occluders::{Occluders, OccludersPrefab},
+    options::{
+        ElementMap, ExternalLightmaps, FaceFilter, FaceInfo, ImportOptions, ImportSelection,
+        LightingOptions, PrefabMap, TextureOptions, TextureSizes,
+    },
+    overlap::{BrushContact, Overlap},
+    pickup::Pickup,
+    portal::{PortalSurface, PortalSystem, PortalView},
+    reload::{MapGeneration, MapReloadSystem, MapReloaded},
+    remap::TextureRemap,
+    render_mode::{RenderMode, RenderModeSystem},
+    shader::{Directive, Shader, ShaderLibrary, Stage},
+    sort::RenderOrder,
+    spatial::{BspSpatialIndex, SpatialIndexSystem, SpatialLocation},
+    surface::SurfaceMaterial,
+    surface_light::SurfaceLight,
+    tcmod::{TcMod, TexCoordAnimation, TexCoordAnimationSystem, TexCoordMatrix, Wave, WaveFunc},
+    terrain::{TerrainBlend, TerrainBlendPrefab},
+    texture_budget::WorldTextureFormat,
+    tree::{BspTree, BspTreeLeaf, BspTreeNode, BspTrees, FrontToBack},
+    unload::despawn_map,
+    upload::{StagedLightmaps, StagedTexture, TextureUploadSystem, TextureUploads},
+    validate::{validate, ValidationIssue, ValidationReport},
+    vis::{AreaPortal, MapVis, VisLeaf, VisNode, VisibilitySystem, OCCLUSION_DETOUR},
+    volumes::{DamageVolume, Ladder},
+    water::{Liquid, Turbulence, WaterSurface},
+    waypoints::{Waypoint, WaypointGraph, Waypoints},
+};
+
+#[cfg(feature = "debug")]
+pub use crate::debug::{BspDebug, BspDebugSystem};
+#[cfg(feature = "gltf")]
+pub use crate::gltf::{bsp_to_gltf, GltfContainer};
+#[cfg(feature = "rendy")]
+pub use crate::rendy::rendy_lightmaps;
+
+pub mod flags;
+#[cfg(feature = "test_support")]
+pub mod test_support;
+
+mod billboard;
+mod brushes;
+mod bspx;
+mod budget;
+mod buffer;
+mod camera_spot;
+mod chunks;
+mod cluster_entities;
+mod collision;
+mod compress;
+#[cfg(feature = "debug")]
+mod debug;
+mod decal;
+mod deform;
+mod detail;
+mod dialect;
+mod entities;
+mod fog;
+mod game_mode;
+mod geometry;
+#[cfg(feature = "gltf")]
+mod gltf;
+mod handler;
+mod light_styles;
+mod lightmap;
+mod loading;
+mod lod;
+mod material;
+mod merge;
+mod mesh;
+mod minimap;
+mod missing;
+mod models;
+mod movers;
+mod occluders;
+mod options;
+mod overlap;
+mod patch;
+mod pickup;
+mod portal;
+mod reload;
+mod remap;
+mod render_mode;
+#[cfg(feature = "rendy")]
+mod rendy;
+mod scale;
+mod shader;
+mod sort;
+mod spatial;
+mod surface;
+mod surface_light;
+mod tcmod;
+mod terrain;
+mod texture_budget;
+mod transform;
+mod tree;
+mod unload;
+mod upload;
+mod validate;
+mod vis;
+mod volumes;
+mod water;
+mod waypoints;
+
+use crate::{lightmap::LightmapLayout, loading::TextureFile, scale::Scale};
 use amethyst::{
     assets::{
         Asset, AssetPrefab, Handle, Prefab, PrefabData, ProcessingState, ProgressCounter,
         SimpleFormat,
     },
+    core::{Named, Transform},
     derive::PrefabData,
     ecs::{Component, Entity, HashMapStorage, WriteStorage},
-    renderer::{MeshData, PosNormTex, Texture, TextureData, TextureMetadata},
+    renderer::{Light, MaterialPrefab, MeshData, Texture, TextureData, TextureMetadata},
     Error,
 };
 use amethyst_detect_filetype::DetectTextureFormat;
 use bsp::Bsp;
-use itertools::Itertools;
 use lazy_static::lazy_static;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Seek, SeekFrom},
+    sync::Arc,
+};
+
+/// How many clusters `ImportOptions::budget_report` logs.
+const BUDGET_LOG_CLUSTERS: usize = 10;
 
 const MISSING_TEXTURE_BYTES: &[u8] =
     include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/missing.png"));
 
-pub struct BspAsset(pub Bsp);
+/// A parsed map, along with the BSPX lumps found after its standard lumps.
+pub struct BspAsset(pub Bsp, pub BspExtensions);
 
 impl Asset for BspAsset {
     type Data = BspAsset;
@@ -29,12 +174,153 @@ impl Asset for BspAsset {
     const NAME: &'static str = "Bsp";
 }
 
+/// These expose the parts of the parsed map that the importer uses, for building custom
+/// pipelines. Everything is in BSP space, with Z up.
+impl BspAsset {
+    /// The entity lump, as text. `parse_entities` splits it into `MapEntity`s.
+    pub fn entities_str(&self) -> &str {
+        entities::entity_string(&self.0)
+    }
+
+    /// The lightmap pages, in the order faces index them. Maps compiled with deluxemapping store
+    /// a light direction page after each lightmap page.
+    pub fn lightmaps(&self) -> &[bsp::Lightmap] {
+        &self.0.lightmaps[..]
+    }
+
+    /// Every brush in the map, with the brushes of each model stored contiguously.
+    pub fn brushes(&self) -> &[bsp::Brush] {
+        &self.0.brushes[..]
+    }
+
+    /// The sides of every brush, indexed by the brushes' `brush_side` and `n_brush_sides`.
+    pub fn brush_sides(&self) -> &[bsp::BrushSide] {
+        &self.0.brush_sides[..]
+    }
+
+    /// The raw PVS: one row of `visdata_row_len()` bytes per cluster, with a bit set for each
+    /// cluster visible from it. This is empty for maps compiled without vis.
+    pub fn visdata(&self) -> &[u8] {
+        &self.0.vis_data.vecs[..]
+    }
+
+    pub fn visdata_row_len(&self) -> usize {
+        self.0.vis_data.sz_vecs as usize
+    }
+
+    /// The number of clusters leaves are in. This is counted from the leaves rather than the
+    /// PVS, so it is also right for maps compiled without vis.
+    pub fn cluster_count(&self) -> usize {
+        self.0
+            .leaves
+            .iter()
+            .map(|leaf| leaf.cluster + 1)
+            .max()
+            .unwrap_or(0)
+            .max(0) as usize
+    }
+
+    /// The number of leaves of the BSP tree, including those in solid.
+    pub fn leaf_count(&self) -> usize {
+        self.0.leaves.iter().count()
+    }
+
+    /// The bounds of the world model, as `(mins, maxs)`.
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        self.0.models().next().map(|world| (world.mins, world.maxs))
+    }
+
+    /// The brushes of every model that overlap the box from `mins` to `maxs`, for trigger checks
+    /// and simple character controllers. Brush models are tested where they were compiled, so
+    /// moved doors and platforms need their own checks.
+    pub fn overlaps_box(&self, mins: [f32; 3], maxs: [f32; 3]) -> Overlap {
+        overlap::overlapping_brushes(&self.0, |plane| overlap::box_outside(mins, maxs, plane))
+    }
+
+    /// The brushes of every model that overlap a sphere. See `overlaps_box`.
+    pub fn overlaps_sphere(&self, center: [f32; 3], radius: f32) -> Overlap {
+        overlap::overlapping_brushes(&self.0, |plane| {
+            overlap::sphere_outside(center, radius, plane)
+        })
+    }
+
+    /// The liquid at `point`, in the map's space rather than BSP space, such as to tell when the
+    /// camera is under water. Lava is reported over slime, and slime over water.
+    pub fn liquid_at(&self, point: [f32; 3]) -> Option<Liquid> {
+        let point = to_bsp_space(point);
+        Liquid::from_contents(self.overlaps_box(point, point).contents)
+    }
+
+    /// The paths of every texture importing this map with `options` will load: face textures,
+    /// terrain overlays, material textures and external lightmaps, sorted and without
+    /// duplicates. Faces of clusters and models the import leaves out, by the selection's
+    /// bounds or by `game_mode`, don't count. Loading these ahead of the map lets games show
+    /// accurate loading progress, since the prefab's own loads will then find them in the asset
+    /// cache.
+    pub fn referenced_textures<E: Extension>(&self, options: &ImportOptions<E>) -> Vec<String> {
+        let mut paths = vec![];
+
+        for group in imported_face_groups(&self.0, &self.1, options) {
+            let shader = options.shader(&group.texture_name);
+
+            match shader.and_then(terrain::terrain_textures) {
+                Some((base, overlay)) => {
+                    paths.push(options.texture_path(&base));
+                    paths.push(options.texture_path(&overlay));
+                }
+                None => paths.push(options.texture_path(&group.texture_name)),
+            }
+
+            if let Some(material) = options
+                .materials
+                .as_ref()
+                .and_then(|materials| materials.get(&group.texture_name, shader))
+            {
+                paths.extend(material.paths().map(String::from));
+            }
+        }
+
+        paths.extend(LightmapLayout::external_paths(&self.0, options));
+
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// The model paths of the map's `ExternalModel`s, sorted and without duplicates. These are
+    /// never loaded by this crate, but games loading them can preload these alongside
+    /// `referenced_textures`. The map's sounds aren't imported, so they aren't listed.
+    pub fn referenced_models<E: Extension>(&self, options: &ImportOptions<E>) -> Vec<String> {
+        let entities = entities::parse_entities(entities::entity_string(&self.0));
+
+        let mut paths = entities
+            .iter()
+            .filter(|entity| {
+                let classname = entity.classname().unwrap_or_default();
+                (classname == "misc_model" || classname == "misc_gamemodel")
+                    && selects_entity(&self.0, &options.selection, entity)
+                    && options
+                        .game_mode
+                        .as_ref()
+                        .map_or(true, |game_mode| game_mode.spawns(entity))
+            })
+            .filter_map(ExternalModel::from_entity)
+            .map(|model| model.path)
+            .collect::<Vec<_>>();
+
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+}
+
 impl From<BspAsset> for Result<ProcessingState<BspAsset>, Error> {
     fn from(other: BspAsset) -> Self {
         Ok(ProcessingState::Loaded(other))
     }
 }
 
+/// Loads Quake 3 maps, which may be compressed with gzip or bzip2.
 #[derive(Clone, Debug)]
 pub struct BspFormat;
 
@@ -44,17 +330,34 @@ impl SimpleFormat<BspAsset> for BspFormat {
     const NAME: &'static str = "Bsp";
 
     fn import(&self, bytes: Vec<u8>, _: Self::Options) -> Result<<BspAsset as Asset>::Data, Error> {
-        use std::io;
-
-        Bsp::read(io::Cursor::new(bytes))
-            .map_err(|e| Error::new(e))
-            .map(BspAsset)
+        let bytes = compress::decompress(bytes)?;
+        let (bsp, extensions) = read_map(io::Cursor::new(bytes), None)?;
+        Ok(BspAsset(bsp, extensions))
     }
 }
 
+/// Marks the root entity of an imported map. `map` is the `map_id` it was imported with. Every
+/// other entity of the map descends from the root, which has a `Transform` and is `Named`
+/// `map:<map_name>` (or `map:<map_id>` without a name), so the map can be moved, scaled or
+/// deleted as a whole.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct MapRoot {
+    pub map: usize,
+    pub name: Option<String>,
+}
+
+impl Component for MapRoot {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// A cluster of the map with the given `map_id`. Cluster entities are children of their map's
+/// `MapRoot`.
 #[derive(Debug, Clone, Default, Deserialize, Serialize, PrefabData)]
 #[prefab(Component)]
 pub struct Cluster {
+    #[serde(default)]
+    pub map: usize,
     pub id: i32,
 }
 
@@ -62,15 +365,74 @@ impl Component for Cluster {
     type Storage = HashMapStorage<Self>;
 }
 
+/// Prefab data that games can attach to map entities alongside the data generated by this crate,
+/// usually from an `EntityHandler`.
+pub trait Extension: for<'a> PrefabData<'a> + Clone + Default + Send + Sync + 'static {}
+
+impl<T> Extension for T where T: for<'a> PrefabData<'a> + Clone + Default + Send + Sync + 'static {}
+
+/// The data for one entity of an imported map. Every field is optional, so elements are usually
+/// built with `..Default::default()`.
 #[derive(Default, Deserialize, Serialize, PrefabData)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
-pub struct BspPrefabElement {
-    cluster: Option<Cluster>,
-    texture: Option<AssetPrefab<Texture, DetectTextureFormat>>,
-    mesh: Option<MeshData>,
+pub struct BspPrefabElement<E: Extension = ()> {
+    pub map_root: Option<MapRoot>,
+    pub cluster: Option<Cluster>,
+    pub cluster_member: Option<ClusterMember>,
+    pub texture: Option<AssetPrefab<Texture, WorldTextureFormat>>,
+    /// Set in place of `texture` with `ImportOptions::staggered_uploads`.
+    #[serde(skip)]
+    pub staged_texture: Option<StagedTexture>,
+    pub mesh: Option<MeshData>,
+    pub billboard: Option<Billboard>,
+    pub draw_range: Option<DrawRange>,
+    pub geometry: Option<MapGeometry>,
+    pub lightmap_coords: Option<LightmapCoords>,
+    pub vertex_colors: Option<VertexColors>,
+    pub light_styles: Option<LightStyles>,
+    pub tc_animation: Option<TexCoordAnimation>,
+    pub deform: Option<VertexDeform>,
+    pub surface: Option<SurfaceMaterial>,
+    pub water: Option<WaterSurface>,
+    pub render_order: Option<RenderOrder>,
+    pub portal: Option<PortalSurface>,
+    pub detail: Option<DetailGeometry>,
+    pub lod: Option<LodLevel>,
+    #[serde(skip)]
+    pub terrain: Option<TerrainBlendPrefab>,
+    #[serde(skip)]
+    pub lightmaps: Option<LightmapPagesPrefab>,
+    #[serde(skip)]
+    pub missing_textures: Option<MissingTexturesPrefab>,
+    pub budget: Option<MapBudget>,
+    pub extensions: Option<BspExtensions>,
+    pub vis: Option<MapVis>,
+    pub tree: Option<BspTree>,
+    pub occluders: Option<OccludersPrefab>,
+    pub fog: Option<MapFogPrefab>,
+    pub waypoints: Option<WaypointGraph>,
+    pub rotator: Option<Rotator>,
+    pub pendulum: Option<Pendulum>,
+    pub ladder: Option<Ladder>,
+    pub damage: Option<DamageVolume>,
+    pub pickup: Option<Pickup>,
+    pub camera_spot: Option<CameraSpot>,
+    pub surface_light: Option<SurfaceLight>,
+    pub light: Option<Light>,
+    pub external_model: Option<ExternalModel>,
+    pub collision: Option<CollisionGeometry>,
+    pub transform: Option<Transform>,
+    pub named: Option<Named>,
+    pub keyvalues: Option<EntityKeyValues>,
+    pub material: Option<MaterialPrefab<DetectTextureFormat>>,
+    pub generation: Option<MapGeneration>,
+    pub extension: Option<E>,
 }
 
+type TextureFallback =
+    Arc<dyn Fn(amethyst::Error) -> Result<TextureData, amethyst::Error> + Send + Sync + 'static>;
+
 lazy_static! {
     static ref MISSING_TEXTURE: TextureData = SimpleFormat::import(
         &DetectTextureFormat,
@@ -78,130 +440,777 @@ lazy_static! {
         TextureMetadata::srgb(),
     )
     .expect("Programmer error: missing texture is invalid");
-    static ref MISSING_TEXTURE_FUNCTION: Arc<dyn Fn(amethyst::Error) -> Result<TextureData, amethyst::Error> + Send + Sync + 'static> =
-        Arc::new(|_| { Ok(MISSING_TEXTURE.clone()) });
 }
-impl SimpleFormat<Prefab<BspPrefabElement>> for BspFormat {
-    type Options = ();
+impl<E: Extension> SimpleFormat<Prefab<BspPrefabElement<E>>> for BspFormat {
+    type Options = ImportOptions<E>;
 
     const NAME: &'static str = "Bsp";
 
     fn import(
         &self,
         bytes: Vec<u8>,
-        _: Self::Options,
-    ) -> Result<<Prefab<BspPrefabElement> as Asset>::Data, Error> {
-        use std::io;
+        options: Self::Options,
+    ) -> Result<<Prefab<BspPrefabElement<E>> as Asset>::Data, Error> {
+        let bytes = compress::decompress(bytes)?;
+        self.import_reader(io::Cursor::new(bytes), options)
+    }
+}
+
+impl BspFormat {
+    /// Import a map straight from a reader, such as a `File`, rather than reading the whole file
+    /// into memory first like `SimpleFormat::import` does. Lumps are read directly into the
+    /// parsed `Bsp`, so the raw file is never held in memory alongside it. Unlike
+    /// `SimpleFormat::import`, this doesn't accept gzip or bzip2 compressed maps.
+    pub fn import_reader<R, E>(
+        &self,
+        reader: R,
+        options: ImportOptions<E>,
+    ) -> Result<Prefab<BspPrefabElement<E>>, Error>
+    where
+        R: Read + Seek,
+        E: Extension,
+    {
+        let (bsp, extensions) = read_map(reader, options.dialect)?;
+
+        if options.validate {
+            let report = validate(&bsp);
+            if report.has_errors() {
+                return Err(Error::new(report));
+            }
+            for issue in &report.issues {
+                warn!("{}", issue);
+            }
+        }
+
+        Ok(build_prefabs(&bsp, &extensions, &options, false).root)
+    }
+}
+
+/// Read a map and its BSPX lumps.
+fn read_map<R: Read + Seek>(
+    mut reader: R,
+    dialect: Option<BspDialect>,
+) -> Result<(Bsp, BspExtensions), Error> {
+    let start = reader
+        .seek(SeekFrom::Current(0))
+        .map_err(|e| Error::new(e))?;
+    // Reading the map first detects its dialect, so BSPX is only looked for after the lump
+    // directory of a format it knows. Extensions are optional, so a broken BSPX header only
+    // loses them rather than the whole map.
+    let bsp = dialect::read_bsp(&mut reader, dialect)?;
+    let extensions = reader
+        .seek(SeekFrom::Start(start))
+        .and_then(|_| BspExtensions::read(&mut reader))
+        .unwrap_or_else(|e| {
+            warn!("Failed to read the map's BSPX lumps: {}", e);
+            BspExtensions::default()
+        });
+
+    for lump in &extensions.bspx {
+        info!("map has BSPX lump {}", lump.name);
+    }
+
+    Ok((bsp, extensions))
+}
+
+/// Build the prefab for an already-parsed map, such as one loaded as a `BspAsset`.
+/// `extensions` are the map's BSPX lumps, as read by `BspExtensions::read`, and are attached to
+/// the map's root.
+///
+/// Importing the same map with the same options always gives the same prefab, so entity
+/// indices can be relied on by cached prefabs, replays and networked spawns. After the root,
+/// clusters come in order of their id, each followed by its face groups sorted by `RenderOrder`
+/// and then by texture name. With `ImportOptions::prefetch_spawn`, the clusters that can be seen
+/// from the player spawn come first instead, followed by the rest, both in order of their id.
+/// Then come the map's entities in the order of the entity lump, and finally the face groups of
+/// each brush model in model order.
+pub fn import_bsp<E: Extension>(
+    bsp: &Bsp,
+    extensions: &BspExtensions,
+    options: &ImportOptions<E>,
+) -> Prefab<BspPrefabElement<E>> {
+    build_prefabs(bsp, extensions, options, false).root
+}
 
-        let bsp = Bsp::read(io::Cursor::new(bytes)).map_err(|e| Error::new(e))?;
+/// Build a map as separate prefabs for its root and each of its clusters, to be instantiated a
+/// few clusters at a time by `ChunkedInstantiationSystem`.
+pub fn import_bsp_chunked<E: Extension>(
+    bsp: &Bsp,
+    extensions: &BspExtensions,
+    options: &ImportOptions<E>,
+) -> MapChunks<E> {
+    build_prefabs(bsp, extensions, options, true)
+}
 
-        let mut prefab = Prefab::new();
+fn build_prefabs<E: Extension>(
+    bsp: &Bsp,
+    extensions: &BspExtensions,
+    options: &ImportOptions<E>,
+    chunked: bool,
+) -> MapChunks<E> {
+    let entities = entities::parse_entities(entities::entity_string(bsp));
 
-        let mut faces = vec![];
+    let mut importer = Importer {
+        bsp,
+        options,
+        lightmaps: LightmapLayout::new(bsp, entities::worldspawn(&entities))
+            .with_face_styles(extensions),
+        missing: MissingTexturesPrefab::default(),
+        budget: MapBudget::default(),
+        geometry: MapGeometry::default(),
+        portals: portal::portal_links(&entities, options.scale),
+        texture_formats: WorldTextureFormat::plan(bsp, options),
+        pivots: HashMap::new(),
+    };
 
-        // TODO: We can do this with index buffers instead of vertex buffers
-        for (id, cluster) in &bsp.leaves.clusters() {
-            let cluster_id = prefab.add(
+    let mut prefab = Prefab::new();
+
+    let mut root = BspPrefabElement::default();
+    root.generation = Some(MapGeneration::of(bsp));
+    root.extensions = Some(extensions.clone());
+    root.map_root = Some(MapRoot {
+        map: options.map_id,
+        name: options.map_name.clone(),
+    });
+    // Everything else in the map is parented to the root, so this moves the whole map. It keeps
+    // a unit scale, since `options.scale` is applied to the imported data itself.
+    root.transform = Some(Transform::default());
+    root.named = Some(Named::new(match &options.map_name {
+        Some(name) => format!("map:{}", name),
+        None => format!("map:{}", options.map_id),
+    }));
+    root.lightmaps = Some(importer.lightmaps.prefab(bsp, options));
+    let mut vis = MapVis::new(bsp, &entities);
+    // Clusters that can be seen from the spawn are imported first, so that their textures are
+    // loaded first.
+    let spawn_visible = if options.prefetch_spawn {
+        entities::player_spawn(&entities)
+            .and_then(|spawn| spawn.get_vec3("origin"))
+            .and_then(|origin| vis.visible_clusters(to_world_space(origin)))
+    } else {
+        None
+    };
+    // Built before scaling, since the graph finds touching leaves with a tolerance in map units.
+    if options.waypoints {
+        let mut waypoints = WaypointGraph::new(&vis.leaves);
+        waypoints.scale(options.scale);
+        root.waypoints = Some(waypoints);
+    }
+    vis.scale(options.scale);
+    if options.bsp_tree {
+        root.tree = Some(BspTree::new(&vis));
+    }
+    root.vis = Some(vis);
+    root.fog = MapFogPrefab::new(bsp, entities::worldspawn(&entities), options);
+    root.occluders = options.occluder_min_size.map(|min_size| {
+        let mut occluders = OccludersPrefab::new(bsp, min_size);
+        occluders.hulls.scale(options.scale);
+        occluders
+    });
+    if options.collision {
+        let mut collision = CollisionGeometry::new(
+            bsp,
+            options.collision_patch_level,
+            options.shaders.as_deref(),
+        );
+        collision.scale(options.scale);
+        root.collision = Some(collision);
+    }
+    *prefab.data_or_default(0) = options.map_element(root);
+
+    let mut faces = vec![];
+
+    let mut clusters = vec![];
+
+    let selection = &options.selection;
+
+    let mut cluster_leaves = mesh::cluster_leaves(bsp).into_iter().collect::<Vec<_>>();
+    if let Some(visible) = &spawn_visible {
+        // Stable, so that both halves stay in order of their id.
+        cluster_leaves.sort_by_key(|&(id, _)| visible.get(id as usize) != Some(&true));
+    }
+
+    for (id, leaves) in cluster_leaves {
+        if !selects_cluster(selection, id, &leaves) {
+            continue;
+        }
+
+        let element = BspPrefabElement {
+            cluster: Some(Cluster {
+                map: options.map_id,
+                id,
+            }),
+            ..Default::default()
+        };
+
+        faces.clear();
+        faces.extend(
+            leaves
+                .into_iter()
+                .flat_map(|leaf| mesh::leaf_faces(bsp, leaf)),
+        );
+
+        // Chunks are parented to the map's root when they are instantiated, so the cluster is
+        // the root of its own prefab.
+        if chunked {
+            let mut chunk = Prefab::new();
+            *chunk.data_or_default(0) = options.map_element(element);
+            importer.add_face_groups(&mut chunk, Some(0), 0, Some(id), &mut faces);
+            clusters.push(chunk);
+        } else {
+            let cluster_id = importer.add(&mut prefab, Some(0), element);
+            importer.add_face_groups(&mut prefab, Some(cluster_id), 0, Some(id), &mut faces);
+        }
+    }
+
+    let ladders = Ladder::extract(bsp).into_iter().filter(|ladder| {
+        selection.includes_model(0) && selection.overlaps((ladder.mins, ladder.maxs))
+    });
+    for mut ladder in ladders {
+        ladder.scale(options.scale);
+        importer.add(
+            &mut prefab,
+            Some(0),
+            BspPrefabElement {
+                ladder: Some(ladder),
+                ..Default::default()
+            },
+        );
+    }
+
+    if options.surface_lights {
+        for (light, transform) in surface_light::surface_lights(bsp, options) {
+            importer.add(
+                &mut prefab,
                 Some(0),
                 BspPrefabElement {
-                    cluster: Some(Cluster { id }),
+                    light: Some(light.point_light()),
+                    surface_light: Some(light),
+                    transform: Some(transform),
                     ..Default::default()
-                }
-                .into(),
+                },
             );
+        }
+    }
 
-            faces.clear();
-            faces.extend(
-                cluster
-                    .into_iter()
-                    .flat_map(|leaf| bsp::Handle::new(&bsp, leaf).faces()),
-            );
-            faces.sort_unstable_by_key(|face| face.texture().map(|t| t.name));
-
-            for (tex, faces) in &faces.iter().group_by(|face| face.texture) {
-                let tex = if let Some(texture) = bsp.texture(tex as usize) {
-                    texture
-                } else {
-                    continue;
-                };
-                if !tex.flags.should_draw() {
-                    continue;
-                }
+    let mut model_parents = HashMap::new();
+    let unspawned_models = unspawned_models(bsp, &entities, options);
+
+    for (index, entity) in entities.iter().enumerate() {
+        let classname = entity.classname().unwrap_or_default();
+        if classname == "worldspawn" {
+            continue;
+        }
+
+        if !selects_entity(bsp, selection, entity) {
+            continue;
+        }
 
-                let tex_name = tex.name;
-
-                let verts = faces
-                    .flat_map(|face| {
-                        face.vertices().map(|vert| PosNormTex {
-                            position: [vert.position[0], vert.position[2], -vert.position[1]]
-                                .into(),
-                            normal: [vert.normal[0], vert.normal[2], -vert.normal[1]].into(),
-                            tex_coord: vert.surface_texcoord.into(),
-                        })
-                    })
-                    .collect::<Vec<_>>();
-
-                prefab.add(
-                    Some(cluster_id),
-                    Some(BspPrefabElement {
-                        texture: Some(AssetPrefab::FileOrElse(
-                            tex_name.to_string(),
-                            DetectTextureFormat,
-                            TextureMetadata::srgb(),
-                            MISSING_TEXTURE_FUNCTION.clone(),
-                        )),
-                        mesh: Some(verts.into()),
-                        ..Default::default()
-                    }),
-                );
+        if let Some(game_mode) = &options.game_mode {
+            if !game_mode.spawns(entity) {
+                continue;
             }
         }
 
-        for model in bsp.models() {
-            faces.clear();
-            faces.extend(model.faces());
-            faces.sort_unstable_by_key(|face| face.texture().map(|t| t.name));
+        let mut transform = transform::entity_transform(entity);
+        transform.scale(options.scale);
+        if let Some(mut pivot) = transform::model_pivot(bsp, entity) {
+            pivot.scale(options.scale);
+            let mut pivoted = transform.unwrap_or_default();
+            pivoted.set_position(pivot.into());
+            transform = Some(pivoted);
+            importer
+                .pivots
+                .extend(entity.model_index().map(|model| (model, pivot)));
+        }
+        let ctx = EntityContext {
+            bsp,
+            index,
+            model: entity.model_index(),
+            transform: transform.as_ref(),
+            scale: options.scale,
+        };
+
+        let mut element = options
+            .entity_handlers
+            .iter()
+            .filter_map(|handler| handler.handle(classname, entity, &ctx))
+            .next()
+            .unwrap_or_else(|| builtin_element(classname, entity, &ctx));
+        if element.transform.is_none() {
+            element.transform = transform;
+        }
+        if element.named.is_none() {
+            element.named = Some(entity_name(classname, entity));
+        }
+        if element.keyvalues.is_none() {
+            element.keyvalues = Some(EntityKeyValues(entity.clone()));
+        }
+
+        let entity_id = importer.add(&mut prefab, Some(0), element);
 
-            for (tex, faces) in &faces.iter().group_by(|face| face.texture) {
-                let tex = if let Some(texture) = bsp.texture(tex as usize) {
-                    texture
-                } else {
-                    continue;
-                };
-                if !tex.flags.should_draw() {
-                    continue;
+        if let Some(model) = entity.model_index() {
+            model_parents.insert(model, entity_id);
+        }
+    }
+
+    // The world model's faces have already been added per-cluster above. Models without an
+    // entity are kept under the root, so that every part of the map is removed along with it.
+    for (i, model) in bsp.models().enumerate().skip(1) {
+        if !selects_model(selection, i, &model, &unspawned_models) {
+            continue;
+        }
+
+        faces.clear();
+        faces.extend(mesh::model_faces(bsp, &model));
+
+        importer.add_face_groups(
+            &mut prefab,
+            Some(model_parents.get(&i).cloned().unwrap_or(0)),
+            i,
+            None,
+            &mut faces,
+        );
+    }
+
+    // Only known once every face group has been added, so these bypass `map_element`.
+    let root = prefab.data_or_default(0);
+    root.missing_textures = Some(importer.missing);
+    if options.budget_report {
+        for budget in importer.budget.heaviest(BUDGET_LOG_CLUSTERS) {
+            info!("{}", budget);
+        }
+        root.budget = Some(importer.budget);
+    }
+    if options.shared_geometry {
+        root.geometry = Some(importer.geometry.finish());
+    }
+
+    options.map_prefab(&mut prefab);
+    for cluster in &mut clusters {
+        options.map_prefab(cluster);
+    }
+
+    MapChunks {
+        root: prefab,
+        clusters,
+    }
+}
+
+/// The element for entities that no `EntityHandler` recognised, with components for the standard
+/// Quake 3 classes this crate knows about.
+fn builtin_element<E: Extension>(
+    classname: &str,
+    entity: &MapEntity,
+    ctx: &EntityContext,
+) -> BspPrefabElement<E> {
+    let mut element = BspPrefabElement::default();
+
+    match classname {
+        "func_rotating" => element.rotator = Some(Rotator::from_entity(entity, ctx)),
+        "func_pendulum" => element.pendulum = Some(Pendulum::from_entity(entity, ctx)),
+        "trigger_hurt" => element.damage = DamageVolume::from_entity(entity, ctx),
+        "misc_model" | "misc_gamemodel" => {
+            element.external_model = ExternalModel::from_entity(entity).map(|mut model| {
+                model.scale.scale(ctx.scale);
+                model
+            })
+        }
+        _ if Pickup::is_pickup(classname) => {
+            let mut pickup = Pickup::from_entity(classname, entity);
+            pickup.origin.scale(ctx.scale);
+            element.pickup = Some(pickup);
+        }
+        _ => {
+            element.camera_spot = CameraSpotKind::from_classname(classname).map(|kind| {
+                let mut spot = CameraSpot::from_entity(kind, entity, ctx.bsp);
+                spot.origin.scale(ctx.scale);
+                spot
+            })
+        }
+    }
+
+    // Camera spots with a `target` face it, rather than along the angles in their keys.
+    if let Some(spot) = &element.camera_spot {
+        element.transform = Some(spot.transform());
+    }
+
+    // Movers need a transform to animate, even if they have no `origin`, and models need one to
+    // be drawn at all.
+    if element.rotator.is_some() || element.pendulum.is_some() || element.external_model.is_some() {
+        element.transform = Some(ctx.transform.cloned().unwrap_or_default());
+    }
+
+    element
+}
+
+fn model_bounds(model: &bsp::Model) -> ([f32; 3], [f32; 3]) {
+    geometry::bounds_of(vec![to_world_space(model.mins), to_world_space(model.maxs)])
+        .unwrap_or_default()
+}
+
+/// Whether the faces of cluster `id` are imported: `selection` has to include the cluster, and
+/// its leaves have to overlap the selection's bounds.
+fn selects_cluster(selection: &ImportSelection, id: i32, leaves: &[&bsp::Leaf]) -> bool {
+    let float = |v: [i32; 3]| [v[0] as f32, v[1] as f32, v[2] as f32];
+    let bounds = geometry::bounds_of(leaves.iter().flat_map(|leaf| {
+        vec![
+            to_world_space(float(leaf.mins)),
+            to_world_space(float(leaf.maxs)),
+        ]
+    }));
+
+    selection.includes_cluster(id) && bounds.map_or(true, |bounds| selection.overlaps(bounds))
+}
+
+/// Whether the faces of brush model `index` are imported. `unspawned` are the models left out
+/// along with their entities, from `unspawned_models`.
+fn selects_model(
+    selection: &ImportSelection,
+    index: usize,
+    model: &bsp::Model,
+    unspawned: &HashSet<usize>,
+) -> bool {
+    selection.includes_model(index)
+        && selection.overlaps(model_bounds(model))
+        && !unspawned.contains(&index)
+}
+
+/// The brush models of the selected entities that `ImportOptions::game_mode` doesn't spawn.
+fn unspawned_models<E: Extension>(
+    bsp: &Bsp,
+    entities: &[MapEntity],
+    options: &ImportOptions<E>,
+) -> HashSet<usize> {
+    let game_mode = match &options.game_mode {
+        Some(game_mode) => game_mode,
+        None => return HashSet::new(),
+    };
+
+    entities
+        .iter()
+        .filter(|entity| selects_entity(bsp, &options.selection, entity))
+        .filter(|entity| !game_mode.spawns(entity))
+        .filter_map(MapEntity::model_index)
+        .collect()
+}
+
+/// The face groups `build_prefabs` builds meshes from, leaving out the clusters and models it
+/// does. Unlike the prefab, brush models aren't moved to their pivots.
+fn imported_face_groups<E: Extension>(
+    bsp: &Bsp,
+    extensions: &BspExtensions,
+    options: &ImportOptions<E>,
+) -> Vec<FaceGroup> {
+    let entities = entities::parse_entities(entities::entity_string(bsp));
+    let lightmaps =
+        LightmapLayout::new(bsp, entities::worldspawn(&entities)).with_face_styles(extensions);
+    let unspawned = unspawned_models(bsp, &entities, options);
+
+    let mut out = vec![];
+    let mut faces = vec![];
+
+    for (id, leaves) in mesh::cluster_leaves(bsp) {
+        if !selects_cluster(&options.selection, id, &leaves) {
+            continue;
+        }
+
+        faces.clear();
+        faces.extend(
+            leaves
+                .into_iter()
+                .flat_map(|leaf| mesh::leaf_faces(bsp, leaf)),
+        );
+        out.extend(mesh::group_faces(
+            bsp,
+            options,
+            &lightmaps,
+            0,
+            Some(id),
+            &mut faces,
+        ));
+    }
+
+    for (i, model) in bsp.models().enumerate().skip(1) {
+        if !selects_model(&options.selection, i, &model, &unspawned) {
+            continue;
+        }
+
+        faces.clear();
+        faces.extend(mesh::model_faces(bsp, &model));
+        out.extend(mesh::group_faces(
+            bsp, options, &lightmaps, i, None, &mut faces,
+        ));
+    }
+
+    out
+}
+
+/// Whether an entity is part of `selection`, going by its brush model if it has one and by its
+/// origin otherwise.
+fn selects_entity(bsp: &Bsp, selection: &ImportSelection, entity: &MapEntity) -> bool {
+    match entity.model_index() {
+        Some(index) => {
+            selection.includes_model(index)
+                && bsp
+                    .models()
+                    .nth(index)
+                    .map_or(false, |model| selection.overlaps(model_bounds(&model)))
+        }
+        None => {
+            let origin = entity.get_vec3("origin").map(to_world_space);
+            selection.includes_model(0)
+                && origin.map_or(true, |origin| selection.overlaps((origin, origin)))
+        }
+    }
+}
+
+/// Names entities `classname:targetname`, or just `classname` for entities without a
+/// `targetname`.
+fn entity_name(classname: &str, entity: &MapEntity) -> Named {
+    match entity.get("targetname") {
+        Some(targetname) => Named::new(format!("{}:{}", classname, targetname)),
+        None => Named::new(classname.to_string()),
+    }
+}
+
+fn to_world_space(v: [f32; 3]) -> [f32; 3] {
+    [v[0], v[2], -v[1]]
+}
+
+/// `to_world_space` for every point in a slice.
+fn points_to_world_space(points: &mut [[f32; 3]]) {
+    for p in points {
+        *p = [p[0], p[2], -p[1]];
+    }
+}
+
+fn to_bsp_space(v: [f32; 3]) -> [f32; 3] {
+    [v[0], -v[2], v[1]]
+}
+
+struct Importer<'a, E: Extension> {
+    bsp: &'a Bsp,
+    options: &'a ImportOptions<E>,
+    lightmaps: LightmapLayout,
+    missing: MissingTexturesPrefab,
+    budget: MapBudget,
+    geometry: MapGeometry,
+    portals: Vec<portal::PortalLink>,
+    /// The formats of world textures that have to be downscaled to fit the texture budget.
+    texture_formats: HashMap<String, WorldTextureFormat>,
+    /// The pivots of brush models imported relative to their origin brush.
+    pivots: HashMap<usize, [f32; 3]>,
+}
+
+impl<'a, E: Extension> Importer<'a, E> {
+    fn add(
+        &self,
+        prefab: &mut Prefab<BspPrefabElement<E>>,
+        parent: Option<usize>,
+        element: BspPrefabElement<E>,
+    ) -> usize {
+        prefab.add(parent, Some(self.options.map_element(element)))
+    }
+
+    fn add_face_groups(
+        &mut self,
+        prefab: &mut Prefab<BspPrefabElement<E>>,
+        parent: Option<usize>,
+        model: usize,
+        cluster: Option<i32>,
+        faces: &mut Vec<mesh::IndexedFace<'a>>,
+    ) {
+        let mut budget = ClusterBudget {
+            model,
+            cluster,
+            ..Default::default()
+        };
+        let mut textures = HashSet::new();
+        let mut pages = HashSet::new();
+
+        for mut group in mesh::group_faces(
+            self.bsp,
+            self.options,
+            &self.lightmaps,
+            model,
+            cluster,
+            faces,
+        ) {
+            if let Some(&[x, y, z]) = self.pivots.get(&model) {
+                group.translate([-x, -y, -z]);
+            }
+            // Missing textures are reported by the name that failed to load, so terrain overlays
+            // are told apart from their base.
+            match self
+                .options
+                .shader(&group.texture_name)
+                .and_then(terrain::terrain_textures)
+            {
+                Some((base, overlay)) => {
+                    self.missing.add_faces(&base, group.face_count);
+                    self.missing.add_faces(&overlay, group.face_count);
                 }
+                None => self
+                    .missing
+                    .add_faces(&group.texture_name, group.face_count),
+            }
+
+            let sprites = self
+                .options
+                .shader(&group.texture_name)
+                .and_then(billboard::autosprite)
+                .and_then(|axial| billboard::split_sprites(&group, axial));
+
+            budget.draw_calls += sprites.as_ref().map_or(1, Vec::len);
+            budget.vertices += group.vertex_count();
+            textures.insert(group.texture);
+            pages.extend(group.lightmap_page);
 
-                let tex_name = tex.name;
-
-                let verts = faces
-                    .flat_map(|face| {
-                        face.vertices().map(|vert| PosNormTex {
-                            position: [vert.position[0], vert.position[2], -vert.position[1]]
-                                .into(),
-                            normal: [vert.normal[0], vert.normal[2], -vert.normal[1]].into(),
-                            tex_coord: vert.surface_texcoord.into(),
-                        })
-                    })
-                    .collect::<Vec<_>>();
-
-                prefab.add(
-                    None,
-                    Some(BspPrefabElement {
-                        texture: Some(AssetPrefab::FileOrElse(
-                            tex_name.to_string(),
-                            DetectTextureFormat,
-                            TextureMetadata::srgb(),
-                            MISSING_TEXTURE_FUNCTION.clone(),
-                        )),
-                        mesh: Some(verts.into()),
-                        ..Default::default()
-                    }),
-                );
+            match sprites {
+                Some(sprites) => {
+                    for (sprite, billboard, transform) in sprites {
+                        let mut element = self.group_element(sprite);
+                        element.billboard = Some(billboard);
+                        element.transform = Some(transform);
+                        self.add(prefab, parent, element);
+                    }
+                }
+                None => {
+                    let lods = if self.options.lods.is_empty() {
+                        None
+                    } else {
+                        lod::generate(&group, &self.options.lods)
+                    };
+
+                    let mut element = self.group_element(group);
+                    // Detail surfaces are culled by distance instead.
+                    let lods = lods.filter(|_| element.detail.is_none());
+                    if let Some((full, lods)) = lods {
+                        element.lod = Some(full);
+                        self.add(prefab, parent, element);
+
+                        for (group, level) in lods {
+                            budget.draw_calls += 1;
+                            budget.vertices += group.vertex_count();
+
+                            let mut element = self.group_element(group);
+                            element.lod = Some(level);
+                            self.add(prefab, parent, element);
+                        }
+                    } else {
+                        self.add(prefab, parent, element);
+                    }
+                }
             }
         }
 
-        Ok(prefab)
+        if budget.draw_calls > 0 {
+            budget.textures = textures.len();
+            budget.lightmap_pages = pages.len();
+            self.budget.clusters.push(budget);
+        }
+    }
+
+    fn group_element(&mut self, group: FaceGroup) -> BspPrefabElement<E> {
+        let shader = self.options.shader(&group.texture_name);
+        let material = self
+            .options
+            .materials
+            .as_ref()
+            .and_then(|materials| materials.get(&group.texture_name, shader));
+        let tc_animation = shader.and_then(TexCoordAnimation::from_shader);
+        let deform = shader
+            .and_then(VertexDeform::from_shader)
+            .map(|mut deform| {
+                deform.scale(self.options.scale);
+                deform
+            });
+        let portal = shader
+            .filter(|shader| portal::is_portal(shader))
+            .and_then(|_| PortalSurface::new(&group, &self.portals, self.options.scale));
+        let bsp_texture = self.bsp.texture(group.texture);
+        let surface = bsp_texture.map(|texture| SurfaceMaterial::of_texture(texture, shader));
+        let detail = bsp_texture.and_then(|texture| DetailGeometry::new(&group, texture, shader));
+        let water = bsp_texture.and_then(|texture| WaterSurface::new(&group, texture, shader));
+        // Terrain shaders are named after the blend rather than an image, so the base texture
+        // comes from the shader too.
+        let terrain = shader.and_then(terrain::terrain_textures);
+        let base_texture = terrain
+            .as_ref()
+            .map_or(group.texture_name.as_str(), |(base, _)| base.as_str());
+        let texture_file = |name: &str| {
+            let path = self.options.texture_path(name);
+            TextureFile {
+                format: self.texture_formats.get(&path).cloned().unwrap_or_default(),
+                path,
+                metadata: self.options.texture_metadata(name),
+                fallback: self.options.missing_texture.fallback(name, &self.missing),
+            }
+        };
+        let TextureFile {
+            path,
+            format,
+            metadata,
+            fallback,
+        } = texture_file(base_texture);
+        let texture = AssetPrefab::FileOrElse(path, format, metadata, fallback);
+        let (texture, staged_texture) = if self.options.staggered_uploads {
+            (None, Some(StagedTexture(texture)))
+        } else {
+            (Some(texture), None)
+        };
+        let terrain = terrain.map(|(_, overlay)| {
+            TerrainBlendPrefab::new(
+                texture_file(&overlay),
+                group.colors.iter().map(|color| color[3]).collect(),
+            )
+        });
+
+        let (mesh, draw_range, lightmap_tex_coords, vertex_colors) = if self.options.shared_geometry
+        {
+            (None, Some(self.geometry.push(&group)), vec![], None)
+        } else {
+            let mesh = group.pos_norm_tex().into();
+            let vertex_colors = match group.lightmap_page {
+                Some(_) => None,
+                None => Some(VertexColors {
+                    colors: group.colors,
+                }),
+            };
+            (Some(mesh), None, group.lightmap_coords, vertex_colors)
+        };
+
+        BspPrefabElement {
+            texture,
+            staged_texture,
+            terrain,
+            material: material.map(MaterialDescription::prefab),
+            tc_animation,
+            deform,
+            surface,
+            water,
+            render_order: Some(RenderOrder::from_shader(shader)),
+            cluster_member: group.cluster.map(|cluster| ClusterMember {
+                map: self.options.map_id,
+                cluster,
+            }),
+            portal,
+            detail,
+            mesh,
+            draw_range,
+            vertex_colors,
+            lightmap_coords: group.lightmap_page.map(|page| LightmapCoords {
+                page,
+                tex_coords: lightmap_tex_coords,
+            }),
+            light_styles: if group.styles.is_animated() {
+                Some(group.styles)
+            } else {
+                None
+            },
+            ..Default::default()
+        }
     }
 }
 
@@ -0,0 +1,189 @@
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::timing::Time,
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, Read, System, Write, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_LIGHT_STYLES: usize = 4;
+pub const NO_STYLE: u8 = 255;
+pub const FIRST_SWITCHABLE_STYLE: u8 = 32;
+
+const FRAMES_PER_SECOND: f64 = 10.0;
+
+// The patterns from Quake's `world.qc`, indexed by style number. `a` is black, `m` is normal
+// brightness and `z` is double brightness.
+const STANDARD_PATTERNS: &[&str] = &[
+    "m",
+    "mmnmmommommnonmmonqnmmo",
+    "abcdefghijklmnopqrstuvwxyzyxwvutsrqponmlkjihgfedcba",
+    "mmmmmaaaaammmmmaaaaaabcdefgabcdefg",
+    "mamamamamama",
+    "jklmnopqrstuvwxyzyxwvutsrqponmlkj",
+    "nmonqnmomnmomomno",
+    "mmmaaaabcdefgmmmmaaaammmaamm",
+    "mmmaaammmaaammmabcdefaaaammmmabcdefmmmaaaa",
+    "aaaaaaaazzzzzzzz",
+    "mmamammmmammamamaaamammma",
+    "abcdefghijklmnopqrrqponmlkjihgfedcba",
+];
+
+/// The (up to four) light styles whose lightmaps are summed to light a face. Unused slots are
+/// `NO_STYLE`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, PrefabData,
+)]
+#[prefab(Component)]
+pub struct LightStyles {
+    pub styles: [u8; MAX_LIGHT_STYLES],
+}
+
+impl Default for LightStyles {
+    fn default() -> Self {
+        LightStyles {
+            styles: [0, NO_STYLE, NO_STYLE, NO_STYLE],
+        }
+    }
+}
+
+impl LightStyles {
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = u8> + 'a {
+        self.styles.iter().cloned().take_while(|&s| s != NO_STYLE)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.iter().any(|s| s != 0)
+    }
+
+    /// Read the styles of every face from a BSPX `LMSTYLE` lump, which stores four style bytes
+    /// per face in face lump order.
+    pub(crate) fn from_lmstyle(bytes: &[u8]) -> Vec<Self> {
+        bytes
+            .chunks_exact(MAX_LIGHT_STYLES)
+            .map(|chunk| {
+                let mut styles = [NO_STYLE; MAX_LIGHT_STYLES];
+                styles.copy_from_slice(chunk);
+                LightStyles { styles }
+            })
+            .collect()
+    }
+}
+
+impl Component for LightStyles {
+    type Storage = DenseVecStorage<Self>;
+}
+
+fn pattern_value(c: u8) -> f32 {
+    (c.max(b'a').min(b'z') - b'a') as f32 / (b'm' - b'a') as f32
+}
+
+/// The current brightness multiplier of every light style, updated by `LightStyleSystem`.
+pub struct LightStyleValues {
+    patterns: Vec<Option<String>>,
+    values: Vec<f32>,
+}
+
+impl Default for LightStyleValues {
+    fn default() -> Self {
+        let mut patterns = vec![None; NO_STYLE as usize];
+        for (pattern, slot) in STANDARD_PATTERNS.iter().zip(patterns.iter_mut()) {
+            *slot = Some(pattern.to_string());
+        }
+
+        LightStyleValues {
+            patterns,
+            values: vec![1.0; NO_STYLE as usize],
+        }
+    }
+}
+
+impl LightStyleValues {
+    pub fn value(&self, style: u8) -> f32 {
+        self.values.get(style as usize).cloned().unwrap_or(0.0)
+    }
+
+    /// Sum of the current values of all styles affecting a face, for use as a lightmap scale when
+    /// a face only has a single pre-blended lightmap.
+    pub fn combined(&self, styles: &LightStyles) -> f32 {
+        styles.iter().map(|s| self.value(s)).sum()
+    }
+
+    /// Replace the pattern for a style, in Quake's `a`-`z` notation.
+    pub fn set_pattern<S: Into<String>>(&mut self, style: u8, pattern: S) {
+        if let Some(slot) = self.patterns.get_mut(style as usize) {
+            *slot = Some(pattern.into());
+        }
+    }
+
+    /// Turn a switchable light on or off, as `trigger_relay`s targetting a light would.
+    pub fn set_switched(&mut self, style: u8, on: bool) {
+        self.set_pattern(style, if on { "m" } else { "a" });
+    }
+
+    fn update(&mut self, seconds: f64) {
+        let frame = (seconds * FRAMES_PER_SECOND) as usize;
+
+        for (pattern, value) in self.patterns.iter().zip(self.values.iter_mut()) {
+            *value = match pattern.as_ref().map(|p| p.as_bytes()) {
+                Some(p) if !p.is_empty() => pattern_value(p[frame % p.len()]),
+                _ => 1.0,
+            };
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LightStyleSystem;
+
+impl<'a> System<'a> for LightStyleSystem {
+    type SystemData = (Read<'a, Time>, Write<'a, LightStyleValues>);
+
+    fn run(&mut self, (time, mut values): Self::SystemData) {
+        values.update(time.absolute_time_seconds());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_advance_at_ten_hertz() {
+        let mut values = LightStyleValues::default();
+
+        values.update(0.0);
+        assert_eq!(values.value(0), 1.0);
+        assert_eq!(values.value(4), 1.0);
+
+        values.update(0.1);
+        assert_eq!(values.value(4), 0.0);
+    }
+
+    #[test]
+    fn reads_lmstyle() {
+        let styles = LightStyles::from_lmstyle(&[0, 255, 255, 255, 0, 5, 255, 255]);
+
+        assert_eq!(
+            styles,
+            vec![
+                LightStyles::default(),
+                LightStyles {
+                    styles: [0, 5, 255, 255]
+                }
+            ]
+        );
+        assert!(styles[1].is_animated());
+    }
+
+    #[test]
+    fn switched_lights() {
+        let mut values = LightStyleValues::default();
+
+        values.set_switched(FIRST_SWITCHABLE_STYLE, false);
+        values.update(0.0);
+        assert_eq!(values.value(FIRST_SWITCHABLE_STYLE), 0.0);
+        assert_eq!(values.combined(&LightStyles::default()), 1.0);
+    }
+}
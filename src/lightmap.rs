@@ -0,0 +1,506 @@
+use crate::{
+    bspx::BspExtensions,
+    entities::MapEntity,
+    loading::{LoadingTexture, TextureFile},
+    options::{ExternalLightmaps, ImportOptions, LightingOptions},
+    upload::StagedLightmaps,
+    Extension, LightStyles, TextureFallback,
+};
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader, PrefabData, ProgressCounter},
+    core::Parent,
+    derive::PrefabData,
+    ecs::{
+        Component, DenseVecStorage, Entities, Entity, HashMapStorage, Join, Read, ReadExpect,
+        ReadStorage, System, WriteStorage,
+    },
+    renderer::{Texture, TextureData, TextureMetadata},
+    Error,
+};
+use amethyst_detect_filetype::DetectTextureFormat;
+use bsp::Bsp;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub const LIGHTMAP_SIZE: usize = 128;
+
+/// Lightmap and (if the map was compiled with deluxemapping) light direction pages, attached to
+/// the root entity of the map. Face groups refer to these by index using `LightmapCoords`, so
+/// pages that failed to load are left as `None` rather than shifting the ones after them, as are
+/// external lightmaps until `TextureLoadSystem` sees them loaded.
+pub struct LightmapPages {
+    pub lightmaps: Vec<Option<Handle<Texture>>>,
+    pub deluxemaps: Vec<Option<Handle<Texture>>>,
+    /// External pages still loading, along with whether they are deluxemaps and their index.
+    loading: Vec<(bool, usize, LoadingTexture)>,
+}
+
+impl LightmapPages {
+    pub(crate) fn new<'p, I>(lightmaps: I, deluxemaps: I) -> Self
+    where
+        I: IntoIterator<Item = Option<&'p TexturePage>>,
+    {
+        let mut loading = vec![];
+        let mut handles = |deluxe: bool, pages: I| {
+            pages
+                .into_iter()
+                .enumerate()
+                .map(|(index, page)| match page {
+                    Some(TexturePage::Loading(file)) => {
+                        loading.push((deluxe, index, file.clone()));
+                        None
+                    }
+                    page => page.and_then(TexturePage::handle),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        LightmapPages {
+            lightmaps: handles(false, lightmaps),
+            deluxemaps: handles(true, deluxemaps),
+            loading,
+        }
+    }
+
+    pub(crate) fn poll(&mut self, loader: &Loader, storage: &AssetStorage<Texture>) {
+        let (lightmaps, deluxemaps) = (&mut self.lightmaps, &mut self.deluxemaps);
+        self.loading.retain(|(deluxe, index, file)| {
+            let result = match file.poll(loader, storage) {
+                Some(result) => result,
+                None => return true,
+            };
+
+            match result {
+                Ok(handle) => {
+                    let slot = if *deluxe {
+                        deluxemaps.get_mut(*index)
+                    } else {
+                        lightmaps.get_mut(*index)
+                    };
+                    if let Some(slot) = slot {
+                        *slot = Some(handle);
+                    }
+                }
+                Err(e) => warn!("Failed to load a lightmap page: {}", e),
+            }
+            false
+        });
+    }
+}
+
+impl Component for LightmapPages {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// The lightmap page used by a face group, along with the lightmap texture coordinates of each
+/// vertex in the group's mesh.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct LightmapCoords {
+    pub page: usize,
+    pub tex_coords: Vec<[f32; 2]>,
+}
+
+impl Component for LightmapCoords {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The lightmap page of a face group and its deluxemap, if the map has them, looked up from the
+/// map's `LightmapPages` by `FaceLightmapSystem`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaceLightmap {
+    pub lightmap: Option<Handle<Texture>>,
+    pub deluxemap: Option<Handle<Texture>>,
+}
+
+impl Component for FaceLightmap {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Gives every face group with `LightmapCoords` a `FaceLightmap` once its map's pages are
+/// loaded, and keeps it up to date when the pages are replaced, as `RenderModeSystem` does.
+#[derive(Default)]
+pub struct FaceLightmapSystem;
+
+impl<'a> System<'a> for FaceLightmapSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Parent>,
+        ReadStorage<'a, LightmapPages>,
+        ReadStorage<'a, LightmapCoords>,
+        WriteStorage<'a, FaceLightmap>,
+    );
+
+    fn run(&mut self, (entities, parents, pages, coords, mut bound): Self::SystemData) {
+        for (entity, coords) in (&entities, &coords).join() {
+            // Face groups are parented to their cluster or the map's root.
+            let mut map = parents.get(entity).map(|parent| parent.entity);
+            while let Some(ancestor) = map {
+                if pages.contains(ancestor) {
+                    break;
+                }
+                map = parents.get(ancestor).map(|parent| parent.entity);
+            }
+            let pages = match map.and_then(|map| pages.get(map)) {
+                Some(pages) => pages,
+                None => continue,
+            };
+
+            let page = |pages: &[Option<Handle<Texture>>]| {
+                pages.get(coords.page).and_then(|handle| handle.clone())
+            };
+            let lightmap = FaceLightmap {
+                lightmap: page(&pages.lightmaps),
+                deluxemap: page(&pages.deluxemaps),
+            };
+            if bound.get(entity) != Some(&lightmap) {
+                // `join` only yields live entities, so this can't fail.
+                let _ = bound.insert(entity, lightmap);
+            }
+        }
+    }
+}
+
+/// Baked lighting for each vertex of a face group that has no lightmap, as linear RGBA. This is
+/// used by maps compiled with `-vertex`, or without lighting at all.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct VertexColors {
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl Component for VertexColors {
+    type Storage = DenseVecStorage<Self>;
+}
+
+#[derive(Clone)]
+pub(crate) enum TexturePage {
+    Data(TextureData),
+    File(TextureFile<DetectTextureFormat>),
+    Loading(LoadingTexture),
+    Loaded(Handle<Texture>),
+}
+
+impl TexturePage {
+    pub(crate) fn handle(&self) -> Option<Handle<Texture>> {
+        match self {
+            TexturePage::Loaded(handle) => Some(handle.clone()),
+            _ => None,
+        }
+    }
+
+    /// Start loading the page. External files are tracked by `TextureLoadSystem` rather than
+    /// `progress`, since missing ones fall back to the lightmap lump.
+    pub(crate) fn load(
+        &mut self,
+        progress: &mut ProgressCounter,
+        loader: &Loader,
+        storage: &AssetStorage<Texture>,
+    ) {
+        *self = match self {
+            TexturePage::Data(data) => {
+                TexturePage::Loaded(loader.load_from_data(data.clone(), &mut *progress, storage))
+            }
+            TexturePage::File(file) => TexturePage::Loading(file.load(loader, storage)),
+            TexturePage::Loading(_) | TexturePage::Loaded(_) => return,
+        };
+    }
+}
+
+#[derive(Default)]
+pub struct LightmapPagesPrefab {
+    lightmaps: Vec<TexturePage>,
+    deluxemaps: Vec<TexturePage>,
+    /// Leave the pages to `TextureUploadSystem` instead of loading them with the prefab.
+    staggered: bool,
+}
+
+impl<'a> PrefabData<'a> for LightmapPagesPrefab {
+    type SystemData = (
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Texture>>,
+        WriteStorage<'a, LightmapPages>,
+        WriteStorage<'a, StagedLightmaps>,
+    );
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        (_, _, pages, staged): &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        if self.staggered {
+            staged.insert(
+                entity,
+                StagedLightmaps::new(self.lightmaps.clone(), self.deluxemaps.clone()),
+            )?;
+        } else {
+            pages.insert(
+                entity,
+                LightmapPages::new(
+                    self.lightmaps.iter().map(Some).collect::<Vec<_>>(),
+                    self.deluxemaps.iter().map(Some).collect::<Vec<_>>(),
+                ),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        progress: &mut ProgressCounter,
+        (loader, storage, _, _): &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        if self.staggered {
+            return Ok(false);
+        }
+
+        for page in self.lightmaps.iter_mut().chain(self.deluxemaps.iter_mut()) {
+            page.load(progress, loader, storage);
+        }
+
+        Ok(true)
+    }
+}
+
+/// q3map2 with `-deluxe` interleaves light direction pages between the lightmap pages, so that
+/// lightmap `n` is stored at `2n` and its deluxemap at `2n + 1`. Newer versions of q3map2 mark
+/// this in worldspawn, but for older maps we fall back to the same heuristic as ioquake3.
+pub(crate) fn uses_deluxemaps(bsp: &Bsp, worldspawn: Option<&MapEntity>) -> bool {
+    if let Some(value) = worldspawn.and_then(|w| w.get("deluxeMapping")) {
+        return value.trim() == "1";
+    }
+
+    !bsp.lightmaps.is_empty()
+        && bsp.lightmaps.len() % 2 == 0
+        && bsp
+            .faces
+            .iter()
+            .all(|f| f.lm_index < 0 || f.lm_index % 2 == 0)
+}
+
+/// Brighten a lightmap texel the same way as Quake 3's `R_ColorShiftLightingBytes`, scaling the
+/// whole colour down if any channel would overflow so that bright lights keep their hue.
+pub(crate) fn shift_color(color: [u8; 3], options: &LightingOptions) -> [u8; 3] {
+    let scale = (1 << options.overbright_bits) as f32 * options.intensity;
+
+    let mut out = [0.0f32; 3];
+    for (out, &c) in out.iter_mut().zip(color.iter()) {
+        *out = c as f32 * scale;
+    }
+
+    let max = out.iter().cloned().fold(0.0, f32::max);
+    if max > 255.0 {
+        for c in &mut out {
+            *c *= 255.0 / max;
+        }
+    }
+
+    let mut shifted = [0u8; 3];
+    for (shifted, &c) in shifted.iter_mut().zip(out.iter()) {
+        let c = if options.gamma == 1.0 {
+            c
+        } else {
+            (c / 255.0).powf(1.0 / options.gamma) * 255.0
+        };
+        *shifted = c.round().max(0.0).min(255.0) as u8;
+    }
+
+    shifted
+}
+
+pub(crate) fn page_rgba(lightmap: &bsp::Lightmap, options: &LightingOptions) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(LIGHTMAP_SIZE * LIGHTMAP_SIZE * 4);
+    for &color in lightmap.map.iter().flat_map(|row| row.iter()) {
+        let [r, g, b] = shift_color(color, options);
+        rgba.extend_from_slice(&[r, g, b, 255]);
+    }
+    rgba
+}
+
+/// A vertex colour with the same brightening as the lightmaps, as Quake 3 does for vertex-lit
+/// surfaces.
+pub(crate) fn vertex_color(color: [u8; 4], options: &LightingOptions) -> [f32; 4] {
+    let [r, g, b] = shift_color([color[0], color[1], color[2]], options);
+
+    [
+        f32::from(r) / 255.0,
+        f32::from(g) / 255.0,
+        f32::from(b) / 255.0,
+        f32::from(color[3]) / 255.0,
+    ]
+}
+
+fn page_data(lightmap: &bsp::Lightmap, options: &LightingOptions) -> TextureData {
+    TextureData::U8(
+        page_rgba(lightmap, options),
+        TextureMetadata::unorm().with_size(LIGHTMAP_SIZE as u16, LIGHTMAP_SIZE as u16),
+    )
+}
+
+/// Maps compiled with q3map2's `-external` have an empty lightmap lump, so the number of pages
+/// has to be taken from the faces that reference them instead.
+fn page_count(bsp: &Bsp) -> usize {
+    let count = bsp
+        .faces
+        .iter()
+        .map(|f| f.lm_index + 1)
+        .max()
+        .unwrap_or(0)
+        .max(0) as usize;
+    count.max(bsp.lightmaps.len())
+}
+
+fn external_mode<E: Extension>(options: &ImportOptions<E>) -> Option<(ExternalLightmaps, &str)> {
+    match (options.external_lightmaps, &options.map_name) {
+        (ExternalLightmaps::Never, _) | (_, None) => None,
+        (mode, Some(name)) => Some((mode, name.as_str())),
+    }
+}
+
+fn external_path(map_name: &str, page: usize) -> String {
+    format!("maps/{}/lm_{:04}.tga", map_name, page)
+}
+
+pub(crate) struct LightmapLayout {
+    pub deluxe: bool,
+    /// The styles of each face in face lump order, from the BSPX `LMSTYLE` lump.
+    pub face_styles: Vec<LightStyles>,
+}
+
+impl LightmapLayout {
+    pub fn new(bsp: &Bsp, worldspawn: Option<&MapEntity>) -> Self {
+        LightmapLayout {
+            deluxe: uses_deluxemaps(bsp, worldspawn),
+            face_styles: vec![],
+        }
+    }
+
+    /// Give faces the styles read from the map's `LMSTYLE` lump.
+    pub fn with_face_styles(mut self, extensions: &BspExtensions) -> Self {
+        self.face_styles = extensions.face_styles.clone();
+        self
+    }
+
+    /// The light styles of the face at `face` in the face lump. IBSP faces only carry a single
+    /// lightmap, so faces without styles from `LMSTYLE` get the default, unanimated set.
+    pub fn styles(&self, face: usize) -> LightStyles {
+        self.face_styles.get(face).cloned().unwrap_or_default()
+    }
+
+    /// The page used by a face, or `None` if the face is not lightmapped.
+    pub fn page(&self, face: &bsp::Face) -> Option<usize> {
+        if face.lm_index < 0 {
+            None
+        } else if self.deluxe {
+            Some(face.lm_index as usize / 2)
+        } else {
+            Some(face.lm_index as usize)
+        }
+    }
+
+    /// Whether lightmap pages can be loaded at all. Maps with an empty lightmap lump are only
+    /// lightmapped if they might have external lightmaps, otherwise every face falls back to
+    /// its vertex colours.
+    pub fn has_lightmaps<E: Extension>(&self, bsp: &Bsp, options: &ImportOptions<E>) -> bool {
+        !bsp.lightmaps.is_empty()
+            || (options.external_lightmaps != ExternalLightmaps::Never
+                && options.map_name.is_some())
+    }
+
+    /// Deluxemap pages store directions rather than colours, so they are never adjusted.
+    pub fn lighting(&self, raw_page: usize, options: &LightingOptions) -> LightingOptions {
+        if self.deluxe && raw_page % 2 == 1 {
+            LightingOptions::identity()
+        } else {
+            *options
+        }
+    }
+
+    /// The external lightmap files `prefab` will try to load, including deluxemap pages.
+    pub fn external_paths<E: Extension>(bsp: &Bsp, options: &ImportOptions<E>) -> Vec<String> {
+        match external_mode(options) {
+            Some((_, name)) => (0..page_count(bsp))
+                .map(|i| external_path(name, i))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn prefab<E: Extension>(
+        &self,
+        bsp: &Bsp,
+        options: &ImportOptions<E>,
+    ) -> LightmapPagesPrefab {
+        let count = page_count(bsp);
+        let external = external_mode(options);
+
+        let pages = (0..count).map(|i| {
+            let lighting = self.lighting(i, &options.lighting);
+            let internal = bsp.lightmaps.get(i).map(|lm| page_data(lm, &lighting));
+
+            match external {
+                Some((mode, name)) => {
+                    let fallback: TextureFallback = match (mode, internal) {
+                        (ExternalLightmaps::Auto, Some(internal)) => {
+                            Arc::new(move |_| Ok(internal.clone()))
+                        }
+                        (ExternalLightmaps::Auto, None) => {
+                            Arc::new(|_| Ok(TextureData::Rgba([1.0; 4], TextureMetadata::unorm())))
+                        }
+                        _ => Arc::new(|e| Err(e)),
+                    };
+
+                    TexturePage::File(TextureFile {
+                        path: external_path(name, i),
+                        format: DetectTextureFormat,
+                        metadata: TextureMetadata::unorm(),
+                        fallback,
+                    })
+                }
+                None => TexturePage::Data(
+                    internal
+                        .unwrap_or_else(|| TextureData::Rgba([1.0; 4], TextureMetadata::unorm())),
+                ),
+            }
+        });
+
+        if self.deluxe {
+            let (lightmaps, deluxemaps) = pages
+                .enumerate()
+                .partition::<Vec<_>, _>(|(i, _)| i % 2 == 0);
+
+            LightmapPagesPrefab {
+                lightmaps: lightmaps.into_iter().map(|(_, page)| page).collect(),
+                deluxemaps: deluxemaps.into_iter().map(|(_, page)| page).collect(),
+                staggered: options.staggered_uploads,
+            }
+        } else {
+            LightmapPagesPrefab {
+                lightmaps: pages.collect(),
+                deluxemaps: vec![],
+                staggered: options.staggered_uploads,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overbright_preserves_hue() {
+        let options = LightingOptions::default();
+
+        assert_eq!(shift_color([10, 20, 30], &options), [20, 40, 60]);
+        assert_eq!(shift_color([128, 64, 0], &options), [255, 128, 0]);
+        assert_eq!(
+            shift_color([128, 64, 0], &LightingOptions::identity()),
+            [128, 64, 0]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{lightmap::LightmapPages, terrain::TerrainBlend, TextureFallback};
+use amethyst::{
+    assets::{AssetStorage, Format, Handle, Loader, ProgressCounter},
+    ecs::{Join, Read, ReadExpect, System, WriteStorage},
+    renderer::{Texture, TextureMetadata},
+    Error,
+};
+use std::{io, sync::Arc};
+
+/// A texture file loaded straight through the `Loader`, falling back like
+/// `AssetPrefab::FileOrElse`. Only a prefab's `texture` may borrow the `Handle<Texture>` storage
+/// that `AssetPrefab` needs, so lightmap pages and terrain overlays are loaded with this.
+#[derive(Clone)]
+pub(crate) struct TextureFile<F> {
+    pub path: String,
+    pub format: F,
+    pub metadata: TextureMetadata,
+    pub fallback: TextureFallback,
+}
+
+impl<F> TextureFile<F>
+where
+    F: Format<Texture, Options = TextureMetadata> + Clone,
+{
+    pub fn load(&self, loader: &Loader, storage: &AssetStorage<Texture>) -> LoadingTexture {
+        // Tracked on its own rather than by the prefab's counter, so that a missing file falls
+        // back instead of failing the whole map.
+        let mut progress = ProgressCounter::new();
+        let handle = loader.load(
+            self.path.clone(),
+            self.format.clone(),
+            self.metadata.clone(),
+            &mut progress,
+            storage,
+        );
+
+        LoadingTexture {
+            handle,
+            progress: Arc::new(progress),
+            path: self.path.clone(),
+            fallback: self.fallback.clone(),
+        }
+    }
+}
+
+/// A `TextureFile` that has started loading.
+#[derive(Clone)]
+pub(crate) struct LoadingTexture {
+    handle: Handle<Texture>,
+    progress: Arc<ProgressCounter>,
+    path: String,
+    fallback: TextureFallback,
+}
+
+impl LoadingTexture {
+    /// The handle to use once the file has loaded, or the fallback's if it failed to. `None`
+    /// while the file is still loading.
+    pub fn poll(
+        &self,
+        loader: &Loader,
+        storage: &AssetStorage<Texture>,
+    ) -> Option<Result<Handle<Texture>, Error>> {
+        if self.progress.num_failed() > 0 {
+            let e = io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to load `{}`", self.path),
+            );
+            Some(
+                (self.fallback)(Error::new(e)).map(|data| loader.load_from_data(data, (), storage)),
+            )
+        } else if self.progress.is_complete() {
+            Some(Ok(self.handle.clone()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Fills in the handles of external lightmap pages and terrain overlays once their files have
+/// loaded, or once their fallbacks have if they are missing. Until then `LightmapPages` has
+/// `None` for those pages and `TerrainBlend` has no overlay.
+#[derive(Default)]
+pub struct TextureLoadSystem;
+
+impl<'a> System<'a> for TextureLoadSystem {
+    type SystemData = (
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Texture>>,
+        WriteStorage<'a, LightmapPages>,
+        WriteStorage<'a, TerrainBlend>,
+    );
+
+    fn run(&mut self, (loader, storage, mut pages, mut blends): Self::SystemData) {
+        for pages in (&mut pages).join() {
+            pages.poll(&loader, &storage);
+        }
+        for blend in (&mut blends).join() {
+            blend.poll(&loader, &storage);
+        }
+    }
+}
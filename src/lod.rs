@@ -0,0 +1,374 @@
+use crate::{
+    geometry::{cross, dot},
+    mesh::FaceGroup,
+    vis,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::GlobalTransform,
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entities, Entity, Join, ReadStorage, System, WriteStorage},
+    renderer::{Camera, Hidden},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::HashMap};
+
+/// Position, normal, texture coordinate, lightmap coordinate and colour.
+type Vertex = [f32; 14];
+
+/// One simplified level of detail to generate for every face group.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodOptions {
+    /// How far from the camera this level replaces the previous one, after `ImportOptions::scale`.
+    pub distance: f32,
+    /// The fraction of the full-detail triangles to keep, such as `0.5`. Edges on the border of
+    /// a group and along texture seams are never collapsed, so groups can keep more than this.
+    pub ratio: f32,
+}
+
+/// Which of the levels of detail of a face group an entity is, and the distances from the
+/// camera it is shown at. Level `0` is the full-detail mesh. The bounds are those of the full
+/// detail mesh, in the space of the entity's `GlobalTransform` or of the map if it has none.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct LodLevel {
+    pub level: usize,
+    pub near: f32,
+    /// `None` for the coarsest level, which is shown however far away the camera is.
+    pub far: Option<f32>,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+impl Component for LodLevel {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl LodLevel {
+    /// Whether this level should be drawn with the camera at `point`.
+    pub fn shown_from(&self, point: [f32; 3]) -> bool {
+        let mut sum = 0.0;
+        for i in 0..3 {
+            let d = (self.mins[i] - point[i])
+                .max(point[i] - self.maxs[i])
+                .max(0.0);
+            sum += d * d;
+        }
+        let distance = sum.sqrt();
+
+        distance >= self.near && self.far.map_or(true, |far| distance < far)
+    }
+}
+
+fn position(v: &Vertex) -> [f32; 3] {
+    [v[0], v[1], v[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn normal(vertices: &[Vertex], triangle: [usize; 3]) -> [f32; 3] {
+    let [a, b, c] = [
+        position(&vertices[triangle[0]]),
+        position(&vertices[triangle[1]]),
+        position(&vertices[triangle[2]]),
+    ];
+    cross(sub(b, a), sub(c, a))
+}
+
+fn bits(v: &Vertex) -> [u32; 14] {
+    let mut out = [0; 14];
+    for (o, v) in out.iter_mut().zip(v.iter()) {
+        *o = v.to_bits();
+    }
+    out
+}
+
+/// A copy of `group` with about `ratio` of its triangles, made by collapsing its shortest edges
+/// first. Vertices on the border of the group, or where its texture or lighting is split, are
+/// locked in place so neighbouring groups don't crack and textures don't swim. Returns `None`
+/// if no triangles could be removed.
+pub(crate) fn simplify(group: &FaceGroup, ratio: f32) -> Option<FaceGroup> {
+    let mut vertices: Vec<Vertex> = vec![];
+    let mut welded = HashMap::new();
+    let mut triangles = vec![];
+
+    for i in (0..group.vertex_count() / 3).map(|i| i * 3) {
+        let mut triangle = [0; 3];
+        for (corner, j) in triangle.iter_mut().zip(i..i + 3) {
+            let mut v = [0.0; 14];
+            v[0..3].copy_from_slice(&group.positions[j]);
+            v[3..6].copy_from_slice(&group.normals[j]);
+            v[6..8].copy_from_slice(&group.tex_coords[j]);
+            v[8..10].copy_from_slice(&group.lightmap_coords[j]);
+            v[10..14].copy_from_slice(&group.colors[j]);
+
+            *corner = *welded.entry(bits(&v)).or_insert_with(|| {
+                vertices.push(v);
+                vertices.len() - 1
+            });
+        }
+        triangles.push(Some(triangle));
+    }
+
+    let original = triangles.len();
+    let target = ((original as f32 * ratio).ceil() as usize).max(1);
+
+    // Edges used by anything but two triangles are on a border or a seam, since the vertices on
+    // either side of a seam weren't welded.
+    let mut edge_uses = HashMap::<(usize, usize), usize>::new();
+    for triangle in triangles.iter().flatten() {
+        for k in 0..3 {
+            let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+            *edge_uses.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    let mut locked = vec![false; vertices.len()];
+    for (&(a, b), &uses) in &edge_uses {
+        if uses != 2 {
+            locked[a] = true;
+            locked[b] = true;
+        }
+    }
+
+    let mut live = original;
+    while live > target {
+        let mut around = vec![vec![]; vertices.len()];
+        let mut edges = vec![];
+        for (t, triangle) in triangles.iter().enumerate() {
+            if let Some(triangle) = triangle {
+                for k in 0..3 {
+                    let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+                    around[a].push(t);
+                    let d = sub(position(&vertices[a]), position(&vertices[b]));
+                    edges.push((dot(d, d), a.min(b), a.max(b)));
+                }
+            }
+        }
+        edges.sort_by(|x, y| {
+            x.0.partial_cmp(&y.0)
+                .unwrap_or(Ordering::Equal)
+                .then((x.1, x.2).cmp(&(y.1, y.2)))
+        });
+        edges.dedup_by_key(|&mut (_, a, b)| (a, b));
+
+        // Triangles only change once per pass, so `around` stays correct.
+        let mut touched = vec![false; vertices.len()];
+        let before = live;
+
+        for &(_, a, b) in &edges {
+            if live <= target {
+                break;
+            }
+            if touched[a] || touched[b] {
+                continue;
+            }
+
+            let collapse = [(a, b), (b, a)].iter().cloned().find(|&(keep, remove)| {
+                !locked[remove]
+                    && around[remove].iter().all(|&t| {
+                        let old = match triangles[t] {
+                            Some(triangle) => triangle,
+                            None => return true,
+                        };
+                        if old.contains(&keep) {
+                            return true;
+                        }
+                        let mut new = old;
+                        for corner in new.iter_mut().filter(|c| **c == remove) {
+                            *corner = keep;
+                        }
+                        dot(normal(&vertices, old), normal(&vertices, new)) > 0.0
+                    })
+            });
+
+            if let Some((keep, remove)) = collapse {
+                for &t in &around[remove] {
+                    if let Some(mut triangle) = triangles[t] {
+                        for &corner in &triangle {
+                            touched[corner] = true;
+                        }
+                        if triangle.contains(&keep) {
+                            triangles[t] = None;
+                            live -= 1;
+                        } else {
+                            for corner in triangle.iter_mut().filter(|c| **c == remove) {
+                                *corner = keep;
+                            }
+                            triangles[t] = Some(triangle);
+                        }
+                    }
+                }
+            }
+        }
+
+        if live == before {
+            break;
+        }
+    }
+
+    if live == original {
+        return None;
+    }
+
+    let mut out = FaceGroup {
+        positions: vec![],
+        normals: vec![],
+        tex_coords: vec![],
+        lightmap_coords: vec![],
+        colors: vec![],
+        texture_name: group.texture_name.clone(),
+        ..*group
+    };
+    for &corner in triangles
+        .iter()
+        .flatten()
+        .flat_map(|triangle| triangle.iter())
+    {
+        let v = &vertices[corner];
+        out.positions.push([v[0], v[1], v[2]]);
+        out.normals.push([v[3], v[4], v[5]]);
+        out.tex_coords.push([v[6], v[7]]);
+        out.lightmap_coords.push([v[8], v[9]]);
+        out.colors.push([v[10], v[11], v[12], v[13]]);
+    }
+
+    Some(out)
+}
+
+/// The `LodLevel` of the full-detail group, and the simplified group and `LodLevel` for each
+/// of `levels` that removed any triangles. Returns `None` if none did.
+pub(crate) fn generate(
+    group: &FaceGroup,
+    levels: &[LodOptions],
+) -> Option<(LodLevel, Vec<(FaceGroup, LodLevel)>)> {
+    let (mins, maxs) = group.bounds()?;
+
+    let simplified = levels
+        .iter()
+        .filter_map(|options| Some((simplify(group, options.ratio)?, options.distance)))
+        .collect::<Vec<_>>();
+    let first = simplified.first()?.1;
+
+    let fars = simplified
+        .iter()
+        .skip(1)
+        .map(|&(_, distance)| Some(distance))
+        .chain(Some(None))
+        .collect::<Vec<_>>();
+    let lods = simplified
+        .into_iter()
+        .zip(fars)
+        .enumerate()
+        .map(|(i, ((group, near), far))| {
+            let level = LodLevel {
+                level: i + 1,
+                near,
+                far,
+                mins,
+                maxs,
+            };
+            (group, level)
+        })
+        .collect();
+
+    let full = LodLevel {
+        level: 0,
+        near: 0.0,
+        far: Some(first),
+        mins,
+        maxs,
+    };
+
+    Some((full, lods))
+}
+
+/// Shows the level of detail of each face group whose distance range contains the first
+/// camera, hiding the rest. Like `DetailCullingSystem`, this uses `Hidden`; detail surfaces are
+/// never given levels of detail, so the two don't fight.
+#[derive(Default)]
+pub struct LodSystem;
+
+impl<'a> System<'a> for LodSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, LodLevel>,
+        WriteStorage<'a, Hidden>,
+    );
+
+    fn run(&mut self, (entities, cameras, globals, lods, mut hidden): Self::SystemData) {
+        let camera = match (&cameras, &globals).join().next() {
+            Some((_, global)) => [global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]],
+            None => return,
+        };
+
+        for (entity, lod, global) in (&entities, &lods, globals.maybe()).join() {
+            if lod.shown_from(vis::to_map_space(global, camera)) {
+                hidden.remove(entity);
+            } else if !hidden.contains(entity) {
+                // Inserting can only fail for dead entities, which `join` never yields.
+                let _ = hidden.insert(entity, Hidden);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LightStyles;
+
+    #[test]
+    fn simplifies_the_inside_of_a_grid() {
+        let mut group = FaceGroup {
+            model: 0,
+            cluster: None,
+            texture: 0,
+            texture_name: "textures/base/floor".to_string(),
+            lightmap_page: None,
+            styles: LightStyles::default(),
+            face_count: 1,
+            positions: vec![],
+            normals: vec![],
+            tex_coords: vec![],
+            lightmap_coords: vec![],
+            colors: vec![],
+        };
+
+        // A 4x4 grid of quads on a floor, with texture coordinates following the position.
+        for x in 0..4 {
+            for z in 0..4 {
+                let (x, z) = (x as f32, z as f32);
+                let quad = [
+                    [x, z],
+                    [x + 1.0, z],
+                    [x + 1.0, z + 1.0],
+                    [x, z],
+                    [x + 1.0, z + 1.0],
+                    [x, z + 1.0],
+                ];
+                for &[x, z] in &quad {
+                    group.positions.push([x, 0.0, -z]);
+                    group.normals.push([0.0, 1.0, 0.0]);
+                    group.tex_coords.push([x, z]);
+                    group.lightmap_coords.push([0.0, 0.0]);
+                    group.colors.push([1.0; 4]);
+                }
+            }
+        }
+
+        let simplified = simplify(&group, 0.5).unwrap();
+
+        assert!(simplified.vertex_count() < group.vertex_count());
+        assert_eq!(simplified.bounds(), group.bounds());
+        assert!(simplified
+            .tex_coords
+            .iter()
+            .zip(&simplified.positions)
+            .all(|(t, p)| *t == [p[0], -p[2]]));
+    }
+}
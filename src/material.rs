@@ -0,0 +1,78 @@
+use crate::shader::Shader;
+use amethyst::renderer::{MaterialPrefab, TextureMetadata, TexturePrefab};
+use amethyst_detect_filetype::DetectTextureFormat;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Paths to the textures making up a PBR material. Any that are missing use amethyst's material
+/// defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MaterialDescription {
+    pub albedo: Option<String>,
+    pub normal: Option<String>,
+    pub roughness: Option<String>,
+    pub metallic: Option<String>,
+    pub emission: Option<String>,
+    pub ambient_occlusion: Option<String>,
+}
+
+impl MaterialDescription {
+    pub(crate) fn prefab(&self) -> MaterialPrefab<DetectTextureFormat> {
+        let texture = |path: &Option<String>, metadata: fn() -> TextureMetadata| {
+            path.as_ref()
+                .map(|path| TexturePrefab::File(path.clone(), DetectTextureFormat, metadata()))
+        };
+
+        MaterialPrefab {
+            albedo: texture(&self.albedo, TextureMetadata::srgb),
+            normal: texture(&self.normal, TextureMetadata::unorm),
+            roughness: texture(&self.roughness, TextureMetadata::unorm),
+            metallic: texture(&self.metallic, TextureMetadata::unorm),
+            emission: texture(&self.emission, TextureMetadata::srgb),
+            ambient_occlusion: texture(&self.ambient_occlusion, TextureMetadata::unorm),
+            ..Default::default()
+        }
+    }
+
+    /// Every texture path this material loads.
+    pub(crate) fn paths(&self) -> impl Iterator<Item = &str> {
+        vec![
+            &self.albedo,
+            &self.normal,
+            &self.roughness,
+            &self.metallic,
+            &self.emission,
+            &self.ambient_occlusion,
+        ]
+        .into_iter()
+        .filter_map(|path| path.as_ref().map(String::as_str))
+    }
+}
+
+/// Materials to use in place of the plain diffuse texture, looked up by texture name and then by
+/// the `surfaceparm`s of the texture's shader.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MaterialMap {
+    pub textures: HashMap<String, MaterialDescription>,
+    pub surfaceparms: HashMap<String, MaterialDescription>,
+}
+
+impl MaterialMap {
+    pub fn from_ron(source: &str) -> Result<Self, ron::de::Error> {
+        ron::de::from_str(source)
+    }
+
+    pub fn get(&self, texture_name: &str, shader: Option<&Shader>) -> Option<&MaterialDescription> {
+        self.textures
+            .get(texture_name)
+            .or_else(|| self.textures.get(&texture_name.to_lowercase()))
+            .or_else(|| {
+                shader?
+                    .surfaceparms()
+                    .filter_map(|parm| self.surfaceparms.get(&parm.to_lowercase()))
+                    .next()
+            })
+    }
+}
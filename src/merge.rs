@@ -0,0 +1,301 @@
+use crate::{
+    geometry::{cross, dot},
+    mesh::FaceGroup,
+};
+use std::collections::HashMap;
+
+const EPSILON: f32 = 0.001;
+
+/// Position, normal, texture coordinate, lightmap coordinate and colour.
+type Vertex = [f32; 14];
+
+fn position(v: &Vertex) -> [f32; 3] {
+    [v[0], v[1], v[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn bits(v: &Vertex) -> [u32; 14] {
+    let mut out = [0; 14];
+    for (o, v) in out.iter_mut().zip(v.iter()) {
+        *o = v.to_bits();
+    }
+    out
+}
+
+/// A convex polygon, wound the same way as the triangles it was merged from.
+struct Polygon {
+    vertices: Vec<Vertex>,
+    normal: [f32; 3],
+    dist: f32,
+}
+
+impl Polygon {
+    fn triangle(vertices: Vec<Vertex>) -> Option<Self> {
+        let [a, b, c] = [
+            position(&vertices[0]),
+            position(&vertices[1]),
+            position(&vertices[2]),
+        ];
+        let normal = cross(sub(b, a), sub(c, a));
+        let len = dot(normal, normal).sqrt();
+        if len < EPSILON {
+            return None;
+        }
+        let normal = [normal[0] / len, normal[1] / len, normal[2] / len];
+
+        Some(Polygon {
+            vertices,
+            normal,
+            dist: dot(normal, a),
+        })
+    }
+
+    fn coplanar(&self, other: &Polygon) -> bool {
+        dot(self.normal, other.normal) > 1.0 - EPSILON && (self.dist - other.dist).abs() < EPSILON
+    }
+
+    /// Whether every attribute of `other` is the same affine function of position as it is on
+    /// this polygon, so that the two can be drawn as one without changing how they look. The
+    /// attributes are extrapolated from the largest triangle on this polygon's first edge, since
+    /// merged polygons can have runs of collinear vertices.
+    fn same_attributes(&self, other: &Polygon) -> bool {
+        let [a, b] = [&self.vertices[0], &self.vertices[1]];
+        let e1 = sub(position(b), position(a));
+        let area = |v: &Vertex| {
+            let n = cross(e1, sub(position(v), position(a)));
+            dot(n, n)
+        };
+        let c = self.vertices[2..]
+            .iter()
+            .max_by(|x, y| {
+                area(x)
+                    .partial_cmp(&area(y))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(b);
+        let e2 = sub(position(c), position(a));
+        let (d00, d01, d11) = (dot(e1, e1), dot(e1, e2), dot(e2, e2));
+        let denom = d00 * d11 - d01 * d01;
+
+        other.vertices.iter().all(|v| {
+            let p = sub(position(v), position(a));
+            let (d20, d21) = (dot(p, e1), dot(p, e2));
+            let s = (d11 * d20 - d01 * d21) / denom;
+            let t = (d00 * d21 - d01 * d20) / denom;
+
+            (3..14).all(|i| {
+                let expected = a[i] + s * (b[i] - a[i]) + t * (c[i] - a[i]);
+                (expected - v[i]).abs() < EPSILON
+            })
+        })
+    }
+
+    fn is_convex(&self) -> bool {
+        let n = self.vertices.len();
+        (0..n).all(|i| {
+            let [a, b, c] = [
+                position(&self.vertices[i]),
+                position(&self.vertices[(i + 1) % n]),
+                position(&self.vertices[(i + 2) % n]),
+            ];
+            dot(cross(sub(b, a), sub(c, b)), self.normal) > -EPSILON
+        })
+    }
+
+    fn edges(&self) -> impl Iterator<Item = ([u32; 14], [u32; 14])> + '_ {
+        let n = self.vertices.len();
+        (0..n).map(move |i| (bits(&self.vertices[i]), bits(&self.vertices[(i + 1) % n])))
+    }
+
+    /// This polygon joined to `other` along the edge running from `from` to `to` on this
+    /// polygon, and the other way on `other`, if the result is convex.
+    fn join(&self, other: &Polygon, from: [u32; 14], to: [u32; 14]) -> Option<Polygon> {
+        let start = |polygon: &Polygon, first: [u32; 14]| {
+            let i = polygon.vertices.iter().position(|v| bits(v) == first)?;
+            let mut out = polygon.vertices.clone();
+            out.rotate_left(i);
+            Some(out)
+        };
+
+        // This polygon from `to` round to `from`, then the rest of `other` between them.
+        let mut vertices = start(self, to)?;
+        let theirs = start(other, from)?;
+        vertices.extend_from_slice(&theirs[1..theirs.len() - 1]);
+
+        let joined = Polygon {
+            vertices,
+            normal: self.normal,
+            dist: self.dist,
+        };
+
+        if joined.is_convex() {
+            Some(joined)
+        } else {
+            None
+        }
+    }
+}
+
+/// Merge the triangles of a face group that lie on the same plane and share an edge into convex
+/// polygons, then triangulate those again as fans, skipping triangles with no area. Triangles
+/// are only merged where every vertex attribute is continuous across the shared edge, and no
+/// vertices are removed, so this never opens cracks against neighbouring faces.
+pub(crate) fn merge_coplanar(group: &mut FaceGroup) {
+    let vertex = |i: usize| {
+        let mut v = [0.0; 14];
+        v[0..3].copy_from_slice(&group.positions[i]);
+        v[3..6].copy_from_slice(&group.normals[i]);
+        v[6..8].copy_from_slice(&group.tex_coords[i]);
+        v[8..10].copy_from_slice(&group.lightmap_coords[i]);
+        v[10..14].copy_from_slice(&group.colors[i]);
+        v
+    };
+
+    let mut degenerate = vec![];
+    let mut polygons = vec![];
+    for i in (0..group.vertex_count() / 3).map(|i| i * 3) {
+        let triangle = vec![vertex(i), vertex(i + 1), vertex(i + 2)];
+        match Polygon::triangle(triangle.clone()) {
+            Some(polygon) => polygons.push(polygon),
+            None => degenerate.extend(triangle),
+        }
+    }
+
+    loop {
+        let edges = polygons
+            .iter()
+            .enumerate()
+            .flat_map(|(i, polygon)| polygon.edges().map(move |edge| (edge, i)))
+            .collect::<HashMap<_, _>>();
+
+        let mut merged = vec![false; polygons.len()];
+        let mut out = vec![];
+
+        for i in 0..polygons.len() {
+            if merged[i] {
+                continue;
+            }
+
+            let joined = polygons[i].edges().find_map(|(from, to)| {
+                let j = *edges.get(&(to, from))?;
+                if j == i || merged[j] {
+                    return None;
+                }
+
+                let (a, b) = (&polygons[i], &polygons[j]);
+                if !a.coplanar(b) || !a.same_attributes(b) {
+                    return None;
+                }
+
+                a.join(b, from, to).map(|joined| (j, joined))
+            });
+
+            if let Some((j, joined)) = joined {
+                merged[i] = true;
+                merged[j] = true;
+                out.push(joined);
+            }
+        }
+
+        if out.is_empty() {
+            break;
+        }
+
+        out.extend(
+            polygons
+                .into_iter()
+                .zip(merged)
+                .filter(|(_, merged)| !merged)
+                .map(|(polygon, _)| polygon),
+        );
+        polygons = out;
+    }
+
+    let mut vertices = degenerate;
+    for polygon in &polygons {
+        let first = &polygon.vertices[0];
+        for pair in polygon.vertices[1..].windows(2) {
+            let area = cross(
+                sub(position(&pair[0]), position(first)),
+                sub(position(&pair[1]), position(first)),
+            );
+            if dot(area, area).sqrt() >= EPSILON {
+                vertices.extend_from_slice(&[*first, pair[0], pair[1]]);
+            }
+        }
+    }
+
+    group.positions = vertices.iter().map(|v| [v[0], v[1], v[2]]).collect();
+    group.normals = vertices.iter().map(|v| [v[3], v[4], v[5]]).collect();
+    group.tex_coords = vertices.iter().map(|v| [v[6], v[7]]).collect();
+    group.lightmap_coords = vertices.iter().map(|v| [v[8], v[9]]).collect();
+    group.colors = vertices
+        .iter()
+        .map(|v| [v[10], v[11], v[12], v[13]])
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LightStyles;
+
+    #[test]
+    fn merges_a_strip_of_quads() {
+        let mut group = FaceGroup {
+            model: 0,
+            cluster: None,
+            texture: 0,
+            texture_name: "textures/base/floor".to_string(),
+            lightmap_page: None,
+            styles: LightStyles::default(),
+            face_count: 2,
+            positions: vec![],
+            normals: vec![],
+            tex_coords: vec![],
+            lightmap_coords: vec![],
+            colors: vec![],
+        };
+
+        // Two unit quads side by side, the second with a different diagonal.
+        let quads = [
+            [
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 1.0],
+            ],
+            [
+                [1.0, 0.0],
+                [2.0, 0.0],
+                [2.0, 1.0],
+                [1.0, 0.0],
+                [2.0, 1.0],
+                [1.0, 1.0],
+            ],
+        ];
+        for &[x, z] in quads.iter().flat_map(|quad| quad.iter()) {
+            group.positions.push([x, 0.0, -z]);
+            group.normals.push([0.0, 1.0, 0.0]);
+            group.tex_coords.push([x, z]);
+            group.lightmap_coords.push([0.0, 0.0]);
+            group.colors.push([1.0; 4]);
+        }
+
+        merge_coplanar(&mut group);
+
+        // The strip's six vertices make a convex polygon, where the two on its long edges
+        // only give one triangle with no area.
+        assert_eq!(group.vertex_count(), 3 * 3);
+        assert!(group
+            .tex_coords
+            .iter()
+            .zip(&group.positions)
+            .all(|(t, p)| *t == [p[0], -p[2]]));
+    }
+}
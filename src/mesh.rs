@@ -0,0 +1,289 @@
+use crate::{
+    brushes::range,
+    bspx::BspExtensions,
+    flags, geometry,
+    lightmap::{vertex_color, LightmapLayout},
+    merge,
+    options::{FaceInfo, ImportOptions},
+    points_to_world_space,
+    scale::Scale,
+    sort::RenderOrder,
+    Extension, LightStyles,
+};
+use amethyst::renderer::PosNormTex;
+use bsp::Bsp;
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+/// The faces of a cluster or model sharing a texture, lightmap page and light styles, converted
+/// to world space and scaled by `ImportOptions::scale`. This is independent of the renderer, and is what the prefab's meshes are
+/// built from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaceGroup {
+    /// The index of the model the faces belong to, where `0` is the world.
+    pub model: usize,
+    /// The cluster the faces belong to, for faces of the world model.
+    pub cluster: Option<i32>,
+    pub texture: usize,
+    pub texture_name: String,
+    pub lightmap_page: Option<usize>,
+    pub styles: LightStyles,
+    /// The number of BSP faces merged into this group.
+    pub face_count: usize,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub lightmap_coords: Vec<[f32; 2]>,
+    /// Vertex colours, brightened the same way as lightmaps. Renderers should use these to light
+    /// groups without a `lightmap_page`.
+    pub colors: Vec<[f32; 4]>,
+}
+
+impl FaceGroup {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// The bounds of the group's vertices, as `(mins, maxs)`.
+    pub fn bounds(&self) -> Option<([f32; 3], [f32; 3])> {
+        geometry::slice_bounds(&self.positions)
+    }
+
+    pub(crate) fn translate(&mut self, offset: [f32; 3]) {
+        for position in &mut self.positions {
+            for i in 0..3 {
+                position[i] += offset[i];
+            }
+        }
+    }
+
+    /// Swap the last two vertices of every triangle.
+    fn reverse_winding(&mut self) {
+        fn reverse<T>(vertices: &mut [T]) {
+            for triangle in vertices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        reverse(&mut self.positions);
+        reverse(&mut self.normals);
+        reverse(&mut self.tex_coords);
+        reverse(&mut self.lightmap_coords);
+        reverse(&mut self.colors);
+    }
+
+    pub(crate) fn pos_norm_tex(&self) -> Vec<PosNormTex> {
+        self.positions
+            .iter()
+            .zip(&self.normals)
+            .zip(&self.tex_coords)
+            .map(|((&position, &normal), &tex_coord)| PosNormTex {
+                position: position.into(),
+                normal: normal.into(),
+                tex_coord: tex_coord.into(),
+            })
+            .collect()
+    }
+}
+
+/// A face with its index in the face lump, which orders faces and looks up their `LMSTYLE`
+/// styles.
+pub(crate) type IndexedFace<'a> = (usize, bsp::Handle<'a, bsp::Face>);
+
+/// The faces of `leaf`. Leaf faces pointing past the face lump are skipped.
+pub(crate) fn leaf_faces<'a>(
+    bsp: &'a Bsp,
+    leaf: &bsp::Leaf,
+) -> impl Iterator<Item = IndexedFace<'a>> + 'a {
+    range(&bsp.leaf_faces, leaf.leaf_face, leaf.n_leaf_faces)
+        .iter()
+        .filter_map(move |leaf_face| {
+            let index = leaf_face.face as usize;
+            bsp.faces
+                .get(index)
+                .map(|face| (index, bsp::Handle::new(bsp, face)))
+        })
+}
+
+/// The faces of `model`.
+pub(crate) fn model_faces<'a>(
+    bsp: &'a Bsp,
+    model: &bsp::Model,
+) -> impl Iterator<Item = IndexedFace<'a>> + 'a {
+    let start = model.face.max(0) as usize;
+    range(&bsp.faces, model.face, model.n_faces)
+        .iter()
+        .enumerate()
+        .map(move |(i, face)| (start + i, bsp::Handle::new(bsp, face)))
+}
+
+pub(crate) fn group_faces<'a, E: Extension>(
+    bsp: &'a Bsp,
+    options: &ImportOptions<E>,
+    lightmaps: &LightmapLayout,
+    model: usize,
+    cluster: Option<i32>,
+    faces: &mut Vec<IndexedFace<'a>>,
+) -> Vec<FaceGroup> {
+    if let Some(filter) = &options.face_filter {
+        faces.retain(|(_, face)| {
+            let texture = match face.texture() {
+                Some(texture) => texture,
+                None => return true,
+            };
+
+            filter(&FaceInfo {
+                texture_name: &texture.name,
+                surface_flags: flags::surface_flags(texture),
+                contents: flags::contents(texture),
+                model,
+                face,
+            })
+        });
+    }
+
+    // A Quake 3 "texture" is really a shader, so the texture index already separates faces that
+    // need different shaders or surface flags even if they share an image. Sorting by name first
+    // keeps groups in the same order however the compiler laid out the texture lump, and faces
+    // are then sorted by their position in the face lump, which also puts together the duplicates
+    // that appear when a face spans several leaves of the same cluster.
+    let group_key = |&(index, ref face): &IndexedFace| {
+        (face.texture, lightmaps.page(face), lightmaps.styles(index))
+    };
+
+    faces.sort_by_key(|face| (face.1.texture().map(|t| t.name), group_key(face), face.0));
+    faces.dedup_by_key(|&mut (index, _)| index);
+
+    let has_lightmaps = lightmaps.has_lightmaps(bsp, options);
+    let mut out = vec![];
+
+    for ((tex, lightmap_page, styles), faces) in &faces.iter().group_by(|face| group_key(face)) {
+        let texture = if let Some(texture) = bsp.texture(tex as usize) {
+            texture
+        } else {
+            continue;
+        };
+        if !texture.flags.should_draw() || options.is_stripped(&texture.name) {
+            continue;
+        }
+
+        let mut group = FaceGroup {
+            model,
+            cluster,
+            texture: tex as usize,
+            texture_name: texture.name.to_string(),
+            lightmap_page: lightmap_page.filter(|_| has_lightmaps),
+            styles,
+            face_count: 0,
+            positions: vec![],
+            normals: vec![],
+            tex_coords: vec![],
+            lightmap_coords: vec![],
+            colors: vec![],
+        };
+
+        let faces = faces.map(|(_, face)| face).collect::<Vec<_>>();
+        group.face_count = faces.len();
+
+        let capacity = faces.iter().map(|face| face.vertices().size_hint().0).sum();
+        group.positions.reserve_exact(capacity);
+        group.normals.reserve_exact(capacity);
+        group.tex_coords.reserve_exact(capacity);
+        group.lightmap_coords.reserve_exact(capacity);
+        group.colors.reserve_exact(capacity);
+
+        for vert in faces.iter().flat_map(|face| face.vertices()) {
+            group.positions.push(vert.position);
+            group.normals.push(vert.normal);
+            group.tex_coords.push(vert.surface_texcoord);
+            group.lightmap_coords.push(vert.lightmap_texcoord);
+            group
+                .colors
+                .push(vertex_color(vert.color, &options.lighting));
+        }
+        // Converted a buffer at a time rather than as each vertex is read, so the loop can be
+        // vectorised.
+        points_to_world_space(&mut group.positions);
+        points_to_world_space(&mut group.normals);
+
+        if options.merge_coplanar {
+            merge::merge_coplanar(&mut group);
+        }
+        if options.reverse_winding {
+            group.reverse_winding();
+        }
+        // After merging, so its tolerances stay in map units.
+        group.positions.scale(options.scale);
+
+        out.push(group);
+    }
+
+    // Stable, so groups with the same order stay sorted by name.
+    out.sort_by(|a, b| {
+        let order =
+            |group: &FaceGroup| RenderOrder::from_shader(options.shader(&group.texture_name));
+        order(a)
+            .partial_cmp(&order(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    out
+}
+
+/// The leaves of each cluster, in order of cluster and then of the leaf lump. Leaves of a cluster
+/// aren't always next to each other in the lump, so this also keeps a cluster from being split.
+/// Solid and opaque leaves, with a negative cluster, aren't part of any cluster and are left out.
+pub(crate) fn cluster_leaves(bsp: &Bsp) -> BTreeMap<i32, Vec<&bsp::Leaf>> {
+    let mut out = BTreeMap::<_, Vec<_>>::new();
+    for leaf in bsp.leaves.iter().filter(|leaf| leaf.cluster >= 0) {
+        out.entry(leaf.cluster).or_default().push(leaf);
+    }
+    out
+}
+
+/// Group the faces of a map the same way as the prefab importer does, for use with other
+/// renderers or tools. `extensions` are the map's BSPX lumps, as read by `BspExtensions::read`.
+/// The models and clusters of `ImportOptions::selection` are respected, but its bounds are not.
+pub fn face_groups<E: Extension>(
+    bsp: &Bsp,
+    extensions: &BspExtensions,
+    options: &ImportOptions<E>,
+) -> Vec<FaceGroup> {
+    let entities = crate::entities::parse_entities(crate::entities::entity_string(bsp));
+    let lightmaps = LightmapLayout::new(bsp, crate::entities::worldspawn(&entities))
+        .with_face_styles(extensions);
+
+    let mut out = vec![];
+    let mut faces = vec![];
+
+    for (id, leaves) in cluster_leaves(bsp) {
+        if !options.selection.includes_cluster(id) {
+            continue;
+        }
+
+        faces.clear();
+        faces.extend(leaves.into_iter().flat_map(|leaf| leaf_faces(bsp, leaf)));
+
+        out.extend(group_faces(
+            bsp,
+            options,
+            &lightmaps,
+            0,
+            Some(id),
+            &mut faces,
+        ));
+    }
+
+    for (i, model) in bsp.models().enumerate().skip(1) {
+        if !options.selection.includes_model(i) {
+            continue;
+        }
+
+        faces.clear();
+        faces.extend(model_faces(bsp, &model));
+
+        out.extend(group_faces(bsp, options, &lightmaps, i, None, &mut faces));
+    }
+
+    out
+}
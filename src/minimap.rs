@@ -0,0 +1,298 @@
+use crate::{
+    flags::{self, SURF_NODRAW, SURF_SKY},
+    geometry::dot,
+    to_world_space,
+};
+use amethyst::renderer::{TextureData, TextureMetadata};
+use bsp::Bsp;
+use std::collections::HashMap;
+
+/// Faces flatter than this, as the cosine of their angle to the vertical, are floors.
+const MIN_FLOOR_FACING: f32 = 0.7;
+
+/// Outline vertices closer than this many units are treated as the same point.
+const WELD_GRID: f32 = 0.125;
+
+const FLOOR_COLOR: [u8; 4] = [96, 96, 96, 255];
+const OUTLINE_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+/// The floors of one storey of a map seen from above, for automaps and minimaps. Points are the
+/// world space `[x, z]` of the floors, so `-z` is the map's north.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MinimapSlice {
+    /// The height of the lowest floor in the slice.
+    pub floor: f32,
+    /// The height the next slice starts at.
+    pub ceiling: f32,
+    pub mins: [f32; 2],
+    pub maxs: [f32; 2],
+    pub triangles: Vec<[[f32; 2]; 3]>,
+    /// The edges of the floors, joined into polylines. Closed outlines end with their first
+    /// point.
+    pub outlines: Vec<Vec<[f32; 2]>>,
+}
+
+impl MinimapSlice {
+    fn new(floor: f32, ceiling: f32, triangles: Vec<[[f32; 2]; 3]>) -> Self {
+        let mut mins = [std::f32::INFINITY; 2];
+        let mut maxs = [std::f32::NEG_INFINITY; 2];
+        for p in triangles.iter().flat_map(|t| t.iter()) {
+            for i in 0..2 {
+                mins[i] = mins[i].min(p[i]);
+                maxs[i] = maxs[i].max(p[i]);
+            }
+        }
+
+        MinimapSlice {
+            floor,
+            ceiling,
+            mins,
+            maxs,
+            outlines: outlines(&triangles),
+            triangles,
+        }
+    }
+
+    /// Draw the slice into a `size` by `size` RGBA texture, with floors in grey and their
+    /// outlines in white on a transparent background. The slice is scaled to fit and centred,
+    /// with the map's north at the top.
+    pub fn rasterize(&self, size: u16) -> TextureData {
+        let size = size.max(1);
+        let pixels = size as usize;
+        let mut rgba = vec![0; pixels * pixels * 4];
+
+        let extent = (self.maxs[0] - self.mins[0]).max(self.maxs[1] - self.mins[1]);
+        let scale = if extent > 0.0 {
+            f32::from(size - 1) / extent
+        } else {
+            1.0
+        };
+        let offset = [
+            (f32::from(size - 1) - (self.maxs[0] - self.mins[0]) * scale) / 2.0,
+            (f32::from(size - 1) - (self.maxs[1] - self.mins[1]) * scale) / 2.0,
+        ];
+        let to_pixel = |p: [f32; 2]| {
+            [
+                (p[0] - self.mins[0]) * scale + offset[0],
+                (p[1] - self.mins[1]) * scale + offset[1],
+            ]
+        };
+
+        let mut plot = |x: f32, y: f32, color: [u8; 4]| {
+            let (x, y) = (x.round(), y.round());
+            if x >= 0.0 && y >= 0.0 && (x as usize) < pixels && (y as usize) < pixels {
+                let i = (y as usize * pixels + x as usize) * 4;
+                rgba[i..i + 4].copy_from_slice(&color);
+            }
+        };
+
+        for triangle in &self.triangles {
+            let [a, b, c] = [
+                to_pixel(triangle[0]),
+                to_pixel(triangle[1]),
+                to_pixel(triangle[2]),
+            ];
+            let edge = |p: [f32; 2], q: [f32; 2], r: [f32; 2]| {
+                (q[0] - p[0]) * (r[1] - p[1]) - (q[1] - p[1]) * (r[0] - p[0])
+            };
+            let area = edge(a, b, c);
+            if area == 0.0 {
+                continue;
+            }
+
+            let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as usize;
+            let max_x = a[0].max(b[0]).max(c[0]).ceil().min(f32::from(size - 1)) as usize;
+            let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as usize;
+            let max_y = a[1].max(b[1]).max(c[1]).ceil().min(f32::from(size - 1)) as usize;
+
+            for y in min_y..=max_y {
+                for x in min_x..=max_x {
+                    let p = [x as f32, y as f32];
+                    let inside = [edge(b, c, p), edge(c, a, p), edge(a, b, p)]
+                        .iter()
+                        .all(|&e| e * area >= 0.0);
+                    if inside {
+                        plot(p[0], p[1], FLOOR_COLOR);
+                    }
+                }
+            }
+        }
+
+        for outline in &self.outlines {
+            for pair in outline.windows(2) {
+                let [a, b] = [to_pixel(pair[0]), to_pixel(pair[1])];
+                let steps = (b[0] - a[0]).abs().max((b[1] - a[1]).abs()).ceil().max(1.0);
+                for i in 0..=steps as usize {
+                    let t = i as f32 / steps;
+                    plot(
+                        a[0] + (b[0] - a[0]) * t,
+                        a[1] + (b[1] - a[1]) * t,
+                        OUTLINE_COLOR,
+                    );
+                }
+            }
+        }
+
+        TextureData::U8(rgba, TextureMetadata::srgb().with_size(size, size))
+    }
+}
+
+type Point = (i32, i32);
+
+fn weld(p: [f32; 2]) -> Point {
+    (
+        (p[0] / WELD_GRID).round() as i32,
+        (p[1] / WELD_GRID).round() as i32,
+    )
+}
+
+/// Remove the edge between `a` and `b`, returning whether it was still there.
+fn take_edge(neighbours: &mut HashMap<Point, Vec<Point>>, a: Point, b: Point) -> bool {
+    let taken = match neighbours.get_mut(&a) {
+        Some(next) => match next.iter().position(|&n| n == b) {
+            Some(i) => {
+                next.remove(i);
+                true
+            }
+            None => false,
+        },
+        None => false,
+    };
+    if taken {
+        if let Some(next) = neighbours.get_mut(&b) {
+            if let Some(i) = next.iter().position(|&n| n == a) {
+                next.remove(i);
+            }
+        }
+    }
+    taken
+}
+
+/// The edges that only one triangle has, chained into polylines.
+fn outlines(triangles: &[[[f32; 2]; 3]]) -> Vec<Vec<[f32; 2]>> {
+    let mut points = HashMap::new();
+    let mut edges = HashMap::<_, usize>::new();
+    for triangle in triangles {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            let (a_key, b_key) = (weld(a), weld(b));
+            if a_key == b_key {
+                continue;
+            }
+            points.insert(a_key, a);
+            points.insert(b_key, b);
+            *edges
+                .entry((a_key.min(b_key), a_key.max(b_key)))
+                .or_default() += 1;
+        }
+    }
+
+    let mut neighbours = HashMap::<_, Vec<_>>::new();
+    let mut boundary = edges
+        .into_iter()
+        .filter(|&(_, count)| count == 1)
+        .map(|(edge, _)| edge)
+        .collect::<Vec<_>>();
+    // Sorted so that the outlines come out the same every time.
+    boundary.sort();
+    for &(a, b) in &boundary {
+        neighbours.entry(a).or_default().push(b);
+        neighbours.entry(b).or_default().push(a);
+    }
+
+    let mut out = vec![];
+    for (start, next) in boundary {
+        if !take_edge(&mut neighbours, start, next) {
+            continue;
+        }
+
+        let mut line = vec![points[&start], points[&next]];
+        let mut at = next;
+        while let Some(&following) = neighbours.get(&at).and_then(|n| n.first()) {
+            take_edge(&mut neighbours, at, following);
+            line.push(points[&following]);
+            at = following;
+            if at == start {
+                break;
+            }
+        }
+        out.push(line);
+    }
+
+    out
+}
+
+/// Split the floors of the world into storeys, for a minimap with one layer per floor of the
+/// map. Each slice starts at the lowest floor above the last one and is `storey_height` units
+/// tall. Sky, invisible and brush model faces are left out, as are walls and steep slopes.
+pub fn minimap_slices(bsp: &Bsp, storey_height: f32) -> Vec<MinimapSlice> {
+    let mut floors = vec![];
+
+    if let Some(world) = bsp.models().next() {
+        for face in world.faces() {
+            match face.texture() {
+                Some(texture) if flags::surface_flags(texture) & (SURF_SKY | SURF_NODRAW) == 0 => {}
+                _ => continue,
+            }
+
+            let vertices = face
+                .vertices()
+                .map(|v| (to_world_space(v.position), to_world_space(v.normal)))
+                .collect::<Vec<_>>();
+
+            for triangle in vertices.chunks_exact(3) {
+                let [a, b, c] = [triangle[0].0, triangle[1].0, triangle[2].0];
+                let normal = triangle.iter().fold([0.0; 3], |sum, &(_, n)| {
+                    [sum[0] + n[0], sum[1] + n[1], sum[2] + n[2]]
+                });
+                let len = dot(normal, normal).sqrt();
+                if len == 0.0 || normal[1] / len < MIN_FLOOR_FACING {
+                    continue;
+                }
+
+                let height = (a[1] + b[1] + c[1]) / 3.0;
+                floors.push((height, [[a[0], a[2]], [b[0], b[2]], [c[0], c[2]]]));
+            }
+        }
+    }
+
+    floors.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut slices = vec![];
+    let mut floors = floors.into_iter().peekable();
+    while let Some(&(floor, _)) = floors.peek() {
+        let ceiling = floor + storey_height.max(std::f32::EPSILON);
+        let mut triangles = vec![];
+        while let Some(&(height, triangle)) = floors.peek() {
+            if height >= ceiling {
+                break;
+            }
+            triangles.push(triangle);
+            floors.next();
+        }
+        slices.push(MinimapSlice::new(floor, ceiling, triangles));
+    }
+
+    slices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outlines_a_square() {
+        let triangles = vec![
+            [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]],
+            [[0.0, 0.0], [1.0, 1.0], [0.0, 1.0]],
+        ];
+        let slice = MinimapSlice::new(0.0, 64.0, triangles);
+
+        // The shared diagonal isn't part of the outline, which goes all the way round.
+        assert_eq!(slice.outlines.len(), 1);
+        assert_eq!(slice.outlines[0].len(), 5);
+        assert_eq!(slice.outlines[0].first(), slice.outlines[0].last());
+        assert_eq!(slice.mins, [0.0, 0.0]);
+        assert_eq!(slice.maxs, [1.0, 1.0]);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{TextureFallback, MISSING_TEXTURE};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    ecs::{Entity, Write},
+    renderer::{TextureData, TextureMetadata},
+    Error,
+};
+use log::warn;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+/// What to use in place of textures that fail to load.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissingTexture {
+    /// The `missing.png` shipped with this crate.
+    Image,
+    /// A magenta and black checkerboard of `size` by `size` pixels with `squares` squares along
+    /// each side.
+    Checkerboard { size: u16, squares: u16 },
+}
+
+impl Default for MissingTexture {
+    fn default() -> Self {
+        MissingTexture::Image
+    }
+}
+
+pub(crate) fn checkerboard(size: u16, squares: u16) -> TextureData {
+    let size = size.max(1);
+    let square = (size / squares.max(1)).max(1);
+
+    let mut rgba = Vec::with_capacity(size as usize * size as usize * 4);
+    for y in 0..size {
+        for x in 0..size {
+            let color = if (x / square + y / square) % 2 == 0 {
+                MAGENTA
+            } else {
+                BLACK
+            };
+            rgba.extend_from_slice(&color);
+        }
+    }
+
+    TextureData::U8(rgba, TextureMetadata::srgb().with_size(size, size))
+}
+
+impl MissingTexture {
+    /// A fallback for the texture `name` that logs which texture was missing and records it in
+    /// `report` before returning the replacement.
+    pub(crate) fn fallback(self, name: &str, report: &MissingTexturesPrefab) -> TextureFallback {
+        let name = name.to_string();
+        let failed = report.failed.clone();
+
+        let data = match self {
+            MissingTexture::Image => None,
+            MissingTexture::Checkerboard { size, squares } => Some(checkerboard(size, squares)),
+        };
+
+        Arc::new(move |e| {
+            warn!("Missing texture `{}`: {}", name, e);
+            if let Ok(mut failed) = failed.lock() {
+                failed.insert(name.clone());
+            }
+
+            Ok(data.clone().unwrap_or_else(|| MISSING_TEXTURE.clone()))
+        })
+    }
+}
+
+/// The textures that fell back to `MissingTexture`, along with how many faces use each, summed
+/// over every map that has been instantiated.
+#[derive(Debug, Clone, Default)]
+pub struct MissingTextures {
+    pub textures: HashMap<String, usize>,
+}
+
+/// Collects the names of missing textures as the fallbacks of one import are used, and adds
+/// them to `MissingTextures` when the map is instantiated. By then every texture of the prefab
+/// has been loaded, except for the cluster prefabs of a chunked import.
+#[derive(Default)]
+pub struct MissingTexturesPrefab {
+    failed: Arc<Mutex<HashSet<String>>>,
+    faces: HashMap<String, usize>,
+}
+
+impl MissingTexturesPrefab {
+    pub(crate) fn add_faces(&mut self, name: &str, count: usize) {
+        *self.faces.entry(name.to_string()).or_insert(0) += count;
+    }
+}
+
+impl<'a> PrefabData<'a> for MissingTexturesPrefab {
+    type SystemData = Write<'a, MissingTextures>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        _: Entity,
+        missing: &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        if let Ok(failed) = self.failed.lock() {
+            for name in failed.iter() {
+                let faces = self.faces.get(name).cloned().unwrap_or(0);
+                *missing.textures.entry(name.clone()).or_insert(0) += faces;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        _: &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkerboard_alternates() {
+        let data = match checkerboard(4, 2) {
+            TextureData::U8(data, _) => data,
+            _ => unreachable!(),
+        };
+
+        let pixel = |x: usize, y: usize| &data[(y * 4 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), &MAGENTA);
+        assert_eq!(pixel(1, 1), &MAGENTA);
+        assert_eq!(pixel(2, 0), &BLACK);
+        assert_eq!(pixel(3, 3), &MAGENTA);
+    }
+}
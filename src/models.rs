@@ -0,0 +1,45 @@
+use crate::entities::MapEntity;
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// A model file placed by a `misc_model` or `misc_gamemodel` entity, which is positioned by the
+/// entity's `Transform`. Nothing is loaded for these, since amethyst has no MD3 format, so games
+/// should load `path` themselves, usually by replacing this with an `AssetPrefab` in an
+/// `ImportOptions::map_element`.
+///
+/// q3map2 bakes most `misc_model`s into the world's faces and removes the entity, so these
+/// usually only come from maps compiled with other tools, or from `misc_gamemodel`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct ExternalModel {
+    /// The model's path, as written in the entity, such as `models/mapobjects/tree.md3`.
+    pub path: String,
+    /// The scale to draw the model at, in world space.
+    pub scale: [f32; 3],
+}
+
+impl Component for ExternalModel {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl ExternalModel {
+    pub(crate) fn from_entity(entity: &MapEntity) -> Option<Self> {
+        let path = entity.get("model").filter(|path| !path.starts_with('*'))?;
+
+        let [x, y, z] = entity.get_vec3("modelscale_vec").unwrap_or_else(|| {
+            let scale = entity.get_f32("modelscale").unwrap_or(1.0);
+            [scale; 3]
+        });
+
+        Some(ExternalModel {
+            path: path.to_string(),
+            // Swizzled like `to_world_space`, but scales have no direction to flip.
+            scale: [x, z, y],
+        })
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{entities::MapEntity, handler::EntityContext, scale::Scale, to_world_space, transform};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::{
+        nalgebra::{Unit, UnitQuaternion, Vector3},
+        timing::Time,
+        Transform,
+    },
+    derive::PrefabData,
+    ecs::{
+        Component, DenseVecStorage, Entities, Entity, Join, Read, ReadStorage, System, WriteStorage,
+    },
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// Quake 3's `func_rotating` turns around Z unless one of these is set.
+const X_AXIS: u32 = 4;
+const Y_AXIS: u32 = 8;
+
+// Quake 3 derives a pendulum's period from its length and the default `g_gravity`.
+const GRAVITY: f32 = 800.0;
+
+/// Brush models compiled with an origin brush are stored relative to it, and the entity's
+/// `origin` is set to it. Compilers that leave the origin brush in the model get the same
+/// treatment from the importer. Otherwise the model is stored in world space, so it is turned
+/// around the centre of its bounds instead.
+fn pivot(entity: &MapEntity, ctx: &EntityContext) -> [f32; 3] {
+    if entity.get_vec3("origin").is_some() || transform::model_pivot(ctx.bsp, entity).is_some() {
+        return [0.0; 3];
+    }
+
+    ctx.model
+        .and_then(|model| ctx.bsp.models().nth(model))
+        .map(|model| {
+            let mut centre = [0.0; 3];
+            for i in 0..3 {
+                centre[i] = (model.mins[i] + model.maxs[i]) / 2.0;
+            }
+            let mut centre = to_world_space(centre);
+            centre.scale(ctx.scale);
+            centre
+        })
+        .unwrap_or([0.0; 3])
+}
+
+/// Continuously turns an entity around `axis` (in the entity's local space) through `pivot`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct Rotator {
+    pub axis: [f32; 3],
+    /// Degrees per second.
+    pub speed: f32,
+    pub pivot: [f32; 3],
+}
+
+impl Component for Rotator {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Rotator {
+    pub(crate) fn from_entity(entity: &MapEntity, ctx: &EntityContext) -> Self {
+        let flags = entity.spawnflags();
+        let axis = if flags & X_AXIS != 0 {
+            [1.0, 0.0, 0.0]
+        } else if flags & Y_AXIS != 0 {
+            [0.0, 1.0, 0.0]
+        } else {
+            [0.0, 0.0, 1.0]
+        };
+
+        Rotator {
+            axis: to_world_space(axis),
+            speed: entity.get_f32("speed").unwrap_or(100.0),
+            pivot: pivot(entity, ctx),
+        }
+    }
+}
+
+/// Swings an entity back and forth around `axis` (in the entity's local space) through `pivot`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct Pendulum {
+    pub axis: [f32; 3],
+    /// The largest angle of the swing, in degrees.
+    pub amplitude: f32,
+    /// Swings per second.
+    pub frequency: f32,
+    /// The fraction of a swing to start at.
+    pub phase: f32,
+    pub pivot: [f32; 3],
+}
+
+impl Component for Pendulum {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Pendulum {
+    pub(crate) fn from_entity(entity: &MapEntity, ctx: &EntityContext) -> Self {
+        // Like Quake 3's `SP_func_pendulum`, this assumes the model hangs down from its origin.
+        let length = ctx
+            .model
+            .and_then(|model| ctx.bsp.models().nth(model))
+            .map_or(0.0, |model| model.mins[2].abs())
+            .max(8.0);
+
+        Pendulum {
+            axis: to_world_space([1.0, 0.0, 0.0]),
+            amplitude: entity.get_f32("speed").unwrap_or(30.0),
+            frequency: (GRAVITY / (3.0 * length)).sqrt() / (2.0 * PI),
+            phase: entity.get_f32("phase").unwrap_or(0.0),
+            pivot: pivot(entity, ctx),
+        }
+    }
+}
+
+/// The transform an animated entity had when it was first animated. Only public because it's
+/// part of `MoverSystem`'s system data.
+#[doc(hidden)]
+pub struct BaseTransform {
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+}
+
+impl Component for BaseTransform {
+    type Storage = DenseVecStorage<Self>;
+}
+
+fn turn(
+    transform: &mut Transform,
+    base: &BaseTransform,
+    axis: [f32; 3],
+    pivot: [f32; 3],
+    angle: f32,
+) {
+    let axis = Unit::new_normalize(Vector3::new(axis[0], axis[1], axis[2]));
+    let pivot = Vector3::new(pivot[0], pivot[1], pivot[2]);
+    let turn = UnitQuaternion::from_axis_angle(&axis, angle.to_radians());
+
+    transform.set_position(base.translation + base.rotation * (pivot - turn * pivot));
+    transform.set_rotation(base.rotation * turn);
+}
+
+/// Animates the `Transform`s of entities with a `Rotator` or `Pendulum`, on top of the transform
+/// they were spawned with.
+#[derive(Default)]
+pub struct MoverSystem {
+    time: f32,
+}
+
+impl<'a> System<'a> for MoverSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, Time>,
+        ReadStorage<'a, Rotator>,
+        ReadStorage<'a, Pendulum>,
+        WriteStorage<'a, BaseTransform>,
+        WriteStorage<'a, Transform>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, time, rotators, pendulums, mut bases, mut transforms): Self::SystemData,
+    ) {
+        self.time += time.delta_seconds();
+
+        let new = (&entities, &transforms, !&bases)
+            .join()
+            .filter(|&(entity, _, _)| rotators.contains(entity) || pendulums.contains(entity))
+            .map(|(entity, transform, _)| {
+                (
+                    entity,
+                    BaseTransform {
+                        translation: *transform.translation(),
+                        rotation: *transform.rotation(),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        for (entity, base) in new {
+            // Inserting can only fail for dead entities, which `join` never yields.
+            let _ = bases.insert(entity, base);
+        }
+
+        for (rotator, base, transform) in (&rotators, &bases, &mut transforms).join() {
+            let angle = (rotator.speed * self.time) % 360.0;
+            turn(transform, base, rotator.axis, rotator.pivot, angle);
+        }
+
+        for (pendulum, base, transform) in (&pendulums, &bases, &mut transforms).join() {
+            let cycle = 2.0 * PI * (pendulum.frequency * self.time + pendulum.phase);
+            let angle = pendulum.amplitude * cycle.sin();
+            turn(transform, base, pendulum.axis, pendulum.pivot, angle);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{
+    brushes::{brush_hull, brush_texture, model_brushes},
+    flags::{self, CONTENTS_DETAIL, CONTENTS_SOLID, CONTENTS_TRANSLUCENT},
+    geometry::ConvexHull,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    ecs::{Entity, Write},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::HashMap};
+
+/// Large opaque brushes that occlusion culling can use to hide what is behind them, keyed by the
+/// root entity of the map they came from.
+#[derive(Default)]
+pub struct Occluders {
+    pub maps: HashMap<Entity, Vec<ConvexHull>>,
+}
+
+impl Occluders {
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a ConvexHull> + 'a {
+        self.maps.values().flat_map(|hulls| hulls.iter())
+    }
+}
+
+// Brushes using these shaders exist purely to occlude, so they are always used regardless of
+// their size.
+const HINT_SHADERS: &[&str] = &["antiportal", "occluder"];
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct OccludersPrefab {
+    pub hulls: Vec<ConvexHull>,
+}
+
+impl OccludersPrefab {
+    /// Extract structural world brushes whose two largest dimensions are at least `min_size`.
+    pub(crate) fn new(bsp: &Bsp, min_size: f32) -> Self {
+        let world = match bsp.models().next() {
+            Some(world) => world,
+            None => return Self::default(),
+        };
+
+        let hulls = model_brushes(bsp, &world)
+            .iter()
+            .filter_map(|brush| {
+                let texture = brush_texture(bsp, brush)?;
+                let name = texture.name.to_lowercase();
+                let hint = HINT_SHADERS.iter().any(|hint| name.ends_with(hint));
+
+                let contents = flags::contents(texture);
+                let structural = contents & CONTENTS_SOLID != 0
+                    && contents & (CONTENTS_DETAIL | CONTENTS_TRANSLUCENT) == 0;
+
+                if !hint && !structural {
+                    return None;
+                }
+
+                let hull = brush_hull(bsp, brush)?;
+
+                let mut size = hull.size();
+                size.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+                if hint || size[1] >= min_size {
+                    Some(hull)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        OccludersPrefab { hulls }
+    }
+}
+
+impl<'a> PrefabData<'a> for OccludersPrefab {
+    type SystemData = Write<'a, Occluders>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        occluders: &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        occluders.maps.insert(entity, self.hulls.clone());
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        _: &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
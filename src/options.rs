@@ -0,0 +1,421 @@
+use crate::{
+    dialect::BspDialect,
+    game_mode::GameMode,
+    geometry::bounds_overlap,
+    handler::EntityHandler,
+    lod::LodOptions,
+    material::MaterialMap,
+    missing::MissingTexture,
+    remap::TextureRemap,
+    shader::{Shader, ShaderLibrary},
+    BspPrefabElement, Extension,
+};
+use amethyst::{
+    assets::Prefab,
+    renderer::{FilterMethod, SamplerInfo, TextureMetadata, WrapMode},
+};
+use std::{ops::Range, sync::Arc};
+
+/// How to treat q3map2-style external lightmaps, stored as `maps/<map_name>/lm_XXXX.tga`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExternalLightmaps {
+    /// Use external lightmaps where they exist, falling back to the lightmap lump.
+    Auto,
+    /// Always use external lightmaps, failing to load if they are missing.
+    Always,
+    /// Only use the lightmap lump.
+    Never,
+}
+
+impl Default for ExternalLightmaps {
+    fn default() -> Self {
+        ExternalLightmaps::Auto
+    }
+}
+
+/// Adjustments applied to the lightmap lump when converting it to textures. These are not applied
+/// to external lightmaps, which are loaded as-is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightingOptions {
+    /// Quake 3 maps are lit assuming the lightmap will be brightened by `2^overbright_bits`,
+    /// which the original engine split between the lightmap and the hardware gamma ramp. The
+    /// default of `1` is the standard 2x brightening; `2` matches `r_mapOverBrightBits 2`.
+    pub overbright_bits: u8,
+    pub gamma: f32,
+    pub intensity: f32,
+}
+
+impl LightingOptions {
+    /// Leave the lightmap data untouched.
+    pub fn identity() -> Self {
+        LightingOptions {
+            overbright_bits: 0,
+            gamma: 1.0,
+            intensity: 1.0,
+        }
+    }
+}
+
+impl Default for LightingOptions {
+    fn default() -> Self {
+        LightingOptions {
+            overbright_bits: 1,
+            ..LightingOptions::identity()
+        }
+    }
+}
+
+/// How world textures are sampled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureOptions {
+    pub srgb: bool,
+    pub filter: FilterMethod,
+    pub wrap: WrapMode,
+    pub mip_levels: u8,
+    /// Clamp textures whose shader uses `clampmap` rather than `map`, regardless of `wrap`.
+    pub respect_clampmap: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        TextureOptions {
+            srgb: true,
+            filter: FilterMethod::Trilinear,
+            wrap: WrapMode::Tile,
+            mip_levels: 1,
+            respect_clampmap: true,
+        }
+    }
+}
+
+/// The properties of a face that a `FaceFilter` can use to exclude it.
+pub struct FaceInfo<'a> {
+    pub texture_name: &'a str,
+    pub surface_flags: u32,
+    pub contents: u32,
+    /// The index of the model containing the face, where `0` is the world.
+    pub model: usize,
+    pub face: &'a bsp::Face,
+}
+
+/// Returns `false` for faces that should be left out of the imported prefab.
+pub type FaceFilter = Arc<dyn Fn(&FaceInfo) -> bool + Send + Sync>;
+
+/// Looks up the width and height of a texture by its asset path, such as by reading the header
+/// of its image with `image::image_dimensions`, or `None` if it isn't known.
+pub type TextureSizes = Arc<dyn Fn(&str) -> Option<(u32, u32)> + Send + Sync>;
+
+/// Restricts an import to part of a map, to load huge maps piecewise or to extract a single
+/// model. Everything is imported by default. Data on the map's root, like vis and collision,
+/// always covers the whole map.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportSelection {
+    /// The models to import, where `0` is the world. Point entities belong to the world.
+    pub models: Option<Vec<usize>>,
+    /// The clusters of the world to import.
+    pub clusters: Option<Range<i32>>,
+    /// Only import clusters, models and point entities that overlap these bounds, given as
+    /// `(mins, maxs)` in world space.
+    pub bounds: Option<([f32; 3], [f32; 3])>,
+}
+
+impl ImportSelection {
+    pub fn includes_model(&self, model: usize) -> bool {
+        self.models
+            .as_ref()
+            .map_or(true, |models| models.contains(&model))
+    }
+
+    pub fn includes_cluster(&self, cluster: i32) -> bool {
+        self.includes_model(0)
+            && self
+                .clusters
+                .as_ref()
+                .map_or(true, |clusters| clusters.contains(&cluster))
+    }
+
+    /// Whether world space bounds overlap `bounds`, if set.
+    pub fn overlaps(&self, bounds: ([f32; 3], [f32; 3])) -> bool {
+        self.bounds
+            .map_or(true, |selected| bounds_overlap(selected, bounds))
+    }
+}
+
+/// Called on every element of the prefab as it is created, to post-process or replace it.
+pub type ElementMap<E = ()> = Arc<dyn Fn(BspPrefabElement<E>) -> BspPrefabElement<E> + Send + Sync>;
+
+/// Called on the finished prefab, to rewrite elements, add entities or drop parts of the map
+/// after everything else has been imported.
+pub type PrefabMap<E = ()> = Arc<dyn Fn(&mut Prefab<BspPrefabElement<E>>) + Send + Sync>;
+
+/// Options for importing a BSP as a prefab, with entity handlers and element maps producing
+/// extension data of type `E`.
+#[derive(Clone)]
+pub struct ImportOptions<E: Extension = ()> {
+    /// The name of the map without its extension, used to find resources stored alongside it.
+    /// External resources are not looked up if this is `None`.
+    pub map_name: Option<String>,
+    /// Stored in the map's `MapRoot` and `Cluster`s, to tell apart maps that are loaded at the
+    /// same time.
+    pub map_id: usize,
+    pub external_lightmaps: ExternalLightmaps,
+    pub lighting: LightingOptions,
+    /// Extract structural brushes at least this large (in map units) along their two largest
+    /// dimensions into the `Occluders` resource. Occluders are not extracted if this is `None`.
+    pub occluder_min_size: Option<f32>,
+    /// Build a `WaypointGraph` between the map's clusters into the `Waypoints` resource.
+    pub waypoints: bool,
+    /// Copy the map's BSP tree into the `BspTrees` resource, for custom traversal.
+    pub bsp_tree: bool,
+    /// Extract brushes (including clip brushes) as `CollisionGeometry` on the map's root entity.
+    pub collision: bool,
+    /// The number of subdivisions per 3x3 sub-patch used when tessellating curved surfaces for
+    /// collision.
+    pub collision_patch_level: usize,
+    /// Put the vertices of every face group into a single `MapGeometry` on the map's root,
+    /// giving face groups a `DrawRange` instead of a mesh each. This needs a renderer that can
+    /// draw ranges of a buffer. The `LightmapCoords` of face groups then only hold their page.
+    pub shared_geometry: bool,
+    /// Count the draw calls, vertices and textures of each cluster into a `MapBudget` on the
+    /// map's root, and log the most expensive clusters, to find the ones that blow the frame
+    /// budget.
+    pub budget_report: bool,
+    /// Merge the triangles of each face group that share an edge and a plane into larger
+    /// polygons, so that big flat walls and floors are drawn with fewer triangles. This makes
+    /// importing slower.
+    pub merge_coplanar: bool,
+    /// Simplified levels of detail to generate for each face group, from nearest to furthest.
+    /// Every level becomes a sibling of the full-detail group, and each is tagged with a
+    /// `LodLevel` for `LodSystem` to switch between. This makes importing much slower.
+    pub lods: Vec<LodOptions>,
+    /// Put the clusters that can be seen from the player spawn before the rest of the map, in
+    /// the prefab and in `MapChunks`. amethyst loads a prefab's textures in the order of its
+    /// entities, so the textures around the spawn are ready first and players can start moving
+    /// before the rest of the map has loaded.
+    pub prefetch_spawn: bool,
+    /// Add an entity with a `SurfaceLight` and a point `Light` at the centre of every world face
+    /// whose shader has `q3map_surfacelight`, so light fixtures light their surroundings in
+    /// renderers without baked lighting.
+    pub surface_lights: bool,
+    /// A uniform scale applied to everything the importer builds, such as `1.0 / 32.0` or
+    /// `0.0254` for physics engines working in meters. Meshes, entity origins, `MapVis`,
+    /// `CollisionGeometry`, occluders, trigger and water volumes, ladders, surface lights and fog
+    /// depths are all scaled as they are imported, and the map's root keeps a unit scale. Data
+    /// added by entity handlers and extensions is theirs to scale, using `EntityContext::scale`.
+    /// `selection` bounds and `occluder_min_size` stay in map units, while `lods` distances are
+    /// compared with the camera's and so are in scaled units.
+    pub scale: f32,
+    /// Reverse the winding of every triangle. Faces keep the BSP's winding by default, which is
+    /// clockwise when seen from the front like Quake 3 draws them, so pipelines that cull
+    /// clockwise triangles see rooms inside-out unless this is set.
+    pub reverse_winding: bool,
+    /// The dialect to read maps imported through `BspFormat` as, for files whose header is
+    /// shared by several games. By default this is detected from the header.
+    pub dialect: Option<BspDialect>,
+    /// Run `validate` on maps imported through `BspFormat`, failing to load ones with errors
+    /// and logging warnings. Maps passed straight to `import_bsp` are not validated.
+    pub validate: bool,
+    pub selection: ImportSelection,
+    /// Leave out entities, along with their brush models, that wouldn't spawn in this game
+    /// mode. Every entity is imported if this is `None`.
+    pub game_mode: Option<GameMode>,
+    /// Faces whose texture name starts with any of these (case-insensitively) are never drawn,
+    /// even if their surface flags say they should be. Compilers don't always mark tool textures
+    /// like caulk with `SURF_NODRAW`.
+    pub strip_texture_prefixes: Vec<String>,
+    pub entity_handlers: Vec<Arc<dyn EntityHandler<E>>>,
+    pub face_filter: Option<FaceFilter>,
+    pub texture_remap: Option<Arc<TextureRemap>>,
+    /// Put in front of every texture path after remapping, such as `maps/q3dm6/`, for projects
+    /// that keep each map's replacement textures in a directory of its own rather than in a
+    /// copy of the `textures/` tree. It's used as given, so it should end with a `/`.
+    pub texture_prefix: Option<String>,
+    /// Shader scripts used to look up per-surface properties like `surfaceparm`s.
+    pub shaders: Option<Arc<ShaderLibrary>>,
+    pub materials: Option<Arc<MaterialMap>>,
+    pub textures: TextureOptions,
+    /// Roughly how many bytes of world textures to load, for low-end targets loading maps with
+    /// high-resolution replacement textures. If the map's textures add up to more than this,
+    /// the largest are loaded at half or a quarter of their resolution until they fit, using
+    /// `texture_sizes` to tell how large they are. Lightmaps and material textures don't count
+    /// towards this.
+    pub texture_budget: Option<usize>,
+    /// The sizes of world textures, for `texture_budget`. Textures it doesn't know, or every
+    /// texture if this is `None`, are assumed to be 256x256.
+    pub texture_sizes: Option<TextureSizes>,
+    /// Queue world textures in the `TextureUploads` resource, and lightmap pages in
+    /// `StagedLightmaps` on the map's root, as the map is instantiated instead of loading them
+    /// all with the prefab, so that `TextureUploadSystem` can spread their uploads over several
+    /// frames. The prefab's `ProgressCounter` doesn't count these loads, and `LightmapPages` is
+    /// only added to the map's root once every page has been loaded.
+    pub staggered_uploads: bool,
+    pub missing_texture: MissingTexture,
+    pub map_element: Option<ElementMap<E>>,
+    /// Called with the prefab once it is complete. Maps imported with `import_bsp_chunked` call
+    /// this on the root prefab and then on the prefab of each cluster.
+    pub map_prefab: Option<PrefabMap<E>>,
+}
+
+impl<E: Extension> ImportOptions<E> {
+    /// The default options, scaled for physics engines working in meters. This treats a Quake
+    /// unit as an inch, which matches the player's height; use a `scale` of `1.0 / 32.0`
+    /// instead for the other common convention of 32 units per meter.
+    pub fn quake_units_to_meters() -> Self {
+        ImportOptions {
+            scale: 0.0254,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_entity_handler<H: EntityHandler<E> + 'static>(mut self, handler: H) -> Self {
+        self.entity_handlers.push(Arc::new(handler));
+        self
+    }
+
+    pub fn with_face_filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&FaceInfo) -> bool + Send + Sync + 'static,
+    {
+        self.face_filter = Some(Arc::new(filter));
+        self
+    }
+
+    pub fn with_texture_sizes<F>(mut self, sizes: F) -> Self
+    where
+        F: Fn(&str) -> Option<(u32, u32)> + Send + Sync + 'static,
+    {
+        self.texture_sizes = Some(Arc::new(sizes));
+        self
+    }
+
+    /// The asset path to load for a BSP texture name.
+    pub fn texture_path(&self, texture_name: &str) -> String {
+        let path = self
+            .texture_remap
+            .as_ref()
+            .and_then(|remap| remap.get(texture_name))
+            .unwrap_or(texture_name);
+
+        match &self.texture_prefix {
+            Some(prefix) => format!("{}{}", prefix, path),
+            None => path.to_string(),
+        }
+    }
+
+    /// Look up textures in `maps/<map_name>/`, next to external lightmaps, if `map_name` has
+    /// already been set.
+    pub fn with_map_texture_prefix(mut self) -> Self {
+        self.texture_prefix = self.map_name.as_ref().map(|name| format!("maps/{}/", name));
+        self
+    }
+
+    pub fn shader(&self, texture_name: &str) -> Option<&Shader> {
+        self.shaders.as_ref()?.get(texture_name)
+    }
+
+    /// The metadata to load a world texture with, taking its shader into account.
+    pub fn texture_metadata(&self, texture_name: &str) -> TextureMetadata {
+        let options = &self.textures;
+
+        let clamped = options.respect_clampmap
+            && self.shader(texture_name).map_or(false, |shader| {
+                shader
+                    .stages
+                    .iter()
+                    .any(|stage| stage.directive("clampmap").next().is_some())
+            });
+        let wrap = if clamped {
+            WrapMode::Clamp
+        } else {
+            options.wrap
+        };
+
+        let mut metadata = if options.srgb {
+            TextureMetadata::srgb()
+        } else {
+            TextureMetadata::unorm()
+        }
+        .with_sampler(SamplerInfo::new(options.filter, wrap));
+        metadata.mip_levels = options.mip_levels;
+
+        metadata
+    }
+
+    pub fn with_element_map<F>(mut self, map: F) -> Self
+    where
+        F: Fn(BspPrefabElement<E>) -> BspPrefabElement<E> + Send + Sync + 'static,
+    {
+        self.map_element = Some(Arc::new(map));
+        self
+    }
+
+    pub(crate) fn map_element(&self, element: BspPrefabElement<E>) -> BspPrefabElement<E> {
+        match &self.map_element {
+            Some(map) => map(element),
+            None => element,
+        }
+    }
+
+    pub fn with_prefab_map<F>(mut self, map: F) -> Self
+    where
+        F: Fn(&mut Prefab<BspPrefabElement<E>>) + Send + Sync + 'static,
+    {
+        self.map_prefab = Some(Arc::new(map));
+        self
+    }
+
+    pub(crate) fn map_prefab(&self, prefab: &mut Prefab<BspPrefabElement<E>>) {
+        if let Some(map) = &self.map_prefab {
+            map(prefab);
+        }
+    }
+
+    pub(crate) fn is_stripped(&self, texture_name: &str) -> bool {
+        let name = texture_name.to_lowercase();
+        self.strip_texture_prefixes
+            .iter()
+            .any(|prefix| name.starts_with(&prefix.to_lowercase()))
+    }
+}
+
+impl<E: Extension> Default for ImportOptions<E> {
+    fn default() -> Self {
+        ImportOptions {
+            map_name: None,
+            map_id: 0,
+            external_lightmaps: Default::default(),
+            lighting: Default::default(),
+            occluder_min_size: None,
+            waypoints: false,
+            bsp_tree: false,
+            collision: false,
+            collision_patch_level: 2,
+            shared_geometry: false,
+            budget_report: false,
+            merge_coplanar: false,
+            lods: vec![],
+            prefetch_spawn: false,
+            surface_lights: false,
+            scale: 1.0,
+            reverse_winding: false,
+            dialect: None,
+            validate: false,
+            selection: Default::default(),
+            game_mode: None,
+            strip_texture_prefixes: vec!["textures/common/".to_string()],
+            entity_handlers: vec![],
+            face_filter: None,
+            texture_remap: None,
+            texture_prefix: None,
+            shaders: None,
+            materials: None,
+            textures: Default::default(),
+            texture_budget: None,
+            texture_sizes: None,
+            staggered_uploads: false,
+            missing_texture: Default::default(),
+            map_element: None,
+            map_prefab: None,
+        }
+    }
+}
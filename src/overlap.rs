@@ -0,0 +1,112 @@
+use crate::{
+    brushes::{brush_sides, brush_texture, range},
+    flags,
+    geometry::{dot, Plane},
+};
+use bsp::Bsp;
+
+/// A brush touched by an overlap query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrushContact {
+    /// The index of the brush in `BspAsset::brushes`.
+    pub brush: usize,
+    /// The index of the model the brush belongs to, where `0` is the world.
+    pub model: usize,
+    pub contents: u32,
+}
+
+/// The brushes touched by an overlap query, and the union of their contents, such as
+/// `CONTENTS_SOLID | CONTENTS_WATER` for a box half in water against a wall.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Overlap {
+    pub brushes: Vec<BrushContact>,
+    pub contents: u32,
+}
+
+impl Overlap {
+    pub fn is_empty(&self) -> bool {
+        self.brushes.is_empty()
+    }
+}
+
+/// Every brush that `outside` doesn't place entirely outside one of its planes. Like Quake 3's
+/// `CM_TestBoxInBrush`, this doesn't bevel the brushes, so shapes near the edges of angled
+/// brushes can be reported as touching them when they are just outside.
+pub(crate) fn overlapping_brushes<F>(bsp: &Bsp, outside: F) -> Overlap
+where
+    F: Fn(&Plane) -> bool,
+{
+    let mut overlap = Overlap::default();
+
+    for (model_index, model) in bsp.models().enumerate() {
+        for (i, brush) in range(&bsp.brushes, model.brush, model.n_brushes)
+            .iter()
+            .enumerate()
+        {
+            let contents = match brush_texture(bsp, brush) {
+                Some(texture) => flags::contents(texture),
+                None => continue,
+            };
+
+            let sides = brush_sides(bsp, brush);
+            let touches = !sides.is_empty()
+                && sides
+                    .iter()
+                    .filter_map(|side| bsp.planes.get(side.plane as usize))
+                    .all(|plane| {
+                        !outside(&Plane {
+                            normal: plane.normal,
+                            dist: plane.dist,
+                        })
+                    });
+
+            if touches {
+                overlap.brushes.push(BrushContact {
+                    brush: model.brush as usize + i,
+                    model: model_index,
+                    contents,
+                });
+                overlap.contents |= contents;
+            }
+        }
+    }
+
+    overlap
+}
+
+/// Whether the box from `mins` to `maxs` is entirely in front of `plane`.
+pub(crate) fn box_outside(mins: [f32; 3], maxs: [f32; 3], plane: &Plane) -> bool {
+    // The corner of the box furthest behind the plane.
+    let mut corner = [0.0; 3];
+    for i in 0..3 {
+        corner[i] = if plane.normal[i] >= 0.0 {
+            mins[i]
+        } else {
+            maxs[i]
+        };
+    }
+
+    plane.distance(corner) > 0.0
+}
+
+pub(crate) fn sphere_outside(center: [f32; 3], radius: f32, plane: &Plane) -> bool {
+    dot(plane.normal, center) - plane.dist > radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tests_shapes_against_planes() {
+        let plane = Plane {
+            normal: [0.0, 0.0, 1.0],
+            dist: 64.0,
+        };
+
+        assert!(box_outside([0.0, 0.0, 65.0], [8.0, 8.0, 80.0], &plane));
+        assert!(!box_outside([0.0, 0.0, 60.0], [8.0, 8.0, 80.0], &plane));
+        assert!(sphere_outside([0.0, 0.0, 80.0], 8.0, &plane));
+        assert!(!sphere_outside([0.0, 0.0, 70.0], 8.0, &plane));
+    }
+}
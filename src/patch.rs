@@ -0,0 +1,85 @@
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
+
+fn quadratic(a: [f32; 3], b: [f32; 3], c: [f32; 3], t: f32) -> [f32; 3] {
+    lerp(lerp(a, b, t), lerp(b, c, t), t)
+}
+
+/// Tessellate a Quake 3 patch, a `width` by `height` grid of control points forming a set of
+/// biquadratic bezier patches sharing their edges, returning a triangle list. Each 3x3 sub-patch
+/// is split into `level` by `level` quads.
+pub(crate) fn tessellate(
+    control: &[[f32; 3]],
+    width: usize,
+    height: usize,
+    level: usize,
+) -> (Vec<[f32; 3]>, Vec<u32>) {
+    let mut vertices = vec![];
+    let mut indices = vec![];
+
+    let level = level.max(1);
+
+    if width < 3 || height < 3 || control.len() < width * height {
+        return (vertices, indices);
+    }
+
+    for py in 0..(height - 1) / 2 {
+        for px in 0..(width - 1) / 2 {
+            let point = |x: usize, y: usize| control[(py * 2 + y) * width + px * 2 + x];
+            let base = vertices.len() as u32;
+
+            for j in 0..=level {
+                let v = j as f32 / level as f32;
+                let rows = [
+                    quadratic(point(0, 0), point(0, 1), point(0, 2), v),
+                    quadratic(point(1, 0), point(1, 1), point(1, 2), v),
+                    quadratic(point(2, 0), point(2, 1), point(2, 2), v),
+                ];
+
+                for i in 0..=level {
+                    let u = i as f32 / level as f32;
+                    vertices.push(quadratic(rows[0], rows[1], rows[2], u));
+                }
+            }
+
+            let stride = level as u32 + 1;
+            for j in 0..level as u32 {
+                for i in 0..level as u32 {
+                    let a = base + j * stride + i;
+                    let b = a + 1;
+                    let c = a + stride;
+                    let d = c + 1;
+                    indices.extend_from_slice(&[a, c, b, b, c, d]);
+                }
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tessellates_sub_patches() {
+        let mut control = vec![];
+        for y in 0..3 {
+            for x in 0..5 {
+                control.push([x as f32, y as f32, 0.0]);
+            }
+        }
+
+        let (vertices, indices) = tessellate(&control, 5, 3, 2);
+
+        assert_eq!(vertices.len(), 2 * 9);
+        assert_eq!(indices.len(), 2 * 4 * 6);
+        assert_eq!(vertices[4], [1.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{entities::MapEntity, to_world_space};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+// Quake 3 drops items to the floor when they spawn unless this is set.
+const SUSPENDED: u32 = 1;
+
+/// The classname prefixes of entities imported as a `Pickup`.
+const PICKUP_PREFIXES: &[&str] = &["item_", "weapon_", "ammo_"];
+
+/// An item, weapon or ammo box placed in the map. `class` is the full classname, such as
+/// `weapon_railgun` or `item_armor_body`, for games to map to their own items.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct Pickup {
+    pub class: String,
+    /// Whether the item floats where it was placed, rather than falling to the floor when it
+    /// spawns.
+    pub suspended: bool,
+    /// Where the item was placed, in world space relative to the map's root.
+    pub origin: [f32; 3],
+    /// Seconds until the item respawns after being picked up, overriding the game's default.
+    pub wait: Option<f32>,
+    /// Up to this many seconds are randomly added to or taken from `wait`.
+    pub random: Option<f32>,
+    /// The amount given, such as the number of rounds in an ammo box, overriding the default.
+    pub count: Option<i32>,
+    /// Items with the same team only spawn one at a time, picking one of them at random.
+    pub team: Option<String>,
+    /// Set by `notfree`, `notteam` and `notsingle`: items that don't spawn in free-for-all,
+    /// team or single player games.
+    pub not_free: bool,
+    pub not_team: bool,
+    pub not_single: bool,
+}
+
+impl Component for Pickup {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Pickup {
+    pub(crate) fn is_pickup(classname: &str) -> bool {
+        PICKUP_PREFIXES
+            .iter()
+            .any(|prefix| classname.starts_with(prefix))
+    }
+
+    pub(crate) fn from_entity(classname: &str, entity: &MapEntity) -> Self {
+        let flag = |key| entity.get_bool(key).unwrap_or(false);
+
+        Pickup {
+            class: classname.to_string(),
+            suspended: entity.spawnflags() & SUSPENDED != 0,
+            origin: to_world_space(entity.get_vec3("origin").unwrap_or_default()),
+            wait: entity.get_f32("wait"),
+            random: entity.get_f32("random"),
+            count: entity.get_i32("count"),
+            team: entity.get("team").map(|team| team.to_string()),
+            not_free: flag("notfree"),
+            not_team: flag("notteam"),
+            not_single: flag("notsingle"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_item_keys() {
+        let entity = MapEntity {
+            keyvalues: vec![
+                ("classname".to_string(), "item_quad".to_string()),
+                ("origin".to_string(), "16 32 64".to_string()),
+                ("spawnflags".to_string(), "1".to_string()),
+                ("wait".to_string(), "120".to_string()),
+                ("notteam".to_string(), "1".to_string()),
+            ],
+        };
+
+        assert!(Pickup::is_pickup("item_quad"));
+        assert!(!Pickup::is_pickup("info_player_start"));
+
+        let pickup = Pickup::from_entity("item_quad", &entity);
+        assert!(pickup.suspended);
+        assert_eq!(pickup.origin, [16.0, 64.0, -32.0]);
+        assert_eq!(pickup.wait, Some(120.0));
+        assert!(pickup.not_team && !pickup.not_free);
+    }
+}
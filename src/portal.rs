@@ -0,0 +1,216 @@
+use crate::{
+    entities::MapEntity,
+    geometry::{dot, Plane},
+    mesh::FaceGroup,
+    scale::Scale,
+    shader::Shader,
+    to_world_space, transform,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::{
+        nalgebra::{Point3, Vector3},
+        GlobalTransform, Transform,
+    },
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entities, Entity, Join, ReadStorage, System, WriteStorage},
+    renderer::Camera,
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// A face group drawn with a `portal` shader, in the map's space. Rendering these needs a second
+/// camera drawing to a texture, which is left to the game: `PortalSystem` keeps a `PortalView`
+/// on each portal saying where that camera should be.
+#[derive(Debug, Clone, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct PortalSurface {
+    /// The plane of the surface, facing the side it is seen from.
+    pub plane: Plane,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    /// The `misc_portal_camera` the portal shows the view from, with the same forward axis as
+    /// other entity transforms. Portals without a camera are mirrors.
+    pub camera: Option<Transform>,
+}
+
+impl Component for PortalSurface {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl PortalSurface {
+    pub fn is_mirror(&self) -> bool {
+        self.camera.is_none()
+    }
+}
+
+pub(crate) fn is_portal(shader: &Shader) -> bool {
+    shader.directive("portal").next().is_some()
+        || shader.directive("sort").any(|d| {
+            d.args
+                .first()
+                .map_or(false, |s| s.eq_ignore_ascii_case("portal"))
+        })
+}
+
+/// A `misc_portal_surface`, and the transform of the camera it targets if it has one.
+pub(crate) struct PortalLink {
+    origin: [f32; 3],
+    camera: Option<Transform>,
+}
+
+/// The transform of a `misc_portal_camera`, aimed at its target if it has one.
+fn camera_transform(camera: &MapEntity, entities: &[MapEntity]) -> Transform {
+    let origin = camera.get_vec3("origin").unwrap_or([0.0; 3]);
+    let aim = camera
+        .get("target")
+        .and_then(|target| {
+            entities
+                .iter()
+                .find(|e| e.get("targetname") == Some(target))
+        })
+        .and_then(|target| target.get_vec3("origin"));
+
+    let mut out = transform::entity_transform(camera).unwrap_or_default();
+
+    if let Some(aim) = aim {
+        let d = [aim[0] - origin[0], aim[1] - origin[1], aim[2] - origin[2]];
+        let yaw = d[1].atan2(d[0]).to_degrees();
+        let pitch = -d[2].atan2((d[0] * d[0] + d[1] * d[1]).sqrt()).to_degrees();
+        let roll = camera.get_f32("roll").unwrap_or(0.0);
+        out.set_rotation(transform::angles_to_rotation([pitch, yaw, roll]));
+    }
+
+    out
+}
+
+/// The links of every `misc_portal_surface`, scaled like the face groups they are matched to.
+pub(crate) fn portal_links(entities: &[MapEntity], scale: f32) -> Vec<PortalLink> {
+    entities
+        .iter()
+        .filter(|e| e.classname() == Some("misc_portal_surface"))
+        .filter_map(|surface| {
+            let camera = surface.get("target").and_then(|target| {
+                entities.iter().find(|e| {
+                    e.classname() == Some("misc_portal_camera")
+                        && e.get("targetname") == Some(target)
+                })
+            });
+
+            let mut link = PortalLink {
+                origin: to_world_space(surface.get_vec3("origin")?),
+                camera: camera.map(|camera| camera_transform(camera, entities)),
+            };
+            link.origin.scale(scale);
+            link.camera.scale(scale);
+            Some(link)
+        })
+        .collect()
+}
+
+impl PortalSurface {
+    /// Like Quake 3, a portal uses the `misc_portal_surface` within 64 map units of its plane.
+    pub(crate) fn new(group: &FaceGroup, links: &[PortalLink], scale: f32) -> Option<Self> {
+        const LINK_DISTANCE: f32 = 64.0;
+
+        let (mins, maxs) = group.bounds()?;
+        let normal = *group.normals.first()?;
+        let plane = Plane {
+            normal,
+            dist: dot(normal, group.positions[0]),
+        };
+
+        let link = links
+            .iter()
+            .filter(|link| plane.distance(link.origin).abs() <= LINK_DISTANCE * scale)
+            .min_by(|a, b| {
+                plane
+                    .distance(a.origin)
+                    .abs()
+                    .partial_cmp(&plane.distance(b.origin).abs())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+
+        Some(PortalSurface {
+            plane,
+            mins,
+            maxs,
+            camera: link.and_then(|link| link.camera.clone()),
+        })
+    }
+}
+
+/// Where to put the camera rendering a portal, in world space, as of the last frame. The view
+/// through a mirror is flipped, so its image needs to be mirrored when it is drawn.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortalView {
+    pub position: [f32; 3],
+    pub forward: [f32; 3],
+    pub up: [f32; 3],
+}
+
+impl Component for PortalView {
+    type Storage = DenseVecStorage<Self>;
+}
+
+fn reflect(v: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    v - normal * 2.0 * v.dot(&normal)
+}
+
+/// Updates the `PortalView` of every `PortalSurface` for the first camera. Mirrors reflect the
+/// camera in their plane, and portals look from their `misc_portal_camera`.
+#[derive(Default)]
+pub struct PortalSystem;
+
+impl<'a> System<'a> for PortalSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, PortalSurface>,
+        WriteStorage<'a, PortalView>,
+    );
+
+    fn run(&mut self, (entities, cameras, globals, portals, mut views): Self::SystemData) {
+        let eye = match (&cameras, &globals).join().next() {
+            Some((_, global)) => global.0,
+            None => return,
+        };
+
+        for (entity, portal, global) in (&entities, &portals, &globals).join() {
+            let map = global.0;
+
+            let view = match &portal.camera {
+                Some(camera) => {
+                    let matrix = map * camera.matrix();
+                    PortalView {
+                        position: matrix.transform_point(&Point3::origin()).coords.into(),
+                        forward: matrix.transform_vector(&Vector3::x()).normalize().into(),
+                        up: matrix.transform_vector(&Vector3::y()).normalize().into(),
+                    }
+                }
+                None => {
+                    let Plane { normal, dist } = portal.plane;
+                    let [x, y, z] = normal;
+                    let on_plane = map.transform_point(&Point3::new(x * dist, y * dist, z * dist));
+                    let normal = map.transform_vector(&Vector3::new(x, y, z)).normalize();
+
+                    let offset = eye.transform_point(&Point3::origin()) - on_plane;
+                    let position = on_plane + reflect(offset, normal);
+                    // Amethyst cameras look down -Z.
+                    let forward = reflect(eye.transform_vector(&-Vector3::z()), normal);
+                    let up = reflect(eye.transform_vector(&Vector3::y()), normal);
+
+                    PortalView {
+                        position: position.coords.into(),
+                        forward: forward.normalize().into(),
+                        up: up.normalize().into(),
+                    }
+                }
+            };
+
+            // Inserting can only fail for dead entities, which `join` never yields.
+            let _ = views.insert(entity, view);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{cluster_entities::ClusterEntities, entities, BspPrefabElement, Extension};
+use amethyst::{
+    assets::{AssetStorage, Handle, Prefab, PrefabData, ProgressCounter},
+    core::ParentHierarchy,
+    derive::PrefabData,
+    ecs::{
+        Component, Entities, Entity, HashMapStorage, Join, Read, ReadExpect, System, Write,
+        WriteStorage,
+    },
+    shrev::EventChannel,
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, hash::Hasher, marker::PhantomData};
+
+/// Identifies the version of a map that was imported, attached to the map's root entity. It is
+/// a hash of the map's entities, geometry, lightmaps and vis, so importing the same map always
+/// gives the same generation while a recompiled map gets a new one, and a hot-reloaded prefab
+/// can be told apart from the one that was instantiated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct MapGeneration(pub usize);
+
+impl Component for MapGeneration {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl MapGeneration {
+    pub(crate) fn of(bsp: &Bsp) -> Self {
+        fn floats(hash: &mut Fnv, floats: &[f32]) {
+            for f in floats {
+                hash.write(&f.to_bits().to_le_bytes());
+            }
+        }
+
+        let mut hash = Fnv::default();
+
+        hash.write(entities::entity_string(bsp).as_bytes());
+        for vertex in &bsp.vertices {
+            floats(&mut hash, &vertex.position);
+            floats(&mut hash, &vertex.normal);
+            floats(&mut hash, &vertex.surface_texcoord);
+            floats(&mut hash, &vertex.lightmap_texcoord);
+            hash.write(&vertex.color);
+        }
+        for face in &bsp.faces {
+            for &i in &[
+                face.texture as i64,
+                face.vertex as i64,
+                face.n_vertexes as i64,
+                face.lm_index as i64,
+            ] {
+                hash.write(&i.to_le_bytes());
+            }
+        }
+        for color in bsp
+            .lightmaps
+            .iter()
+            .flat_map(|lightmap| lightmap.map.iter().flat_map(|row| row.iter()))
+        {
+            hash.write(color);
+        }
+        hash.write(&bsp.vis_data.vecs[..]);
+
+        MapGeneration(hash.finish() as usize)
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` gives the same hash in every build, so generations can
+/// be saved along with prefabs.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+/// Sent when a map's entities have been despawned so that its reloaded prefab can be
+/// instantiated in their place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapReloaded {
+    pub map: Entity,
+}
+
+/// Respawns maps whose prefab was hot-reloaded. amethyst only instantiates a prefab when its
+/// handle is added to an entity, so this deletes everything below the map's root and re-adds
+/// the handle. The root entity itself is kept, and `Cluster` ids are the BSP's own cluster
+/// numbers, so anything referring to either stays valid as long as the map's vis is unchanged.
+/// A reload that leaves the map itself unchanged keeps its `MapGeneration`, so it isn't
+/// respawned.
+///
+/// Maps instantiated with `ChunkedInstantiationSystem` are not reloaded.
+pub struct MapReloadSystem<E: Extension = ()> {
+    marker: PhantomData<E>,
+}
+
+impl<E: Extension> Default for MapReloadSystem<E> {
+    fn default() -> Self {
+        MapReloadSystem {
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, E: Extension> System<'a> for MapReloadSystem<E> {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, AssetStorage<Prefab<BspPrefabElement<E>>>>,
+        ReadExpect<'a, ParentHierarchy>,
+        WriteStorage<'a, Handle<Prefab<BspPrefabElement<E>>>>,
+        WriteStorage<'a, MapGeneration>,
+        Write<'a, EventChannel<MapReloaded>>,
+        Write<'a, ClusterEntities>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            prefabs,
+            hierarchy,
+            mut handles,
+            mut generations,
+            mut events,
+            mut clusters,
+        ): Self::SystemData,
+    ) {
+        let mut reloaded = vec![];
+
+        for (map, handle, generation) in (&entities, &handles, &mut generations).join() {
+            let latest = prefabs
+                .get(handle)
+                .and_then(|prefab| prefab.entities().next())
+                .and_then(|root| root.data())
+                .and_then(|root| root.generation);
+
+            if let Some(latest) = latest {
+                if latest != *generation {
+                    // Updated straight away, so the map isn't reloaded again before the prefab
+                    // loader has had a chance to instantiate it.
+                    *generation = latest;
+                    reloaded.push((map, handle.clone()));
+                }
+            }
+        }
+
+        for (map, handle) in reloaded {
+            let children = hierarchy
+                .all_children(map)
+                .join()
+                .map(|child| entities.entity(child))
+                .collect::<HashSet<_>>();
+            for &child in &children {
+                // Deleting can only fail for entities that are already dead.
+                let _ = entities.delete(child);
+            }
+            clusters.remove_all(&children);
+
+            handles.remove(map);
+            // Inserting can only fail for dead entities, and `map` was just joined over.
+            let _ = handles.insert(map, handle);
+
+            events.single_write(MapReloaded { map });
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test_support"))]
+mod tests {
+    use super::*;
+    use crate::test_support::FixtureBuilder;
+    use std::io::Cursor;
+
+    #[test]
+    fn generation_follows_the_map() {
+        let read = |bytes: Vec<u8>| Bsp::read(Cursor::new(bytes)).unwrap();
+        let map = || FixtureBuilder::new().build();
+
+        assert_eq!(
+            MapGeneration::of(&read(map())),
+            MapGeneration::of(&read(map()))
+        );
+        assert_ne!(
+            MapGeneration::of(&read(map())),
+            MapGeneration::of(&read(FixtureBuilder::new().with_floor_tiles(2).build())),
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A table redirecting BSP texture names to asset paths. In RON this is just a map:
+///
+/// ```ron
+/// {
+///     "textures/base_wall/basewall01": "textures/walls/concrete",
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct TextureRemap {
+    pub textures: HashMap<String, String>,
+}
+
+impl TextureRemap {
+    pub fn from_ron(source: &str) -> Result<Self, ron::de::Error> {
+        ron::de::from_str(source)
+    }
+
+    /// The remapped path for a texture. Names are matched exactly first, then in lowercase since
+    /// Quake 3 texture names are case-insensitive.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.textures
+            .get(name)
+            .or_else(|| self.textures.get(&name.to_lowercase()))
+            .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_from_ron() {
+        let remap =
+            TextureRemap::from_ron(r#"{ "textures/base/wall": "walls/concrete" }"#).unwrap();
+
+        assert_eq!(remap.get("textures/base/wall"), Some("walls/concrete"));
+        assert_eq!(remap.get("TEXTURES/BASE/WALL"), Some("walls/concrete"));
+        assert_eq!(remap.get("textures/base/floor"), None);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::lightmap::{LightmapCoords, LightmapPages};
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader},
+    ecs::{
+        Component, DenseVecStorage, Entities, HashMapStorage, Join, Read, ReadExpect, ReadStorage,
+        System, WriteStorage,
+    },
+    renderer::{Material, Texture, TextureData, TextureMetadata},
+};
+use serde::{Deserialize, Serialize};
+
+/// What lightmapped surfaces show, like Quake 3's `r_lightmap` and `r_fullbright`. Insert this
+/// as a resource and run `RenderModeSystem` to switch at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RenderMode {
+    Full,
+    /// Replace the textures of lightmapped surfaces with white, so only the lighting shows.
+    LightmapOnly,
+    /// Replace every lightmap page with white, so only the textures show.
+    TextureOnly,
+}
+
+impl Default for RenderMode {
+    fn default() -> Self {
+        RenderMode::Full
+    }
+}
+
+/// The textures of a face group replaced by `RenderModeSystem`. Only public because it's
+/// part of `RenderModeSystem`'s system data.
+#[doc(hidden)]
+pub struct ReplacedTextures {
+    texture: Option<Handle<Texture>>,
+    albedo: Option<Handle<Texture>>,
+}
+
+impl Component for ReplacedTextures {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The lightmap pages of a map replaced by `RenderModeSystem`. Only public because it's
+/// part of `RenderModeSystem`'s system data.
+#[doc(hidden)]
+pub struct ReplacedLightmaps {
+    lightmaps: Vec<Option<Handle<Texture>>>,
+}
+
+impl Component for ReplacedLightmaps {
+    type Storage = HashMapStorage<Self>;
+}
+
+/// Applies the `RenderMode` resource, swapping textures out and back as it changes. Maps
+/// spawned while a mode is active are switched too.
+#[derive(Default)]
+pub struct RenderModeSystem {
+    white: Option<Handle<Texture>>,
+}
+
+impl<'a> System<'a> for RenderModeSystem {
+    type SystemData = (
+        Read<'a, RenderMode>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Texture>>,
+        Entities<'a>,
+        ReadStorage<'a, LightmapCoords>,
+        WriteStorage<'a, Handle<Texture>>,
+        WriteStorage<'a, Material>,
+        WriteStorage<'a, LightmapPages>,
+        WriteStorage<'a, ReplacedTextures>,
+        WriteStorage<'a, ReplacedLightmaps>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            mode,
+            loader,
+            storage,
+            entities,
+            lightmapped,
+            mut textures,
+            mut materials,
+            mut pages,
+            mut replaced_textures,
+            mut replaced_lightmaps,
+        ): Self::SystemData,
+    ) {
+        if *mode == RenderMode::Full && self.white.is_none() {
+            return;
+        }
+
+        let white = self
+            .white
+            .get_or_insert_with(|| {
+                loader.load_from_data(
+                    TextureData::Rgba([1.0; 4], TextureMetadata::unorm()),
+                    (),
+                    &storage,
+                )
+            })
+            .clone();
+
+        if *mode == RenderMode::LightmapOnly {
+            let unreplaced = (&entities, &lightmapped, !&replaced_textures)
+                .join()
+                .map(|(entity, _, _)| entity)
+                .collect::<Vec<_>>();
+
+            for entity in unreplaced {
+                let replaced = ReplacedTextures {
+                    texture: textures.get(entity).cloned(),
+                    albedo: materials.get(entity).map(|m| m.albedo.clone()),
+                };
+
+                if let Some(texture) = textures.get_mut(entity) {
+                    *texture = white.clone();
+                }
+                if let Some(material) = materials.get_mut(entity) {
+                    material.albedo = white.clone();
+                }
+                // Inserting can only fail for dead entities, which `join` never yields.
+                let _ = replaced_textures.insert(entity, replaced);
+            }
+        } else {
+            let restored = (&entities, replaced_textures.drain())
+                .join()
+                .collect::<Vec<_>>();
+
+            for (entity, replaced) in restored {
+                if let (Some(texture), Some(original)) =
+                    (textures.get_mut(entity), replaced.texture)
+                {
+                    *texture = original;
+                }
+                if let (Some(material), Some(original)) =
+                    (materials.get_mut(entity), replaced.albedo)
+                {
+                    material.albedo = original;
+                }
+            }
+        }
+
+        if *mode == RenderMode::TextureOnly {
+            let unreplaced = (&entities, &pages, !&replaced_lightmaps)
+                .join()
+                .map(|(entity, _, _)| entity)
+                .collect::<Vec<_>>();
+
+            for entity in unreplaced {
+                if let Some(pages) = pages.get_mut(entity) {
+                    let lightmaps = vec![Some(white.clone()); pages.lightmaps.len()];
+                    let original = std::mem::replace(&mut pages.lightmaps, lightmaps);
+                    let _ = replaced_lightmaps.insert(
+                        entity,
+                        ReplacedLightmaps {
+                            lightmaps: original,
+                        },
+                    );
+                }
+            }
+        } else {
+            for (pages, replaced) in (&mut pages, replaced_lightmaps.drain()).join() {
+                pages.lightmaps = replaced.lightmaps;
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Conversions to the mesh and texture builders used by the rendy-based renderer in amethyst 0.11
+//! and later. The prefab produced by `BspFormat` targets the older renderer, so these work from
+//! `FaceGroup`s and the raw BSP instead.
+
+use crate::{
+    entities,
+    lightmap::{page_rgba, LightmapLayout, LIGHTMAP_SIZE},
+    Extension, FaceGroup, ImportOptions, MapGeometry,
+};
+use amethyst_rendy::{
+    rendy::{
+        hal::{
+            format::Format,
+            image::{Filter, Kind, SamplerInfo, ViewKind, WrapMode},
+        },
+        mesh::{MeshBuilder, Normal, Position, TexCoord},
+        texture::TextureBuilder,
+    },
+    types::{MeshData, TextureData},
+};
+use bsp::Bsp;
+
+impl FaceGroup {
+    /// A mesh with `Position`, `Normal` and `TexCoord` attributes.
+    pub fn rendy_mesh(&self) -> MeshData {
+        MeshBuilder::new()
+            .with_vertices(
+                self.positions
+                    .iter()
+                    .map(|&p| Position(p))
+                    .collect::<Vec<_>>(),
+            )
+            .with_vertices(self.normals.iter().map(|&n| Normal(n)).collect::<Vec<_>>())
+            .with_vertices(
+                self.tex_coords
+                    .iter()
+                    .map(|&t| TexCoord(t))
+                    .collect::<Vec<_>>(),
+            )
+            .into()
+    }
+
+    /// The lightmap texture coordinates, as a `TexCoord` attribute for use in a second vertex
+    /// buffer.
+    pub fn rendy_lightmap_coords(&self) -> Vec<TexCoord> {
+        self.lightmap_coords.iter().map(|&t| TexCoord(t)).collect()
+    }
+}
+
+impl MapGeometry {
+    /// One indexed mesh for the whole map, with `Position`, `Normal` and `TexCoord` attributes.
+    /// Face groups are drawn with their `DrawRange` as the index range.
+    pub fn rendy_mesh(&self) -> MeshData {
+        MeshBuilder::new()
+            .with_indices(self.indices.clone())
+            .with_vertices(
+                self.positions
+                    .iter()
+                    .map(|&p| Position(p))
+                    .collect::<Vec<_>>(),
+            )
+            .with_vertices(self.normals.iter().map(|&n| Normal(n)).collect::<Vec<_>>())
+            .with_vertices(
+                self.tex_coords
+                    .iter()
+                    .map(|&t| TexCoord(t))
+                    .collect::<Vec<_>>(),
+            )
+            .into()
+    }
+}
+
+fn lightmap_texture(rgba: Vec<u8>) -> TextureData {
+    let size = LIGHTMAP_SIZE as u32;
+
+    TextureBuilder::new()
+        .with_kind(Kind::D2(size, size, 1, 1))
+        .with_view_kind(ViewKind::D2)
+        .with_data_width(size)
+        .with_data_height(size)
+        .with_sampler_info(SamplerInfo::new(Filter::Linear, WrapMode::Clamp))
+        .with_raw_data(rgba, Format::Rgba8Unorm)
+        .into()
+}
+
+/// The lightmap and deluxemap pages from the lightmap lump, indexed the same way as
+/// `FaceGroup::lightmap_page`. External lightmaps are not supported here.
+pub fn rendy_lightmaps<E: Extension>(
+    bsp: &Bsp,
+    options: &ImportOptions<E>,
+) -> (Vec<TextureData>, Vec<TextureData>) {
+    let entities = entities::parse_entities(entities::entity_string(bsp));
+    let layout = LightmapLayout::new(bsp, entities::worldspawn(&entities));
+
+    let mut lightmaps = vec![];
+    let mut deluxemaps = vec![];
+
+    for (i, lightmap) in bsp.lightmaps.iter().enumerate() {
+        let texture = lightmap_texture(page_rgba(lightmap, &layout.lighting(i, &options.lighting)));
+
+        if layout.deluxe && i % 2 == 1 {
+            deluxemaps.push(texture);
+        } else {
+            lightmaps.push(texture);
+        }
+    }
+
+    (lightmaps, deluxemaps)
+}
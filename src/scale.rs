@@ -0,0 +1,99 @@
+//! `ImportOptions::scale`, applied to everything the importer builds in map units.
+
+use crate::geometry::{ConvexHull, Plane};
+use amethyst::core::Transform;
+
+/// Data whose positions and sizes are in map units until the importer scales them.
+pub(crate) trait Scale {
+    fn scale(&mut self, scale: f32);
+}
+
+impl Scale for [f32; 3] {
+    fn scale(&mut self, scale: f32) {
+        for c in self {
+            *c *= scale;
+        }
+    }
+}
+
+impl<T: Scale> Scale for Option<T> {
+    fn scale(&mut self, scale: f32) {
+        if let Some(inner) = self {
+            inner.scale(scale);
+        }
+    }
+}
+
+impl<T: Scale> Scale for Vec<T> {
+    fn scale(&mut self, scale: f32) {
+        for item in self {
+            item.scale(scale);
+        }
+    }
+}
+
+impl Scale for Plane {
+    fn scale(&mut self, scale: f32) {
+        self.dist *= scale;
+    }
+}
+
+impl Scale for ConvexHull {
+    fn scale(&mut self, scale: f32) {
+        self.planes.scale(scale);
+        self.vertices.scale(scale);
+        self.mins.scale(scale);
+        self.maxs.scale(scale);
+    }
+}
+
+/// Only the translation is scaled, since the meshes and data on the entity are already scaled
+/// themselves.
+impl Scale for Transform {
+    fn scale(&mut self, scale: f32) {
+        let translation = *self.translation() * scale;
+        self.set_position(translation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_planes_with_their_points() {
+        let mut hull = ConvexHull::from_planes(vec![
+            Plane {
+                normal: [1.0, 0.0, 0.0],
+                dist: 64.0,
+            },
+            Plane {
+                normal: [-1.0, 0.0, 0.0],
+                dist: 0.0,
+            },
+            Plane {
+                normal: [0.0, 1.0, 0.0],
+                dist: 32.0,
+            },
+            Plane {
+                normal: [0.0, -1.0, 0.0],
+                dist: 0.0,
+            },
+            Plane {
+                normal: [0.0, 0.0, 1.0],
+                dist: 16.0,
+            },
+            Plane {
+                normal: [0.0, 0.0, -1.0],
+                dist: 0.0,
+            },
+        ])
+        .unwrap();
+
+        hull.scale(0.5);
+
+        assert_eq!(hull.maxs, [32.0, 16.0, 8.0]);
+        assert!(hull.contains([31.0, 15.0, 7.0]));
+        assert!(!hull.contains([33.0, 15.0, 7.0]));
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+/// A single line of a shader, split on whitespace. The keyword is lowercased, the arguments
+/// are left as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    pub keyword: String,
+    pub args: Vec<String>,
+}
+
+impl Directive {
+    pub fn arg_f32(&self, index: usize) -> Option<f32> {
+        self.args.get(index)?.parse().ok()
+    }
+}
+
+/// A `{ ... }` block inside a shader, describing one rendering pass.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stage {
+    pub directives: Vec<Directive>,
+}
+
+impl Stage {
+    pub fn directive<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a Directive> + 'a {
+        self.directives.iter().filter(move |d| d.keyword == keyword)
+    }
+}
+
+/// A Quake 3 shader from a `.shader` script. Only the structure is parsed here; individual
+/// directives are interpreted by the parts of the importer that use them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Shader {
+    pub name: String,
+    pub directives: Vec<Directive>,
+    pub stages: Vec<Stage>,
+}
+
+impl Shader {
+    pub fn directive<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a Directive> + 'a {
+        self.directives.iter().filter(move |d| d.keyword == keyword)
+    }
+
+    pub fn surfaceparms<'a>(&'a self) -> impl Iterator<Item = &'a str> + 'a {
+        self.directive("surfaceparm")
+            .filter_map(|d| d.args.first())
+            .map(String::as_str)
+    }
+
+    pub fn has_surfaceparm(&self, parm: &str) -> bool {
+        self.surfaceparms().any(|p| p.eq_ignore_ascii_case(parm))
+    }
+}
+
+/// All shaders available to the importer, keyed by lowercase name.
+#[derive(Debug, Clone, Default)]
+pub struct ShaderLibrary {
+    shaders: HashMap<String, Shader>,
+}
+
+enum Line<'a> {
+    Open,
+    Close,
+    Tokens(Vec<&'a str>),
+}
+
+fn lines<'a>(source: &'a str) -> impl Iterator<Item = Line<'a>> + 'a {
+    source.lines().flat_map(|line| {
+        let line = match line.find("//") {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+
+        let mut out = vec![];
+        let mut tokens = vec![];
+
+        for token in line.split_whitespace() {
+            let mut rest = token;
+
+            while !rest.is_empty() {
+                let brace = rest.find(|c| c == '{' || c == '}');
+
+                match brace {
+                    Some(0) => {
+                        if !tokens.is_empty() {
+                            out.push(Line::Tokens(std::mem::replace(&mut tokens, vec![])));
+                        }
+                        out.push(if rest.starts_with('{') {
+                            Line::Open
+                        } else {
+                            Line::Close
+                        });
+                        rest = &rest[1..];
+                    }
+                    Some(i) => {
+                        tokens.push(&rest[..i]);
+                        rest = &rest[i..];
+                    }
+                    None => {
+                        tokens.push(rest);
+                        rest = "";
+                    }
+                }
+            }
+        }
+
+        if !tokens.is_empty() {
+            out.push(Line::Tokens(tokens));
+        }
+
+        out
+    })
+}
+
+fn directive(tokens: Vec<&str>) -> Directive {
+    Directive {
+        keyword: tokens[0].to_lowercase(),
+        args: tokens[1..].iter().map(|t| t.to_string()).collect(),
+    }
+}
+
+impl ShaderLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a shader script and add every shader in it, replacing existing shaders with the same
+    /// name. Unbalanced braces end parsing early rather than causing an error.
+    pub fn add_script(&mut self, source: &str) {
+        let mut lines = lines(source);
+
+        while let Some(line) = lines.next() {
+            let name = match line {
+                Line::Tokens(tokens) => tokens[0].to_lowercase(),
+                _ => continue,
+            };
+
+            match lines.next() {
+                Some(Line::Open) => {}
+                _ => return,
+            }
+
+            let mut shader = Shader {
+                name: name.clone(),
+                ..Default::default()
+            };
+            let mut stage: Option<Stage> = None;
+
+            loop {
+                match lines.next() {
+                    Some(Line::Open) => stage = Some(Stage::default()),
+                    Some(Line::Close) => match stage.take() {
+                        Some(finished) => shader.stages.push(finished),
+                        None => break,
+                    },
+                    Some(Line::Tokens(tokens)) => match &mut stage {
+                        Some(stage) => stage.directives.push(directive(tokens)),
+                        None => shader.directives.push(directive(tokens)),
+                    },
+                    None => {
+                        self.shaders.insert(name, shader);
+                        return;
+                    }
+                }
+            }
+
+            self.shaders.insert(name, shader);
+        }
+    }
+
+    pub fn with_script(mut self, source: &str) -> Self {
+        self.add_script(source);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Shader> {
+        self.shaders.get(&name.to_lowercase())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Shader> {
+        self.shaders.values()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_shader_script() {
+        let library = ShaderLibrary::new().with_script(
+            r#"
+// A comment
+textures/liquids/lava
+{
+    surfaceparm lava
+    surfaceparm noimpact // trailing comment
+    q3map_surfacelight 600
+    {
+        map textures/liquids/lava.tga
+        tcMod turb 0 .1 0 .05
+    }
+}
+textures/base/trim {
+    {map textures/base/trim.tga}
+}
+"#,
+        );
+
+        let lava = library.get("Textures/Liquids/Lava").unwrap();
+        assert!(lava.has_surfaceparm("LAVA"));
+        assert_eq!(lava.surfaceparms().count(), 2);
+        assert_eq!(
+            lava.directive("q3map_surfacelight")
+                .next()
+                .unwrap()
+                .arg_f32(0),
+            Some(600.0)
+        );
+        assert_eq!(lava.stages.len(), 1);
+        assert_eq!(
+            lava.stages[0].directive("tcmod").next().unwrap().args[0],
+            "turb"
+        );
+
+        let trim = library.get("textures/base/trim").unwrap();
+        assert_eq!(
+            trim.stages[0].directives[0].args[0],
+            "textures/base/trim.tga"
+        );
+    }
+}
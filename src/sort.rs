@@ -0,0 +1,136 @@
+use crate::shader::{Shader, Stage};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// The Quake 3 sort order of a face group's shader. Groups should be drawn in increasing order,
+/// so that blended effects and banners layer over the opaque world. Face groups within a cluster
+/// or model are imported in this order.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct RenderOrder(pub f32);
+
+impl Default for RenderOrder {
+    fn default() -> Self {
+        RenderOrder::OPAQUE
+    }
+}
+
+impl Component for RenderOrder {
+    type Storage = DenseVecStorage<Self>;
+}
+
+fn blends(stage: &Stage) -> bool {
+    stage.directive("blendfunc").any(|d| {
+        let args = d.args.iter().map(|a| a.to_lowercase()).collect::<Vec<_>>();
+        args != ["gl_one", "gl_zero"]
+    })
+}
+
+impl RenderOrder {
+    pub const PORTAL: RenderOrder = RenderOrder(1.0);
+    pub const SKY: RenderOrder = RenderOrder(2.0);
+    pub const OPAQUE: RenderOrder = RenderOrder(3.0);
+    pub const DECAL: RenderOrder = RenderOrder(4.0);
+    pub const SEE_THROUGH: RenderOrder = RenderOrder(5.0);
+    pub const BANNER: RenderOrder = RenderOrder(6.0);
+    pub const UNDERWATER: RenderOrder = RenderOrder(8.0);
+    /// The default for blended shaders.
+    pub const BLEND: RenderOrder = RenderOrder(9.0);
+    pub const ADDITIVE: RenderOrder = RenderOrder(10.0);
+    pub const NEAREST: RenderOrder = RenderOrder(16.0);
+
+    /// The order from a shader's `sort` directive, or else worked out like Quake 3 does from
+    /// whether it is a portal, a sky, a decal or blended. Faces without a shader are opaque.
+    pub fn from_shader(shader: Option<&Shader>) -> Self {
+        let shader = match shader {
+            Some(shader) => shader,
+            None => return RenderOrder::OPAQUE,
+        };
+
+        if let Some(sort) = shader
+            .directive("sort")
+            .filter_map(|d| d.args.first())
+            .next()
+        {
+            let order = match sort.to_lowercase().as_str() {
+                "portal" => Some(RenderOrder::PORTAL),
+                "sky" => Some(RenderOrder::SKY),
+                "opaque" => Some(RenderOrder::OPAQUE),
+                "decal" => Some(RenderOrder::DECAL),
+                "seethrough" => Some(RenderOrder::SEE_THROUGH),
+                "banner" => Some(RenderOrder::BANNER),
+                "underwater" => Some(RenderOrder::UNDERWATER),
+                "additive" => Some(RenderOrder::ADDITIVE),
+                "nearest" => Some(RenderOrder::NEAREST),
+                other => other.parse().ok().map(RenderOrder),
+            };
+            if let Some(order) = order {
+                return order;
+            }
+        }
+
+        if shader.directive("portal").next().is_some() {
+            RenderOrder::PORTAL
+        } else if shader.directive("skyparms").next().is_some() {
+            RenderOrder::SKY
+        } else if shader.directive("polygonoffset").next().is_some() {
+            RenderOrder::DECAL
+        } else if let Some(first) = shader.stages.first().filter(|stage| blends(stage)) {
+            // Blended stages that still write depth are grates and the like.
+            if first.directive("depthwrite").next().is_some() {
+                RenderOrder::SEE_THROUGH
+            } else {
+                RenderOrder::BLEND
+            }
+        } else {
+            RenderOrder::OPAQUE
+        }
+    }
+
+    /// Whether faces with this order are drawn over what is behind them rather than hiding it.
+    pub fn is_blended(self) -> bool {
+        self.0 > RenderOrder::BANNER.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderLibrary;
+
+    #[test]
+    fn orders_blended_shaders_after_opaque() {
+        let library = ShaderLibrary::new().with_script(
+            r#"
+textures/base/wall
+{
+    {
+        map textures/base/wall.tga
+    }
+}
+textures/sfx/flame
+{
+    {
+        map textures/sfx/flame.tga
+        blendFunc GL_ONE GL_ONE
+    }
+}
+textures/base/banner
+{
+    sort banner
+}
+"#,
+        );
+        let order = |name| RenderOrder::from_shader(library.get(name));
+
+        assert_eq!(order("textures/base/wall"), RenderOrder::OPAQUE);
+        assert_eq!(order("textures/sfx/flame"), RenderOrder::BLEND);
+        assert_eq!(order("textures/base/banner"), RenderOrder::BANNER);
+        assert!(order("textures/sfx/flame").is_blended());
+    }
+}
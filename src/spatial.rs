@@ -0,0 +1,163 @@
+use crate::vis::{self, MapVis};
+use amethyst::{
+    core::{nalgebra::Matrix4, GlobalTransform, Transform},
+    ecs::{Entities, Entity, Join, ReadStorage, System, Write},
+};
+use std::collections::{HashMap, HashSet};
+
+/// Where an entity is in a map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpatialLocation {
+    /// The root entity of the map.
+    pub map: Entity,
+    pub leaf: usize,
+    pub cluster: i32,
+    pub(crate) position: [f32; 3],
+}
+
+/// The leaf and cluster of every entity with a `Transform`, kept up to date by
+/// `SpatialIndexSystem`. Entities outside every map, or in solid, aren't in the index.
+#[derive(Default)]
+pub struct BspSpatialIndex {
+    locations: HashMap<Entity, SpatialLocation>,
+    clusters: HashMap<(Entity, i32), HashSet<Entity>>,
+    /// The global transform of each map's root when its entities were located.
+    roots: HashMap<Entity, Option<Matrix4<f32>>>,
+}
+
+impl BspSpatialIndex {
+    pub fn location(&self, entity: Entity) -> Option<&SpatialLocation> {
+        self.locations.get(&entity)
+    }
+
+    /// The entities in a cluster of the map with the root `map`.
+    pub fn in_cluster(&self, map: Entity, cluster: i32) -> impl Iterator<Item = Entity> + '_ {
+        self.clusters
+            .get(&(map, cluster))
+            .into_iter()
+            .flat_map(|entities| entities.iter().cloned())
+    }
+
+    /// The entities in any cluster the PVS says can be seen from `cluster`. This doesn't take
+    /// closed area portals into account.
+    pub fn in_pvs<'a>(
+        &'a self,
+        map: Entity,
+        vis: &'a MapVis,
+        cluster: i32,
+    ) -> impl Iterator<Item = Entity> + 'a {
+        self.clusters
+            .iter()
+            .filter(move |((m, c), _)| *m == map && vis.cluster_visible(cluster, *c))
+            .flat_map(|(_, entities)| entities.iter().cloned())
+    }
+
+    /// Forget `map` and every entity located in it.
+    pub(crate) fn remove_map(&mut self, map: Entity) {
+        self.locations.retain(|_, location| location.map != map);
+        self.clusters.retain(|&(m, _), _| m != map);
+        self.roots.remove(&map);
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(old) = self.locations.remove(&entity) {
+            let key = (old.map, old.cluster);
+            if let Some(entities) = self.clusters.get_mut(&key) {
+                entities.remove(&entity);
+                if entities.is_empty() {
+                    self.clusters.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn insert(&mut self, entity: Entity, location: SpatialLocation) {
+        self.remove(entity);
+        self.clusters
+            .entry((location.map, location.cluster))
+            .or_default()
+            .insert(entity);
+        self.locations.insert(entity, location);
+    }
+}
+
+/// Updates `BspSpatialIndex`. Only entities that have moved since the last frame are looked up
+/// in the BSP tree again, along with every entity of a map whose root has moved or gone away.
+/// Entities outside every map are looked up each frame, so they are found in maps spawned
+/// around them.
+#[derive(Default)]
+pub struct SpatialIndexSystem;
+
+impl<'a> System<'a> for SpatialIndexSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, MapVis>,
+        Write<'a, BspSpatialIndex>,
+    );
+
+    fn run(&mut self, (entities, transforms, globals, maps, mut index): Self::SystemData) {
+        let dead = index
+            .locations
+            .keys()
+            .cloned()
+            .filter(|&entity| !entities.is_alive(entity) || !transforms.contains(entity))
+            .collect::<Vec<_>>();
+        for entity in dead {
+            index.remove(entity);
+        }
+
+        let maps = (&entities, &maps, globals.maybe())
+            .join()
+            .collect::<Vec<_>>();
+
+        // Locations in maps that have been removed or moved are stale, even for entities that
+        // haven't moved themselves.
+        let stale = index
+            .roots
+            .iter()
+            .filter(|&(&map, &root)| {
+                maps.iter()
+                    .find(|&&(m, _, _)| m == map)
+                    .map_or(true, |&(_, _, global)| {
+                        global.map(|global| global.0) != root
+                    })
+            })
+            .map(|(&map, _)| map)
+            .collect::<Vec<_>>();
+        for map in stale {
+            index.remove_map(map);
+        }
+        for &(map, _, global) in &maps {
+            index.roots.insert(map, global.map(|global| global.0));
+        }
+
+        for (entity, _, global) in (&entities, &transforms, &globals).join() {
+            let position = [global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]];
+            if index.location(entity).map(|l| l.position) == Some(position) {
+                continue;
+            }
+
+            let location = maps.iter().find_map(|&(map, vis, root)| {
+                let leaf = vis.leaf_at(vis::to_map_space(root, position))?;
+                let cluster = vis.leaves.get(leaf)?.cluster;
+                if cluster < 0 {
+                    return None;
+                }
+
+                Some(SpatialLocation {
+                    map,
+                    leaf,
+                    cluster,
+                    position,
+                })
+            });
+
+            match location {
+                Some(location) => index.insert(entity, location),
+                None => index.remove(entity),
+            }
+        }
+    }
+}
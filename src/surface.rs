@@ -0,0 +1,140 @@
+use crate::{
+    flags::{
+        self, CONTENTS_LAVA, CONTENTS_SLIME, CONTENTS_WATER, SURF_DUST, SURF_FLESH,
+        SURF_METALSTEPS, SURF_NOSTEPS,
+    },
+    shader::Shader,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// What a surface is made of, for picking footstep sounds and impact effects. Quake 3 only has
+/// surface flags for metal, flesh and dust, the rest come from the `surfaceparm`s used by later
+/// id Tech 3 games.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub enum SurfaceMaterial {
+    Default,
+    /// `SURF_NOSTEPS`: footsteps make no sound.
+    Silent,
+    Metal,
+    Flesh,
+    Dust,
+    Wood,
+    Snow,
+    Grass,
+    Gravel,
+    Carpet,
+    Roof,
+    Glass,
+    Water,
+    Slime,
+    Lava,
+}
+
+impl Default for SurfaceMaterial {
+    fn default() -> Self {
+        SurfaceMaterial::Default
+    }
+}
+
+impl Component for SurfaceMaterial {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl SurfaceMaterial {
+    fn from_surfaceparm(parm: &str) -> Option<Self> {
+        let material = match parm.to_lowercase().as_str() {
+            "nosteps" => SurfaceMaterial::Silent,
+            "metalsteps" => SurfaceMaterial::Metal,
+            "flesh" => SurfaceMaterial::Flesh,
+            "dust" => SurfaceMaterial::Dust,
+            "woodsteps" => SurfaceMaterial::Wood,
+            "snowsteps" => SurfaceMaterial::Snow,
+            "grasssteps" => SurfaceMaterial::Grass,
+            "gravelsteps" => SurfaceMaterial::Gravel,
+            "carpetsteps" => SurfaceMaterial::Carpet,
+            "roofsteps" => SurfaceMaterial::Roof,
+            "glass" => SurfaceMaterial::Glass,
+            _ => return None,
+        };
+
+        Some(material)
+    }
+
+    /// Classify a surface by its shader's `surfaceparm`s, falling back to the surface and content
+    /// flags compiled into the BSP.
+    pub fn classify(surface_flags: u32, contents: u32, shader: Option<&Shader>) -> Self {
+        if let Some(material) = shader.and_then(|shader| {
+            shader
+                .surfaceparms()
+                .filter_map(SurfaceMaterial::from_surfaceparm)
+                .next()
+        }) {
+            return material;
+        }
+
+        if surface_flags & SURF_NOSTEPS != 0 {
+            SurfaceMaterial::Silent
+        } else if surface_flags & SURF_METALSTEPS != 0 {
+            SurfaceMaterial::Metal
+        } else if surface_flags & SURF_FLESH != 0 {
+            SurfaceMaterial::Flesh
+        } else if surface_flags & SURF_DUST != 0 {
+            SurfaceMaterial::Dust
+        } else if contents & CONTENTS_LAVA != 0 {
+            SurfaceMaterial::Lava
+        } else if contents & CONTENTS_SLIME != 0 {
+            SurfaceMaterial::Slime
+        } else if contents & CONTENTS_WATER != 0 {
+            SurfaceMaterial::Water
+        } else {
+            SurfaceMaterial::Default
+        }
+    }
+
+    pub(crate) fn of_texture(texture: &bsp::Texture, shader: Option<&Shader>) -> Self {
+        SurfaceMaterial::classify(
+            flags::surface_flags(texture),
+            flags::contents(texture),
+            shader,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderLibrary;
+
+    #[test]
+    fn surfaceparms_override_flags() {
+        let library = ShaderLibrary::new().with_script(
+            r#"
+textures/base/crate
+{
+    surfaceparm woodsteps
+}
+"#,
+        );
+        let shader = library.get("textures/base/crate");
+
+        assert_eq!(
+            SurfaceMaterial::classify(SURF_METALSTEPS, 0, shader),
+            SurfaceMaterial::Wood
+        );
+        assert_eq!(
+            SurfaceMaterial::classify(SURF_METALSTEPS, 0, None),
+            SurfaceMaterial::Metal
+        );
+        assert_eq!(
+            SurfaceMaterial::classify(0, 0, None),
+            SurfaceMaterial::Default
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    geometry::{bounds_of, cross, dot},
+    options::ImportOptions,
+    scale::Scale,
+    to_world_space, Extension,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::{nalgebra::Vector3, Transform},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    renderer::{Light, PointLight, Rgba},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+
+/// The `PointLight` for a surface light reaches `sqrt(intensity * area) / SURFACE_LIGHT_FALLOFF`
+/// units, which roughly matches how far q3map2 lights the surroundings of a light fixture.
+const SURFACE_LIGHT_FALLOFF: f32 = 8.0;
+
+/// A face whose shader has `q3map_surfacelight`, approximated as a light at the face's centroid.
+/// The entity's `Transform` is at the centroid, and `normal` is in world space.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct SurfaceLight {
+    /// The `q3map_surfacelight` value, which q3map2 emits per unit of area.
+    pub intensity: f32,
+    /// From `q3map_lightRGB`, or white. q3map2 otherwise averages the shader's light image,
+    /// which isn't available while importing.
+    pub color: [f32; 3],
+    pub area: f32,
+    pub normal: [f32; 3],
+}
+
+impl Component for SurfaceLight {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl SurfaceLight {
+    /// The light for a face with the given triangles, in world space, or `None` if the face
+    /// has no area.
+    fn from_triangles<I>(triangles: I, intensity: f32, color: [f32; 3]) -> Option<(Self, [f32; 3])>
+    where
+        I: IntoIterator<Item = [[f32; 3]; 3]>,
+    {
+        let mut area = 0.0;
+        let mut weighted = [0.0; 3];
+        let mut normal = [0.0; 3];
+
+        for [a, b, c] in triangles {
+            let n = cross(
+                [b[0] - a[0], b[1] - a[1], b[2] - a[2]],
+                [c[0] - a[0], c[1] - a[1], c[2] - a[2]],
+            );
+            let triangle_area = dot(n, n).sqrt() / 2.0;
+
+            area += triangle_area;
+            for i in 0..3 {
+                weighted[i] += (a[i] + b[i] + c[i]) / 3.0 * triangle_area;
+                normal[i] += n[i];
+            }
+        }
+
+        let len = dot(normal, normal).sqrt();
+        if area <= 0.0 || len <= 0.0 {
+            return None;
+        }
+
+        let centroid = [weighted[0] / area, weighted[1] / area, weighted[2] / area];
+        let light = SurfaceLight {
+            intensity,
+            color,
+            area,
+            normal: [normal[0] / len, normal[1] / len, normal[2] / len],
+        };
+
+        Some((light, centroid))
+    }
+
+    /// A point light approximating this surface light, for renderers without baked lighting.
+    pub fn point_light(&self) -> Light {
+        let [r, g, b] = self.color;
+        Light::Point(PointLight {
+            color: Rgba(r, g, b, 1.0),
+            radius: (self.intensity * self.area).sqrt() / SURFACE_LIGHT_FALLOFF,
+            ..Default::default()
+        })
+    }
+}
+
+/// The surface lights of the world's faces, along with the transform of each, skipping faces
+/// outside `options.selection`.
+pub(crate) fn surface_lights<E: Extension>(
+    bsp: &Bsp,
+    options: &ImportOptions<E>,
+) -> Vec<(SurfaceLight, Transform)> {
+    let world = match bsp.models().next() {
+        Some(world) if options.selection.includes_model(0) => world,
+        _ => return vec![],
+    };
+
+    world
+        .faces()
+        .filter_map(|face| {
+            let shader = options.shader(&face.texture()?.name)?;
+            let intensity = shader
+                .directive("q3map_surfacelight")
+                .filter_map(|d| d.arg_f32(0))
+                .next()?;
+            let color = shader
+                .directive("q3map_lightrgb")
+                .filter_map(|d| Some([d.arg_f32(0)?, d.arg_f32(1)?, d.arg_f32(2)?]))
+                .next()
+                .unwrap_or([1.0; 3]);
+
+            let mut positions = face
+                .vertices()
+                .map(|v| to_world_space(v.position))
+                .collect::<Vec<_>>();
+            if !bounds_of(positions.iter().cloned())
+                .map_or(false, |bounds| options.selection.overlaps(bounds))
+            {
+                return None;
+            }
+            // Scaling the face scales its area by the square, so the point light's radius
+            // scales with the map.
+            positions.scale(options.scale);
+
+            let triangles = positions.chunks_exact(3).map(|t| [t[0], t[1], t[2]]);
+            let (light, centroid) = SurfaceLight::from_triangles(triangles, intensity, color)?;
+
+            let mut transform = Transform::default();
+            transform.set_position(Vector3::from(centroid));
+
+            Some((light, transform))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lights_quads_from_their_centre() {
+        let quad = vec![
+            [[0.0, 0.0, 0.0], [64.0, 0.0, 0.0], [64.0, 0.0, -32.0]],
+            [[0.0, 0.0, 0.0], [64.0, 0.0, -32.0], [0.0, 0.0, -32.0]],
+        ];
+
+        let (light, centroid) = SurfaceLight::from_triangles(quad, 1000.0, [1.0; 3]).unwrap();
+
+        assert_eq!(light.area, 64.0 * 32.0);
+        assert!((centroid[0] - 32.0).abs() < 1e-4 && (centroid[2] + 16.0).abs() < 1e-4);
+        assert_eq!(light.normal, [0.0, 1.0, 0.0]);
+    }
+}
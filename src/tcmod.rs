@@ -0,0 +1,287 @@
+use crate::shader::{Directive, Shader};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::timing::Time,
+    derive::PrefabData,
+    ecs::{
+        Component, DenseVecStorage, Entities, Entity, Join, Read, ReadStorage, System, WriteStorage,
+    },
+    renderer::{Material, TextureOffset},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// The shape of a shader wave, as used by `tcMod stretch`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum WaveFunc {
+    Sin,
+    Triangle,
+    Square,
+    Sawtooth,
+    InverseSawtooth,
+}
+
+/// A shader wave of the form `func base amplitude phase frequency`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Wave {
+    pub func: WaveFunc,
+    pub base: f32,
+    pub amplitude: f32,
+    pub phase: f32,
+    pub frequency: f32,
+}
+
+impl Wave {
+    pub(crate) fn parse(args: &[String]) -> Option<Self> {
+        let func = match args.first()?.to_lowercase().as_str() {
+            "sin" => WaveFunc::Sin,
+            "triangle" => WaveFunc::Triangle,
+            "square" => WaveFunc::Square,
+            "sawtooth" => WaveFunc::Sawtooth,
+            "inversesawtooth" => WaveFunc::InverseSawtooth,
+            _ => return None,
+        };
+        let arg = |i: usize| args.get(i)?.parse::<f32>().ok();
+
+        Some(Wave {
+            func,
+            base: arg(1)?,
+            amplitude: arg(2)?,
+            phase: arg(3)?,
+            frequency: arg(4)?,
+        })
+    }
+
+    pub fn value(&self, time: f32) -> f32 {
+        let x = (self.phase + time * self.frequency).rem_euclid(1.0);
+        let table = match self.func {
+            WaveFunc::Sin => (x * 2.0 * PI).sin(),
+            WaveFunc::Triangle => {
+                if x < 0.25 {
+                    4.0 * x
+                } else if x < 0.75 {
+                    2.0 - 4.0 * x
+                } else {
+                    4.0 * x - 4.0
+                }
+            }
+            WaveFunc::Square => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            WaveFunc::Sawtooth => x,
+            WaveFunc::InverseSawtooth => 1.0 - x,
+        };
+
+        self.base + table * self.amplitude
+    }
+}
+
+/// One `tcMod` directive. `tcMod turb` and `tcMod entityTranslate` move vertices independently,
+/// so they can't be expressed as a single transform and aren't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum TcMod {
+    /// Texture repeats per second.
+    Scroll {
+        s: f32,
+        t: f32,
+    },
+    /// Degrees per second, around the centre of the texture.
+    Rotate {
+        degrees: f32,
+    },
+    Scale {
+        s: f32,
+        t: f32,
+    },
+    Stretch(Wave),
+    /// A fixed `s' = s * m[0][0] + t * m[1][0] + m[2][0]` transform, and likewise for `t'`.
+    Transform {
+        matrix: [[f32; 2]; 3],
+    },
+}
+
+impl TcMod {
+    fn parse(directive: &Directive) -> Option<Self> {
+        let arg = |i: usize| directive.arg_f32(i);
+
+        match directive.args.first()?.to_lowercase().as_str() {
+            "scroll" => Some(TcMod::Scroll {
+                s: arg(1)?,
+                t: arg(2)?,
+            }),
+            "rotate" => Some(TcMod::Rotate { degrees: arg(1)? }),
+            "scale" => Some(TcMod::Scale {
+                s: arg(1)?,
+                t: arg(2)?,
+            }),
+            "stretch" => Wave::parse(&directive.args[1..]).map(TcMod::Stretch),
+            "transform" => Some(TcMod::Transform {
+                matrix: [[arg(1)?, arg(2)?], [arg(3)?, arg(4)?], [arg(5)?, arg(6)?]],
+            }),
+            _ => None,
+        }
+    }
+
+    /// This modifier in the same layout as `TcMod::Transform`, at `time` seconds.
+    fn matrix(&self, time: f32) -> [[f32; 2]; 3] {
+        match *self {
+            // Wrapped like Quake 3 does, to keep precision over long play sessions.
+            TcMod::Scroll { s, t } => [
+                [1.0, 0.0],
+                [0.0, 1.0],
+                [(s * time).rem_euclid(1.0), (t * time).rem_euclid(1.0)],
+            ],
+            TcMod::Rotate { degrees } => {
+                let (sin, cos) = (-degrees * time).to_radians().sin_cos();
+                [
+                    [cos, sin],
+                    [-sin, cos],
+                    [0.5 - 0.5 * cos + 0.5 * sin, 0.5 - 0.5 * sin - 0.5 * cos],
+                ]
+            }
+            TcMod::Scale { s, t } => [[s, 0.0], [0.0, t], [0.0, 0.0]],
+            TcMod::Stretch(wave) => {
+                let value = wave.value(time);
+                let p = if value == 0.0 { 1.0 } else { 1.0 / value };
+                [[p, 0.0], [0.0, p], [0.5 - 0.5 * p, 0.5 - 0.5 * p]]
+            }
+            TcMod::Transform { matrix } => matrix,
+        }
+    }
+}
+
+fn then(a: [[f32; 2]; 3], b: [[f32; 2]; 3]) -> [[f32; 2]; 3] {
+    let mut out = [[0.0; 2]; 3];
+    for i in 0..2 {
+        out[0][i] = a[0][0] * b[0][i] + a[0][1] * b[1][i];
+        out[1][i] = a[1][0] * b[0][i] + a[1][1] * b[1][i];
+        out[2][i] = a[2][0] * b[0][i] + a[2][1] * b[1][i] + b[2][i];
+    }
+    out
+}
+
+/// The `tcMod`s of a face group's shader, from the first stage that isn't the lightmap.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct TexCoordAnimation {
+    pub mods: Vec<TcMod>,
+}
+
+impl Component for TexCoordAnimation {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl TexCoordAnimation {
+    pub(crate) fn from_shader(shader: &Shader) -> Option<Self> {
+        let stage = shader.stages.iter().find(|stage| {
+            stage
+                .directive("map")
+                .chain(stage.directive("animmap"))
+                .any(|map| map.args.first().map_or(true, |m| m != "$lightmap"))
+        })?;
+
+        let mods = stage
+            .directive("tcmod")
+            .filter_map(TcMod::parse)
+            .collect::<Vec<_>>();
+
+        if mods.is_empty() {
+            None
+        } else {
+            Some(TexCoordAnimation { mods })
+        }
+    }
+
+    /// The combined transform of every modifier at `time` seconds, applied in the order they
+    /// were written. Texture coordinates are transformed with
+    /// `s' = s * m[0][0] + t * m[1][0] + m[2][0]` and `t' = s * m[0][1] + t * m[1][1] + m[2][1]`.
+    pub fn matrix(&self, time: f32) -> [[f32; 2]; 3] {
+        self.mods
+            .iter()
+            .fold([[1.0, 0.0], [0.0, 1.0], [0.0, 0.0]], |m, tc_mod| {
+                then(m, tc_mod.matrix(time))
+            })
+    }
+}
+
+/// The current transform of an entity's `TexCoordAnimation`, for renderers that can apply a
+/// full texture matrix.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TexCoordMatrix {
+    pub matrix: [[f32; 2]; 3],
+}
+
+impl Component for TexCoordMatrix {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Updates `TexCoordMatrix` for every `TexCoordAnimation`. Amethyst's `Material` can only offset
+/// and scale its albedo, so its `albedo_offset` is set from the matrix without rotation or shear.
+#[derive(Default)]
+pub struct TexCoordAnimationSystem {
+    time: f32,
+}
+
+impl<'a> System<'a> for TexCoordAnimationSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, Time>,
+        ReadStorage<'a, TexCoordAnimation>,
+        WriteStorage<'a, TexCoordMatrix>,
+        WriteStorage<'a, Material>,
+    );
+
+    fn run(&mut self, (entities, time, animations, mut matrices, mut materials): Self::SystemData) {
+        self.time += time.delta_seconds();
+
+        for (entity, animation) in (&entities, &animations).join() {
+            let matrix = animation.matrix(self.time);
+
+            if let Some(material) = materials.get_mut(entity) {
+                let [[su, _], [_, sv], [u, v]] = matrix;
+                material.albedo_offset = TextureOffset {
+                    u: (u, u + su),
+                    v: (v, v + sv),
+                };
+            }
+
+            // Inserting can only fail for dead entities, which `join` never yields.
+            let _ = matrices.insert(entity, TexCoordMatrix { matrix });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderLibrary;
+
+    #[test]
+    fn applies_mods_in_order() {
+        let library = ShaderLibrary::new().with_script(
+            r#"
+textures/base/conveyor
+{
+    {
+        map $lightmap
+    }
+    {
+        map textures/base/conveyor.tga
+        tcMod scale 2 2
+        tcMod scroll 0.5 0
+    }
+}
+"#,
+        );
+        let animation =
+            TexCoordAnimation::from_shader(library.get("textures/base/conveyor").unwrap()).unwrap();
+
+        assert_eq!(animation.mods.len(), 2);
+        assert_eq!(animation.matrix(0.5), [[2.0, 0.0], [0.0, 2.0], [0.25, 0.0]]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    loading::{LoadingTexture, TextureFile},
+    shader::{Shader, Stage},
+    texture_budget::WorldTextureFormat,
+};
+use amethyst::{
+    assets::{AssetStorage, Handle, Loader, PrefabData, ProgressCounter},
+    ecs::{Component, DenseVecStorage, Entity, Read, ReadExpect, WriteStorage},
+    renderer::Texture,
+    Error,
+};
+use log::warn;
+
+/// A second texture blended over a face group's texture by vertex alpha, as used by q3map2
+/// terrain. `alpha` has an entry for each vertex of the group's mesh, where `1.0` shows only
+/// the overlay. `overlay` is `None` until `TextureLoadSystem` sees it loaded.
+pub struct TerrainBlend {
+    pub overlay: Option<Handle<Texture>>,
+    pub alpha: Vec<f32>,
+    loading: Option<LoadingTexture>,
+}
+
+impl Component for TerrainBlend {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl TerrainBlend {
+    pub(crate) fn poll(&mut self, loader: &Loader, storage: &AssetStorage<Texture>) {
+        let result = match &self.loading {
+            Some(loading) => loading.poll(loader, storage),
+            None => return,
+        };
+
+        match result {
+            Some(Ok(overlay)) => self.overlay = Some(overlay),
+            Some(Err(e)) => warn!("Failed to load a terrain overlay: {}", e),
+            None => return,
+        }
+        self.loading = None;
+    }
+}
+
+pub struct TerrainBlendPrefab {
+    overlay: TextureFile<WorldTextureFormat>,
+    loading: Option<LoadingTexture>,
+    alpha: Vec<f32>,
+}
+
+impl TerrainBlendPrefab {
+    pub(crate) fn new(overlay: TextureFile<WorldTextureFormat>, alpha: Vec<f32>) -> Self {
+        TerrainBlendPrefab {
+            overlay,
+            loading: None,
+            alpha,
+        }
+    }
+}
+
+impl<'a> PrefabData<'a> for TerrainBlendPrefab {
+    type SystemData = (
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Texture>>,
+        WriteStorage<'a, TerrainBlend>,
+    );
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        (_, _, blends): &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        blends.insert(
+            entity,
+            TerrainBlend {
+                overlay: None,
+                alpha: self.alpha.clone(),
+                loading: self.loading.clone(),
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        (loader, storage, _): &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        self.loading = Some(self.overlay.load(loader, storage));
+        Ok(false)
+    }
+}
+
+fn stage_map(stage: &Stage) -> Option<&str> {
+    stage
+        .directive("map")
+        .filter_map(|map| map.args.first())
+        .map(String::as_str)
+        .find(|&map| !map.eq_ignore_ascii_case("$lightmap"))
+}
+
+fn without_extension(path: &str) -> &str {
+    match path.rfind('.') {
+        Some(dot) if !path[dot..].contains('/') => &path[..dot],
+        _ => path,
+    }
+}
+
+/// The base and overlay textures of a q3map2 terrain shader, which draws its base texture and
+/// then blends a second stage over it with `alphaGen vertex`. Paths are returned without their
+/// extension, like BSP texture names.
+pub(crate) fn terrain_textures(shader: &Shader) -> Option<(String, String)> {
+    let base = shader.stages.iter().filter_map(stage_map).next()?;
+    let overlay = shader
+        .stages
+        .iter()
+        .filter(|stage| {
+            stage.directive("alphagen").any(|d| {
+                d.args
+                    .first()
+                    .map_or(false, |a| a.eq_ignore_ascii_case("vertex"))
+            })
+        })
+        .filter_map(stage_map)
+        .next()?;
+
+    Some((
+        without_extension(base).to_string(),
+        without_extension(overlay).to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderLibrary;
+
+    #[test]
+    fn finds_terrain_stages() {
+        let library = ShaderLibrary::new().with_script(
+            r#"
+textures/terrain/valley_0to1
+{
+    q3map_lightmapAxis z
+    {
+        map textures/terrain/rock.tga
+    }
+    {
+        map textures/terrain/grass.jpg
+        blendFunc GL_SRC_ALPHA GL_ONE_MINUS_SRC_ALPHA
+        alphaGen vertex
+    }
+    {
+        map $lightmap
+        blendFunc GL_DST_COLOR GL_ZERO
+    }
+}
+"#,
+        );
+
+        assert_eq!(
+            terrain_textures(library.get("textures/terrain/valley_0to1").unwrap()),
+            Some((
+                "textures/terrain/rock".to_string(),
+                "textures/terrain/grass".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Synthetic Quake 3 maps for tests, so that the importer can be exercised without shipping
+//! real map files. Maps are built as the raw bytes of an IBSP version 46 file, which can be
+//! read with `bsp::Bsp::read` or imported with `BspFormat`.
+
+const LUMPS: usize = 17;
+
+const ENTITIES: usize = 0;
+const TEXTURES: usize = 1;
+const PLANES: usize = 2;
+const NODES: usize = 3;
+const LEAVES: usize = 4;
+const LEAF_FACES: usize = 5;
+const MODELS: usize = 7;
+const VERTICES: usize = 10;
+const MESH_VERTS: usize = 11;
+const FACES: usize = 13;
+const VIS_DATA: usize = 16;
+
+const CONTENTS_SOLID: i32 = 1;
+const POLYGON: i32 = 1;
+
+#[derive(Default)]
+struct Lump(Vec<u8>);
+
+impl Lump {
+    fn i32(&mut self, v: i32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn i32s(&mut self, vs: &[i32]) -> &mut Self {
+        for &v in vs {
+            self.i32(v);
+        }
+        self
+    }
+
+    fn f32s(&mut self, vs: &[f32]) -> &mut Self {
+        for &v in vs {
+            self.0.extend_from_slice(&v.to_le_bytes());
+        }
+        self
+    }
+
+    fn name(&mut self, name: &str) -> &mut Self {
+        let mut bytes = [0; 64];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        self.0.extend_from_slice(&bytes);
+        self
+    }
+}
+
+/// Builds a map with a single 256x128x128 room, split down the middle into two clusters that
+/// can see each other. Each cluster has a vertex lit floor face using `textures/base/floor`, and
+/// the map has a `worldspawn` and whatever entities are added.
+pub struct FixtureBuilder {
+    entities: Vec<Vec<(String, String)>>,
+    floor_tiles: usize,
+    bspx: Vec<(String, Vec<u8>)>,
+}
+
+impl Default for FixtureBuilder {
+    fn default() -> Self {
+        FixtureBuilder {
+            entities: vec![vec![("classname".into(), "worldspawn".into())]],
+            floor_tiles: 1,
+            bspx: vec![],
+        }
+    }
+}
+
+impl FixtureBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_entity(mut self, keyvalues: &[(&str, &str)]) -> Self {
+        self.entities.push(
+            keyvalues
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Split each floor face into `tiles` by `tiles` quads, to build maps with many vertices.
+    pub fn with_floor_tiles(mut self, tiles: usize) -> Self {
+        self.floor_tiles = tiles.max(1);
+        self
+    }
+
+    /// Append a BSPX lump, such as `LMSTYLE` with four style bytes for each of the two faces.
+    pub fn with_bspx_lump(mut self, name: &str, bytes: &[u8]) -> Self {
+        self.bspx.push((name.to_string(), bytes.to_vec()));
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut lumps = (0..LUMPS).map(|_| Lump::default()).collect::<Vec<_>>();
+
+        let mut entities = String::new();
+        for entity in &self.entities {
+            entities.push_str("{\n");
+            for (k, v) in entity {
+                entities.push_str(&format!("\"{}\" \"{}\"\n", k, v));
+            }
+            entities.push_str("}\n");
+        }
+        lumps[ENTITIES].0 = entities.into_bytes();
+        lumps[ENTITIES].0.push(0);
+
+        lumps[TEXTURES]
+            .name("textures/base/floor")
+            .i32s(&[0, CONTENTS_SOLID]);
+
+        // The plane splitting the room into its two clusters, at x = 128.
+        lumps[PLANES].f32s(&[1.0, 0.0, 0.0, 128.0]);
+        lumps[NODES]
+            .i32s(&[0, -2, -1])
+            .i32s(&[0, 0, 0])
+            .i32s(&[256, 128, 128]);
+
+        // The leaf behind the plane is cluster 0, and the one in front is cluster 1.
+        for cluster in 0..2 {
+            let x = cluster * 128;
+            lumps[LEAVES]
+                .i32s(&[cluster, 0])
+                .i32s(&[x, 0, 0])
+                .i32s(&[x + 128, 128, 128])
+                .i32s(&[cluster, 1, 0, 0]);
+            lumps[LEAF_FACES].i32(cluster);
+        }
+
+        lumps[MODELS]
+            .f32s(&[0.0, 0.0, 0.0, 256.0, 128.0, 128.0])
+            .i32s(&[0, 2, 0, 0]);
+
+        let tiles = self.floor_tiles;
+        let tile = 128.0 / tiles as f32;
+        let quads = (tiles * tiles) as i32;
+
+        for face in 0..2 {
+            for (i, j) in (0..tiles).flat_map(|i| (0..tiles).map(move |j| (i, j))) {
+                let x = face as f32 * 128.0 + i as f32 * tile;
+                let y = j as f32 * tile;
+                let corners = [[x, y], [x + tile, y], [x + tile, y + tile], [x, y + tile]];
+
+                for &[cx, cy] in &corners {
+                    lumps[VERTICES]
+                        .f32s(&[cx, cy, 0.0])
+                        .f32s(&[cx / 128.0, cy / 128.0, 0.0, 0.0])
+                        .f32s(&[0.0, 0.0, 1.0]);
+                    lumps[VERTICES].0.extend_from_slice(&[255, 255, 255, 255]);
+                }
+            }
+
+            lumps[FACES]
+                .i32s(&[
+                    0,
+                    -1,
+                    POLYGON,
+                    face * 4 * quads,
+                    4 * quads,
+                    face * 6 * quads,
+                    6 * quads,
+                ])
+                // No lightmap, so `lm_index` is -1 and the other lightmap fields are unused.
+                .i32s(&[-1, 0, 0, 0, 0])
+                .f32s(&[0.0; 3])
+                .f32s(&[0.0; 6])
+                .f32s(&[0.0, 0.0, 1.0])
+                .i32s(&[0, 0]);
+        }
+        for _ in 0..2 {
+            for quad in 0..quads {
+                let v = quad * 4;
+                lumps[MESH_VERTS].i32s(&[v, v + 1, v + 2, v, v + 2, v + 3]);
+            }
+        }
+
+        // Both clusters can see each other.
+        lumps[VIS_DATA].i32s(&[2, 1]);
+        lumps[VIS_DATA].0.extend_from_slice(&[0b11, 0b11]);
+
+        let mut out = b"IBSP".to_vec();
+        out.extend_from_slice(&46i32.to_le_bytes());
+
+        let mut offset = out.len() + LUMPS * 8;
+        for lump in &lumps {
+            out.extend_from_slice(&(offset as i32).to_le_bytes());
+            out.extend_from_slice(&(lump.0.len() as i32).to_le_bytes());
+            offset += lump.0.len();
+        }
+        for lump in &lumps {
+            out.extend_from_slice(&lump.0);
+        }
+
+        if !self.bspx.is_empty() {
+            while out.len() % 4 != 0 {
+                out.push(0);
+            }
+            out.extend_from_slice(b"BSPX");
+            out.extend_from_slice(&(self.bspx.len() as u32).to_le_bytes());
+
+            let mut offset = out.len() + self.bspx.len() * (24 + 8);
+            for (name, bytes) in &self.bspx {
+                let mut padded = [0; 24];
+                padded[..name.len()].copy_from_slice(name.as_bytes());
+                out.extend_from_slice(&padded);
+                out.extend_from_slice(&(offset as u32).to_le_bytes());
+                out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                offset += bytes.len();
+            }
+            for (_, bytes) in &self.bspx {
+                out.extend_from_slice(bytes);
+            }
+        }
+
+        out
+    }
+}
+
+/// The default fixture, with a player start in each cluster and a light.
+pub fn two_cluster_map() -> Vec<u8> {
+    FixtureBuilder::new()
+        .with_entity(&[
+            ("classname", "info_player_deathmatch"),
+            ("origin", "64 64 24"),
+        ])
+        .with_entity(&[
+            ("classname", "info_player_deathmatch"),
+            ("origin", "192 64 24"),
+            ("angle", "180"),
+        ])
+        .with_entity(&[
+            ("classname", "light"),
+            ("origin", "128 64 96"),
+            ("light", "300"),
+        ])
+        .build()
+}
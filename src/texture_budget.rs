@@ -0,0 +1,196 @@
+use crate::{options::ImportOptions, Extension};
+use amethyst::{
+    assets::SimpleFormat,
+    renderer::{ImageData, Texture, TextureData, TextureMetadata},
+    Error,
+};
+use amethyst_detect_filetype::DetectTextureFormat;
+use bsp::Bsp;
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Textures are halved at most this many times, to a quarter of their resolution.
+const MAX_DOWNSCALES: usize = 2;
+
+/// The size assumed for textures that `ImportOptions::texture_sizes` doesn't know, which is
+/// typical of Quake 3's own textures.
+const DEFAULT_SIZE: (u32, u32) = (256, 256);
+
+fn rgba_bytes((width, height): (u32, u32)) -> usize {
+    width as usize * height as usize * 4
+}
+
+fn halved((width, height): (u32, u32)) -> (u32, u32) {
+    ((width / 2).max(1), (height / 2).max(1))
+}
+
+/// Loads world textures like `DetectTextureFormat`, halving the resolution of images larger than
+/// `max_bytes` until they fit. Images are never shrunk below a quarter of their resolution, so
+/// they can still end up over it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorldTextureFormat {
+    pub max_bytes: Option<usize>,
+}
+
+impl WorldTextureFormat {
+    /// The format for each world texture of `bsp` by path, fitting `options.texture_budget`.
+    /// Textures are only downscaled if their total size is over the budget, and then the largest
+    /// ones are halved first. Textures missing from the result are loaded as they are.
+    pub(crate) fn plan<E: Extension>(
+        bsp: &Bsp,
+        options: &ImportOptions<E>,
+    ) -> HashMap<String, Self> {
+        let budget = match options.texture_budget {
+            Some(budget) => budget,
+            None => return HashMap::new(),
+        };
+
+        let mut textures = bsp
+            .models()
+            .flat_map(|model| model.faces())
+            .filter_map(|face| face.texture())
+            .filter(|texture| texture.flags.should_draw() && !options.is_stripped(&texture.name))
+            .map(|texture| options.texture_path(&texture.name))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        // Sorted so that textures of the same size are halved in the same order every time.
+        textures.sort();
+
+        let sizes = textures
+            .iter()
+            .map(|path| {
+                options
+                    .texture_sizes
+                    .as_ref()
+                    .and_then(|sizes| sizes(path))
+                    .unwrap_or(DEFAULT_SIZE)
+            })
+            .collect::<Vec<_>>();
+
+        textures
+            .into_iter()
+            .zip(fit_budget(&sizes, budget))
+            .filter(|(_, max_bytes)| max_bytes.is_some())
+            .map(|(path, max_bytes)| (path, Self { max_bytes }))
+            .collect()
+    }
+}
+
+/// The most bytes each of the images of `sizes` may take to fit them all in `budget`, or `None`
+/// for those that can stay as they are. The largest image is halved until the total fits or
+/// every image has been halved `MAX_DOWNSCALES` times.
+fn fit_budget(sizes: &[(u32, u32)], budget: usize) -> Vec<Option<usize>> {
+    let mut fitted = sizes.iter().map(|&size| (size, 0)).collect::<Vec<_>>();
+    let mut total = sizes.iter().cloned().map(rgba_bytes).sum::<usize>();
+
+    while total > budget {
+        let largest = fitted
+            .iter_mut()
+            .filter(|((width, height), downscales)| {
+                *downscales < MAX_DOWNSCALES && *width > 1 && *height > 1
+            })
+            .max_by_key(|(size, _)| rgba_bytes(*size));
+        let (size, downscales) = match largest {
+            Some(largest) => largest,
+            None => break,
+        };
+
+        total -= rgba_bytes(*size) - rgba_bytes(halved(*size));
+        *size = halved(*size);
+        *downscales += 1;
+    }
+
+    fitted
+        .into_iter()
+        .map(|(size, downscales)| Some(rgba_bytes(size)).filter(|_| downscales > 0))
+        .collect()
+}
+
+/// Half the resolution of an image, averaging each 2x2 block of pixels.
+fn half(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = ((image.width() / 2).max(1), (image.height() / 2).max(1));
+
+    RgbaImage::from_fn(width, height, |x, y| {
+        let mut sum = [0u32; 4];
+        for (dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let px = (x * 2 + dx).min(image.width() - 1);
+            let py = (y * 2 + dy).min(image.height() - 1);
+            for (s, &c) in sum.iter_mut().zip(image.get_pixel(px, py).data.iter()) {
+                *s += c as u32;
+            }
+        }
+        Rgba {
+            data: [
+                (sum[0] / 4) as u8,
+                (sum[1] / 4) as u8,
+                (sum[2] / 4) as u8,
+                (sum[3] / 4) as u8,
+            ],
+        }
+    })
+}
+
+fn fit(mut image: RgbaImage, max_bytes: usize) -> RgbaImage {
+    let bytes = |image: &RgbaImage| image.width() as usize * image.height() as usize * 4;
+
+    for _ in 0..MAX_DOWNSCALES {
+        if bytes(&image) <= max_bytes || image.width() <= 1 || image.height() <= 1 {
+            break;
+        }
+        image = half(&image);
+    }
+
+    image
+}
+
+impl SimpleFormat<Texture> for WorldTextureFormat {
+    const NAME: &'static str = "WorldTexture";
+
+    type Options = TextureMetadata;
+
+    fn import(&self, bytes: Vec<u8>, options: TextureMetadata) -> Result<TextureData, Error> {
+        let data = SimpleFormat::<Texture>::import(&DetectTextureFormat, bytes, options)?;
+
+        Ok(match (data, self.max_bytes) {
+            (TextureData::Image(image, metadata), Some(max_bytes)) => TextureData::Image(
+                ImageData {
+                    rgba: fit(image.rgba, max_bytes),
+                },
+                metadata,
+            ),
+            (data, _) => data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downscales_up_to_a_quarter() {
+        let image = RgbaImage::from_pixel(512, 256, Rgba { data: [255; 4] });
+
+        let fitted = fit(image.clone(), 256 * 128 * 4);
+        assert_eq!(fitted.dimensions(), (256, 128));
+        assert_eq!(fitted.get_pixel(0, 0).data, [255; 4]);
+
+        assert_eq!(fit(image, 1).dimensions(), (128, 64));
+    }
+
+    #[test]
+    fn only_downscales_over_budget() {
+        let mut sizes = vec![(1024, 1024)];
+        sizes.extend(vec![(64, 64); 100]);
+        let total = 1024 * 1024 * 4 + 100 * 64 * 64 * 4;
+
+        assert!(fit_budget(&sizes, total).iter().all(Option::is_none));
+
+        // Halving the large texture is enough, so the small ones are left alone.
+        let fitted = fit_budget(&sizes, total - 1024 * 1024);
+        assert_eq!(fitted[0], Some(512 * 512 * 4));
+        assert!(fitted[1..].iter().all(Option::is_none));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{brushes, entities::MapEntity, to_world_space};
+use amethyst::core::{
+    nalgebra::{UnitQuaternion, Vector3},
+    Transform,
+};
+use bsp::Bsp;
+
+/// Convert Quake's pitch/yaw/roll in degrees to a rotation in world space. Quake applies yaw
+/// around Z, then pitch around Y, then roll around X, with Z up, so the axes are swizzled the
+/// same way as positions.
+pub(crate) fn angles_to_rotation([pitch, yaw, roll]: [f32; 3]) -> UnitQuaternion<f32> {
+    let yaw = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), yaw.to_radians());
+    let pitch = UnitQuaternion::from_axis_angle(&-Vector3::z_axis(), pitch.to_radians());
+    let roll = UnitQuaternion::from_axis_angle(&Vector3::x_axis(), roll.to_radians());
+
+    yaw * pitch * roll
+}
+
+/// The `angles` of an entity, falling back to `angle` as the yaw, where `-1` and `-2` mean
+/// straight up and straight down.
+pub(crate) fn entity_angles(entity: &MapEntity) -> Option<[f32; 3]> {
+    if let Some(angles) = entity.get_vec3("angles") {
+        return Some(angles);
+    }
+
+    match entity.get_f32("angle")? {
+        a if a == -1.0 => Some([-90.0, 0.0, 0.0]),
+        a if a == -2.0 => Some([90.0, 0.0, 0.0]),
+        yaw => Some([0.0, yaw, 0.0]),
+    }
+}
+
+/// The transform of an entity from its `origin` and `angle`/`angles` keys, or `None` if it has
+/// neither.
+pub(crate) fn entity_transform(entity: &MapEntity) -> Option<Transform> {
+    let origin = entity.get_vec3("origin");
+    let angles = entity_angles(entity);
+
+    if origin.is_none() && angles.is_none() {
+        return None;
+    }
+
+    let mut transform = Transform::default();
+
+    if let Some(origin) = origin {
+        let [x, y, z] = to_world_space(origin);
+        transform.set_position(Vector3::new(x, y, z));
+    }
+    if let Some(angles) = angles {
+        transform.set_rotation(angles_to_rotation(angles));
+    }
+
+    Some(transform)
+}
+
+/// Where a brush entity without an `origin` turns around, from the origin brush left in its
+/// model. Its faces are imported relative to this, with the entity's `Transform` placed here,
+/// so that rotating the entity turns it in place like in the original games.
+pub(crate) fn model_pivot(bsp: &Bsp, entity: &MapEntity) -> Option<[f32; 3]> {
+    if entity.get_vec3("origin").is_some() {
+        return None;
+    }
+
+    let model = bsp.models().nth(entity.model_index()?)?;
+    brushes::origin_brush_centre(bsp, &model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaw_turns_towards_bsp_y() {
+        let rotation = angles_to_rotation([0.0, 90.0, 0.0]);
+        let forward = rotation * Vector3::x();
+        let expected = to_world_space([0.0, 1.0, 0.0]);
+
+        for i in 0..3 {
+            assert!((forward[i] - expected[i]).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn positive_pitch_looks_down() {
+        let rotation = angles_to_rotation([90.0, 0.0, 0.0]);
+        let forward = rotation * Vector3::x();
+
+        assert!((forward[1] + 1.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{
+    geometry::{bounds_of, dot},
+    to_world_space,
+    vis::MapVis,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    ecs::{Entity, Write},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A node of a `BspTree`, with its splitting plane stored inline. Negative children are leaves,
+/// stored as `-(leaf + 1)` like in the BSP.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct BspTreeNode {
+    pub normal: [f32; 3],
+    pub dist: f32,
+    pub children: [i32; 2],
+}
+
+impl BspTreeNode {
+    /// The child on the side of the plane `point` is on, then the other one.
+    fn near_far(&self, point: [f32; 3]) -> (i32, i32) {
+        if dot(self.normal, point) >= self.dist {
+            (self.children[0], self.children[1])
+        } else {
+            (self.children[1], self.children[0])
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct BspTreeLeaf {
+    /// `-1` for leaves in solid.
+    pub cluster: i32,
+    pub area: i32,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+/// The BSP tree of a map in world space, laid out for walking rather than for vis queries like
+/// `MapVis`. Leaves are indexed the same as in the BSP's leaf lump and in `MapVis::leaves`, so
+/// they can be used to find the faces and brushes of each leaf.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct BspTree {
+    pub nodes: Vec<BspTreeNode>,
+    pub leaves: Vec<BspTreeLeaf>,
+}
+
+impl BspTree {
+    pub(crate) fn new(vis: &MapVis) -> Self {
+        let nodes = vis
+            .nodes
+            .iter()
+            .filter_map(|node| {
+                let plane = vis.planes.get(node.plane)?;
+                Some(BspTreeNode {
+                    normal: to_world_space(plane.normal),
+                    dist: plane.dist,
+                    children: node.children,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Nodes with a missing plane would shift every later node, so give up on the tree.
+        let nodes = if nodes.len() == vis.nodes.len() {
+            nodes
+        } else {
+            vec![]
+        };
+
+        let leaves = vis
+            .leaves
+            .iter()
+            .map(|leaf| {
+                let (mins, maxs) =
+                    bounds_of(vec![to_world_space(leaf.mins), to_world_space(leaf.maxs)])
+                        .unwrap_or_default();
+                BspTreeLeaf {
+                    cluster: leaf.cluster,
+                    area: leaf.area,
+                    mins,
+                    maxs,
+                }
+            })
+            .collect();
+
+        BspTree { nodes, leaves }
+    }
+
+    /// The index of the leaf containing a point.
+    pub fn leaf_at(&self, point: [f32; 3]) -> Option<usize> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut index = 0i32;
+        while index >= 0 {
+            index = self.nodes.get(index as usize)?.near_far(point).0;
+        }
+
+        Some((-(index + 1)) as usize)
+    }
+
+    /// Every leaf of the tree, nearest to `eye` first, for front-to-back drawing or portal
+    /// flooding. Reverse the order for painter's-algorithm sorting of blended surfaces.
+    pub fn front_to_back(&self, eye: [f32; 3]) -> FrontToBack<'_> {
+        FrontToBack {
+            tree: self,
+            eye,
+            stack: if self.nodes.is_empty() {
+                vec![]
+            } else {
+                vec![0]
+            },
+        }
+    }
+}
+
+/// The leaves of a `BspTree` in front-to-back order from an eye point. See
+/// `BspTree::front_to_back`.
+pub struct FrontToBack<'a> {
+    tree: &'a BspTree,
+    eye: [f32; 3],
+    stack: Vec<i32>,
+}
+
+impl<'a> Iterator for FrontToBack<'a> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while let Some(index) = self.stack.pop() {
+            if index < 0 {
+                return Some((-(index + 1)) as usize);
+            }
+
+            if let Some(node) = self.tree.nodes.get(index as usize) {
+                let (near, far) = node.near_far(self.eye);
+                self.stack.push(far);
+                self.stack.push(near);
+            }
+        }
+
+        None
+    }
+}
+
+/// The `BspTree` of every loaded map, keyed by the map's root entity.
+#[derive(Default)]
+pub struct BspTrees {
+    pub maps: HashMap<Entity, BspTree>,
+}
+
+impl BspTrees {
+    pub fn get(&self, map: Entity) -> Option<&BspTree> {
+        self.maps.get(&map)
+    }
+}
+
+impl<'a> PrefabData<'a> for BspTree {
+    type SystemData = Write<'a, BspTrees>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        trees: &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        trees.maps.insert(entity, self.clone());
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        _: &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walks_nearest_leaves_first() {
+        let node = |x: f32, children| BspTreeNode {
+            normal: [1.0, 0.0, 0.0],
+            dist: x,
+            children,
+        };
+        // Three leaves along X, split at 0 and 128.
+        let tree = BspTree {
+            nodes: vec![node(0.0, [1, -1]), node(128.0, [-3, -2])],
+            leaves: vec![],
+        };
+
+        assert_eq!(tree.leaf_at([64.0, 0.0, 0.0]), Some(1));
+        assert_eq!(
+            tree.front_to_back([200.0, 0.0, 0.0]).collect::<Vec<_>>(),
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            tree.front_to_back([-64.0, 0.0, 0.0]).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+    }
+}
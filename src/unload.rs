@@ -0,0 +1,157 @@
+use crate::{
+    cluster_entities::ClusterEntities, fog::MapFog, occluders::Occluders, spatial::BspSpatialIndex,
+    tree::BspTrees, upload::TextureUploads, waypoints::Waypoints,
+};
+use amethyst::{
+    core::Parent,
+    ecs::{Entities, Entity, Join, ReadStorage, World},
+    Error,
+};
+use std::collections::HashSet;
+
+/// Every entity below `map` in the hierarchy. This walks `Parent` directly rather than using
+/// `ParentHierarchy`, which doesn't know about entities spawned since it was last updated.
+fn descendants(entities: &Entities, parents: &ReadStorage<Parent>, map: Entity) -> Vec<Entity> {
+    let mut found = HashSet::new();
+    found.insert(map);
+
+    loop {
+        let before = found.len();
+        for (entity, parent) in (&**entities, parents).join() {
+            if found.contains(&parent.entity) {
+                found.insert(entity);
+            }
+        }
+        if found.len() == before {
+            break;
+        }
+    }
+
+    found.remove(&map);
+    found.into_iter().collect()
+}
+
+/// Delete a map's root entity and everything below it, for level transitions. Deleted
+/// components are dropped straight away, so the mesh and texture handles they own are released
+/// and the assets are freed by their storages once nothing else refers to them. Other
+/// instances of the same prefab keep theirs, as does anything still holding the prefab's
+/// `Handle`. The map is also removed from every resource that keeps data for each map:
+/// `ClusterEntities`, `Occluders`, `MapFog`, `BspTrees`, `Waypoints`, `TextureUploads` and
+/// `BspSpatialIndex`.
+///
+/// Fails if `map` is already dead.
+pub fn despawn_map(world: &mut World, map: Entity) -> Result<(), Error> {
+    let mut doomed = {
+        let (entities, parents) = world.system_data::<(Entities, ReadStorage<Parent>)>();
+        descendants(&entities, &parents, map)
+    };
+    doomed.push(map);
+
+    world.delete_entities(&doomed).map_err(|e| Error::new(e))?;
+    world.maintain();
+
+    let doomed = doomed.into_iter().collect::<HashSet<_>>();
+    if let Some(mut clusters) = world.res.try_fetch_mut::<ClusterEntities>() {
+        clusters.remove_all(&doomed);
+    }
+    if let Some(mut occluders) = world.res.try_fetch_mut::<Occluders>() {
+        occluders.maps.remove(&map);
+    }
+    if let Some(mut fog) = world.res.try_fetch_mut::<MapFog>() {
+        fog.maps.remove(&map);
+    }
+    if let Some(mut trees) = world.res.try_fetch_mut::<BspTrees>() {
+        trees.maps.remove(&map);
+    }
+    if let Some(mut waypoints) = world.res.try_fetch_mut::<Waypoints>() {
+        waypoints.maps.remove(&map);
+    }
+    if let Some(mut uploads) = world.res.try_fetch_mut::<TextureUploads>() {
+        uploads.remove_all(&doomed);
+    }
+    if let Some(mut index) = world.res.try_fetch_mut::<BspSpatialIndex>() {
+        index.remove_map(map);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fog::FogParms, spatial::SpatialLocation, texture_budget::WorldTextureFormat, tree::BspTree,
+        waypoints::WaypointGraph,
+    };
+    use amethyst::{assets::AssetPrefab, ecs::Builder, renderer::TextureMetadata};
+
+    #[test]
+    fn despawns_every_descendant() {
+        let mut world = World::new();
+        world.register::<Parent>();
+
+        let map = world.create_entity().build();
+        let cluster = world.create_entity().with(Parent { entity: map }).build();
+        let face_group = world
+            .create_entity()
+            .with(Parent { entity: cluster })
+            .build();
+        let other = world.create_entity().build();
+
+        let mut occluders = Occluders::default();
+        occluders.maps.insert(map, vec![]);
+        world.add_resource(occluders);
+        let mut fog = MapFog::default();
+        fog.maps.insert(
+            map,
+            FogParms {
+                color: [1.0; 3],
+                depth_for_opaque: 512.0,
+            },
+        );
+        world.add_resource(fog);
+        let mut trees = BspTrees::default();
+        trees.maps.insert(map, BspTree::default());
+        world.add_resource(trees);
+        let mut waypoints = Waypoints::default();
+        waypoints.maps.insert(map, WaypointGraph::default());
+        world.add_resource(waypoints);
+        let mut uploads = TextureUploads::default();
+        uploads.push(
+            face_group,
+            AssetPrefab::File(
+                "textures/base_wall.png".into(),
+                WorldTextureFormat::default(),
+                TextureMetadata::srgb(),
+            ),
+        );
+        world.add_resource(uploads);
+        let mut index = BspSpatialIndex::default();
+        index.insert(
+            face_group,
+            SpatialLocation {
+                map,
+                leaf: 0,
+                cluster: 0,
+                position: [0.0; 3],
+            },
+        );
+        world.add_resource(index);
+
+        despawn_map(&mut world, map).unwrap();
+
+        assert!(!world.is_alive(map));
+        assert!(!world.is_alive(cluster));
+        assert!(!world.is_alive(face_group));
+        assert!(world.is_alive(other));
+
+        assert!(world.read_resource::<Occluders>().maps.is_empty());
+        assert!(world.read_resource::<MapFog>().maps.is_empty());
+        assert!(world.read_resource::<BspTrees>().maps.is_empty());
+        assert!(world.read_resource::<Waypoints>().maps.is_empty());
+        assert!(world.read_resource::<TextureUploads>().is_empty());
+        let index = world.read_resource::<BspSpatialIndex>();
+        assert!(index.location(face_group).is_none());
+        assert_eq!(index.in_cluster(map, 0).count(), 0);
+    }
+}
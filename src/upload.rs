@@ -0,0 +1,192 @@
+use crate::{
+    lightmap::{LightmapPages, TexturePage},
+    texture_budget::WorldTextureFormat,
+};
+use amethyst::{
+    assets::{AssetPrefab, AssetStorage, Loader, PrefabData, ProgressCounter},
+    ecs::{
+        Component, Entities, Entity, HashMapStorage, Join, Read, ReadExpect, System, Write,
+        WriteStorage,
+    },
+    renderer::Texture,
+    Error,
+};
+use log::warn;
+use std::collections::{HashSet, VecDeque};
+
+type TexturePrefab = AssetPrefab<Texture, WorldTextureFormat>;
+
+/// The lightmap pages of a map imported with `ImportOptions::staggered_uploads`, waiting on the
+/// map's root for `TextureUploadSystem` to load them. `LightmapPages` replaces this once every
+/// page has been loaded, since face groups can refer to any of them.
+pub struct StagedLightmaps {
+    lightmaps: Vec<TexturePage>,
+    deluxemaps: Vec<TexturePage>,
+    /// How many pages have been loaded so far, counting the lightmaps first.
+    loaded: usize,
+}
+
+impl Component for StagedLightmaps {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl StagedLightmaps {
+    pub(crate) fn new(lightmaps: Vec<TexturePage>, deluxemaps: Vec<TexturePage>) -> Self {
+        StagedLightmaps {
+            lightmaps,
+            deluxemaps,
+            loaded: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lightmaps.len() + self.deluxemaps.len()
+    }
+
+    fn next(&mut self) -> Option<&mut TexturePage> {
+        let next = self.loaded;
+        self.loaded += 1;
+        let lightmaps = self.lightmaps.len();
+        if next < lightmaps {
+            self.lightmaps.get_mut(next)
+        } else {
+            self.deluxemaps.get_mut(next - lightmaps)
+        }
+    }
+}
+
+/// World textures of maps imported with `ImportOptions::staggered_uploads` that are still
+/// waiting for `TextureUploadSystem` to load them, in the order they were instantiated. Their
+/// lightmap pages wait in `StagedLightmaps` instead.
+#[derive(Default)]
+pub struct TextureUploads {
+    queue: VecDeque<(Entity, TexturePrefab)>,
+}
+
+impl TextureUploads {
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, entity: Entity, texture: TexturePrefab) {
+        self.queue.push_back((entity, texture));
+    }
+
+    /// Drop everything still queued for `entities`.
+    pub(crate) fn remove_all(&mut self, entities: &HashSet<Entity>) {
+        self.queue.retain(|(entity, _)| !entities.contains(entity));
+    }
+}
+
+/// A face group's texture, loaded by `TextureUploadSystem` rather than with the prefab.
+#[derive(Clone)]
+pub struct StagedTexture(pub(crate) TexturePrefab);
+
+impl<'a> PrefabData<'a> for StagedTexture {
+    type SystemData = Write<'a, TextureUploads>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        uploads: &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        uploads.push(entity, self.0.clone());
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        _: &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+/// Starts loading up to `per_frame` of the staged lightmap pages and textures each frame, so
+/// that a map's textures and lightmaps reach the GPU over several frames rather than all at
+/// once. Lightmap pages go first, since every face group needs them. Pages that are already in
+/// memory are uploaded the frame after they are handed to the loader, while texture files are
+/// uploaded as they finish loading.
+pub struct TextureUploadSystem {
+    pub per_frame: usize,
+}
+
+impl TextureUploadSystem {
+    pub fn new(per_frame: usize) -> Self {
+        TextureUploadSystem { per_frame }
+    }
+}
+
+impl Default for TextureUploadSystem {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+impl<'a> System<'a> for TextureUploadSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Texture>>,
+        Write<'a, TextureUploads>,
+        WriteStorage<'a, StagedLightmaps>,
+        WriteStorage<'a, LightmapPages>,
+        <TexturePrefab as PrefabData<'a>>::SystemData,
+    );
+
+    fn run(
+        &mut self,
+        (entities, loader, storage, mut uploads, mut staged, mut pages, mut file_data): Self::SystemData,
+    ) {
+        // Loads are tracked by the handles they give, so there is nothing to wait on here.
+        let mut progress = ProgressCounter::new();
+        let mut started = 0;
+
+        let mut finished = vec![];
+        for (map, staged) in (&entities, &mut staged).join() {
+            while started < self.per_frame && staged.loaded < staged.len() {
+                if let Some(page) = staged.next() {
+                    page.load(&mut progress, &loader, &storage);
+                }
+                started += 1;
+            }
+            if staged.loaded >= staged.len() {
+                finished.push(map);
+            }
+        }
+
+        for map in finished {
+            if let Some(staged) = staged.remove(map) {
+                let lightmaps = staged.lightmaps.iter().map(Some).collect::<Vec<_>>();
+                let deluxemaps = staged.deluxemaps.iter().map(Some).collect::<Vec<_>>();
+                // `join` only yields live entities, so this can't fail.
+                let _ = pages.insert(map, LightmapPages::new(lightmaps, deluxemaps));
+            }
+        }
+
+        while started < self.per_frame {
+            let (entity, mut texture) = match uploads.queue.pop_front() {
+                Some(next) => next,
+                None => break,
+            };
+            if !entities.is_alive(entity) {
+                continue;
+            }
+            started += 1;
+
+            let result = texture
+                .load_sub_assets(&mut progress, &mut file_data)
+                .and_then(|_| texture.add_to_entity(entity, &mut file_data, &[]));
+            if let Err(e) = result {
+                warn!("Failed to load a staged texture: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::brushes::range;
+use bsp::Bsp;
+use std::fmt;
+
+/// Something wrong with a BSP that would make the importer drop or misplace geometry. Indices
+/// are into the lump named by the variant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    FaceTexture {
+        face: usize,
+        texture: i32,
+    },
+    FaceLightmap {
+        face: usize,
+        lightmap: i32,
+    },
+    FaceVertices {
+        face: usize,
+    },
+    NonFiniteVertex {
+        vertex: usize,
+    },
+    NodePlane {
+        node: usize,
+        plane: i32,
+    },
+    NodeChild {
+        node: usize,
+        child: i32,
+    },
+    LeafCluster {
+        leaf: usize,
+        cluster: i32,
+    },
+    BrushSides {
+        brush: usize,
+    },
+    BrushSidePlane {
+        side: usize,
+        plane: i32,
+    },
+    BrushSideTexture {
+        side: usize,
+        texture: i32,
+    },
+    ModelBrushes {
+        model: usize,
+    },
+    NonFiniteModelBounds {
+        model: usize,
+    },
+    /// The map was compiled without vis, so every cluster will be drawn from everywhere.
+    MissingVis,
+}
+
+impl ValidationIssue {
+    /// Whether the map can still be imported with this issue, with reduced quality.
+    pub fn is_warning(&self) -> bool {
+        *self == ValidationIssue::MissingVis
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ValidationIssue::*;
+
+        match self {
+            FaceTexture { face, texture } => write!(f, "face {} has bad texture {}", face, texture),
+            FaceLightmap { face, lightmap } => {
+                write!(f, "face {} has bad lightmap {}", face, lightmap)
+            }
+            FaceVertices { face } => write!(f, "face {} has out of range vertices", face),
+            NonFiniteVertex { vertex } => write!(f, "vertex {} is not finite", vertex),
+            NodePlane { node, plane } => write!(f, "node {} has bad plane {}", node, plane),
+            NodeChild { node, child } => write!(f, "node {} has bad child {}", node, child),
+            LeafCluster { leaf, cluster } => {
+                write!(f, "leaf {} has bad cluster {}", leaf, cluster)
+            }
+            BrushSides { brush } => write!(f, "brush {} has out of range sides", brush),
+            BrushSidePlane { side, plane } => {
+                write!(f, "brush side {} has bad plane {}", side, plane)
+            }
+            BrushSideTexture { side, texture } => {
+                write!(f, "brush side {} has bad texture {}", side, texture)
+            }
+            ModelBrushes { model } => write!(f, "model {} has out of range brushes", model),
+            NonFiniteModelBounds { model } => write!(f, "model {} has non-finite bounds", model),
+            MissingVis => write!(f, "map has no vis data"),
+        }
+    }
+}
+
+/// The result of `validate`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Whether there are any issues other than warnings.
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|issue| !issue.is_warning())
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|issue| !issue.is_warning())
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid BSP")?;
+        for issue in self.errors() {
+            write!(f, "\n  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+fn index_ok(index: i32, len: usize) -> bool {
+    index >= 0 && (index as usize) < len
+}
+
+fn finite(v: [f32; 3]) -> bool {
+    v.iter().all(|c| c.is_finite())
+}
+
+/// Check a map for broken cross-references between lumps, non-finite vertices and missing vis
+/// data, without importing it. `ImportOptions::validate` runs this before importing.
+pub fn validate(bsp: &Bsp) -> ValidationReport {
+    let mut issues = vec![];
+
+    for (i, face) in bsp.faces.iter().enumerate() {
+        if face.texture < 0 || bsp.texture(face.texture as usize).is_none() {
+            issues.push(ValidationIssue::FaceTexture {
+                face: i,
+                texture: face.texture,
+            });
+        }
+        // Maps with external lightmaps have an empty lump, so any page can be valid.
+        if face.lm_index >= 0
+            && !bsp.lightmaps.is_empty()
+            && face.lm_index as usize >= bsp.lightmaps.len()
+        {
+            issues.push(ValidationIssue::FaceLightmap {
+                face: i,
+                lightmap: face.lm_index,
+            });
+        }
+        if range(&bsp.vertices, face.vertex, face.n_vertexes).len()
+            != face.n_vertexes.max(0) as usize
+        {
+            issues.push(ValidationIssue::FaceVertices { face: i });
+        }
+    }
+
+    for (i, vertex) in bsp.vertices.iter().enumerate() {
+        if !finite(vertex.position) || !finite(vertex.normal) {
+            issues.push(ValidationIssue::NonFiniteVertex { vertex: i });
+        }
+    }
+
+    for (i, node) in bsp.nodes.iter().enumerate() {
+        if !index_ok(node.plane, bsp.planes.len()) {
+            issues.push(ValidationIssue::NodePlane {
+                node: i,
+                plane: node.plane,
+            });
+        }
+        for &child in &node.children {
+            let ok = if child >= 0 {
+                index_ok(child, bsp.nodes.len())
+            } else {
+                index_ok(-(child + 1), bsp.leaves.iter().count())
+            };
+            if !ok {
+                issues.push(ValidationIssue::NodeChild { node: i, child });
+            }
+        }
+    }
+
+    let row_len = bsp.vis_data.sz_vecs.max(0) as usize;
+    let vis_clusters = if row_len == 0 {
+        0
+    } else {
+        bsp.vis_data.vecs.len() / row_len
+    };
+
+    if vis_clusters == 0 {
+        issues.push(ValidationIssue::MissingVis);
+    } else {
+        for (i, leaf) in bsp.leaves.iter().enumerate() {
+            if leaf.cluster >= 0 && leaf.cluster as usize >= vis_clusters {
+                issues.push(ValidationIssue::LeafCluster {
+                    leaf: i,
+                    cluster: leaf.cluster,
+                });
+            }
+        }
+    }
+
+    for (i, brush) in bsp.brushes.iter().enumerate() {
+        if range(&bsp.brush_sides, brush.brush_side, brush.n_brush_sides).len()
+            != brush.n_brush_sides.max(0) as usize
+        {
+            issues.push(ValidationIssue::BrushSides { brush: i });
+        }
+    }
+
+    for (i, side) in bsp.brush_sides.iter().enumerate() {
+        if !index_ok(side.plane, bsp.planes.len()) {
+            issues.push(ValidationIssue::BrushSidePlane {
+                side: i,
+                plane: side.plane,
+            });
+        }
+        if side.texture < 0 || bsp.texture(side.texture as usize).is_none() {
+            issues.push(ValidationIssue::BrushSideTexture {
+                side: i,
+                texture: side.texture,
+            });
+        }
+    }
+
+    for (i, model) in bsp.models().enumerate() {
+        if range(&bsp.brushes, model.brush, model.n_brushes).len()
+            != model.n_brushes.max(0) as usize
+        {
+            issues.push(ValidationIssue::ModelBrushes { model: i });
+        }
+        if !finite(model.mins) || !finite(model.maxs) {
+            issues.push(ValidationIssue::NonFiniteModelBounds { model: i });
+        }
+    }
+
+    ValidationReport { issues }
+}
@@ -0,0 +1,505 @@
+use crate::{
+    entities::MapEntity,
+    geometry::{bounds_overlap, dot, Plane},
+    scale::Scale,
+    to_bsp_space,
+    waypoints::WaypointGraph,
+    Cluster,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::{nalgebra::Point3, GlobalTransform, Parent},
+    derive::PrefabData,
+    ecs::{Component, Entities, Entity, HashMapStorage, Join, ReadStorage, System, WriteStorage},
+    renderer::{Camera, HiddenPropagate},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+
+/// How much further than the straight line a sound has to travel around walls for
+/// `MapVis::occlusion` to half occlude it, in map units before `ImportOptions::scale`.
+pub const OCCLUSION_DETOUR: f32 = 256.0;
+
+/// A node of the BSP tree. Negative children are leaves, stored as `-(leaf + 1)`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct VisNode {
+    pub plane: usize,
+    pub children: [i32; 2],
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct VisLeaf {
+    pub cluster: i32,
+    pub area: i32,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+/// A `func_areaportal`, separating two areas. Doors targetting the portal should close it while
+/// they are closed.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AreaPortal {
+    pub targetname: Option<String>,
+    pub areas: [i32; 2],
+    pub open: bool,
+}
+
+/// The BSP tree, PVS and area portals of a map, attached to the map's root entity. All positions
+/// passed to and returned by this are in world space.
+#[derive(Debug, Clone, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct MapVis {
+    pub planes: Vec<Plane>,
+    pub nodes: Vec<VisNode>,
+    pub leaves: Vec<VisLeaf>,
+    pub cluster_bytes: usize,
+    pub vis: Vec<u8>,
+    pub portals: Vec<AreaPortal>,
+    pub num_areas: usize,
+    cluster_areas: Vec<Vec<i32>>,
+    /// `ImportOptions::scale`, which `OCCLUSION_DETOUR` is scaled by.
+    scale: f32,
+}
+
+impl Component for MapVis {
+    type Storage = HashMapStorage<Self>;
+}
+
+impl Default for MapVis {
+    fn default() -> Self {
+        MapVis {
+            planes: vec![],
+            nodes: vec![],
+            leaves: vec![],
+            cluster_bytes: 0,
+            vis: vec![],
+            portals: vec![],
+            num_areas: 0,
+            cluster_areas: vec![],
+            scale: 1.0,
+        }
+    }
+}
+
+impl Scale for MapVis {
+    fn scale(&mut self, scale: f32) {
+        self.planes.scale(scale);
+        for leaf in &mut self.leaves {
+            leaf.mins.scale(scale);
+            leaf.maxs.scale(scale);
+        }
+        self.scale *= scale;
+    }
+}
+
+impl MapVis {
+    pub(crate) fn new(bsp: &Bsp, entities: &[MapEntity]) -> Self {
+        let float = |v: [i32; 3]| [v[0] as f32, v[1] as f32, v[2] as f32];
+
+        let leaves = bsp
+            .leaves
+            .iter()
+            .map(|leaf| VisLeaf {
+                cluster: leaf.cluster,
+                area: leaf.area,
+                mins: float(leaf.mins),
+                maxs: float(leaf.maxs),
+            })
+            .collect::<Vec<_>>();
+
+        let num_areas = leaves.iter().map(|l| l.area + 1).max().unwrap_or(0).max(0) as usize;
+        let num_clusters = leaves
+            .iter()
+            .map(|l| l.cluster + 1)
+            .max()
+            .unwrap_or(0)
+            .max(0) as usize;
+
+        let mut cluster_areas = vec![vec![]; num_clusters];
+        for leaf in &leaves {
+            if leaf.cluster >= 0 && leaf.area >= 0 {
+                let areas = &mut cluster_areas[leaf.cluster as usize];
+                if !areas.contains(&leaf.area) {
+                    areas.push(leaf.area);
+                }
+            }
+        }
+
+        let portals = entities
+            .iter()
+            .filter(|e| e.classname() == Some("func_areaportal"))
+            .filter_map(|e| {
+                let model = e.model_index()?;
+                let model = bsp.models().nth(model)?;
+                let offset = |v: [f32; 3], d: f32| [v[0] + d, v[1] + d, v[2] + d];
+                let bounds = (offset(model.mins, -1.0), offset(model.maxs, 1.0));
+
+                // Like Quake 3's `SV_LinkEntity`, a portal joins the two areas touched by its
+                // brush. Anything else is a mapping error and is ignored.
+                let mut areas = vec![];
+                for leaf in &leaves {
+                    if leaf.area >= 0
+                        && !areas.contains(&leaf.area)
+                        && bounds_overlap(bounds, (leaf.mins, leaf.maxs))
+                    {
+                        areas.push(leaf.area);
+                    }
+                }
+
+                if areas.len() != 2 {
+                    return None;
+                }
+
+                Some(AreaPortal {
+                    targetname: e.get("targetname").map(String::from),
+                    areas: [areas[0], areas[1]],
+                    open: true,
+                })
+            })
+            .collect();
+
+        MapVis {
+            planes: bsp
+                .planes
+                .iter()
+                .map(|p| Plane {
+                    normal: p.normal,
+                    dist: p.dist,
+                })
+                .collect(),
+            nodes: bsp
+                .nodes
+                .iter()
+                .map(|n| VisNode {
+                    plane: n.plane as usize,
+                    children: n.children,
+                })
+                .collect(),
+            leaves,
+            cluster_bytes: bsp.vis_data.sz_vecs as usize,
+            vis: bsp.vis_data.vecs.to_vec(),
+            portals,
+            num_areas,
+            cluster_areas,
+            scale: 1.0,
+        }
+    }
+
+    /// The index of the leaf containing a point.
+    pub fn leaf_at(&self, point: [f32; 3]) -> Option<usize> {
+        let point = to_bsp_space(point);
+        let mut index = 0i32;
+
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        while index >= 0 {
+            let node = self.nodes.get(index as usize)?;
+            let plane = self.planes.get(node.plane)?;
+
+            index = if dot(plane.normal, point) >= plane.dist {
+                node.children[0]
+            } else {
+                node.children[1]
+            };
+        }
+
+        Some((-(index + 1)) as usize)
+    }
+
+    /// The cluster containing a point, or `None` if the point is outside the map or in solid.
+    pub fn cluster_at(&self, point: [f32; 3]) -> Option<i32> {
+        self.leaf_at(point)
+            .and_then(|l| self.leaves.get(l))
+            .map(|l| l.cluster)
+            .filter(|&c| c >= 0)
+    }
+
+    /// Whether the PVS says anything in cluster `to` can be seen from cluster `from`. Maps
+    /// without vis data are treated as if everything is visible.
+    pub fn cluster_visible(&self, from: i32, to: i32) -> bool {
+        if from < 0 || to < 0 || self.vis.is_empty() {
+            return true;
+        }
+
+        let byte = from as usize * self.cluster_bytes + to as usize / 8;
+        self.vis
+            .get(byte)
+            .map(|b| b & (1 << (to % 8)) != 0)
+            .unwrap_or(true)
+    }
+
+    pub fn set_portal_open(&mut self, portal: usize, open: bool) {
+        if let Some(portal) = self.portals.get_mut(portal) {
+            portal.open = open;
+        }
+    }
+
+    /// Open or close every portal with the given `targetname`.
+    pub fn set_portal_open_by_name(&mut self, targetname: &str, open: bool) {
+        for portal in &mut self.portals {
+            if portal.targetname.as_ref().map(String::as_str) == Some(targetname) {
+                portal.open = open;
+            }
+        }
+    }
+
+    /// Flood through open portals from `area`, returning whether each area is reachable.
+    pub fn connected_areas(&self, area: i32) -> Vec<bool> {
+        let mut connected = vec![false; self.num_areas];
+
+        if area < 0 || area as usize >= self.num_areas {
+            return vec![true; self.num_areas];
+        }
+
+        let mut stack = vec![area];
+        connected[area as usize] = true;
+
+        while let Some(current) = stack.pop() {
+            for portal in self.portals.iter().filter(|p| p.open) {
+                let other = match portal.areas {
+                    [a, b] if a == current => b,
+                    [a, b] if b == current => a,
+                    _ => continue,
+                };
+
+                if !connected[other as usize] {
+                    connected[other as usize] = true;
+                    stack.push(other);
+                }
+            }
+        }
+
+        connected
+    }
+
+    /// The clusters visible from a point, taking both the PVS and closed area portals into
+    /// account. Returns `None` if the point is outside the map, in which case nothing should be
+    /// culled.
+    pub fn visible_clusters(&self, point: [f32; 3]) -> Option<Vec<bool>> {
+        let leaf = self.leaves.get(self.leaf_at(point)?)?;
+        if leaf.cluster < 0 {
+            return None;
+        }
+
+        let areas = self.connected_areas(leaf.area);
+
+        Some(
+            (0..self.cluster_areas.len() as i32)
+                .map(|cluster| {
+                    self.cluster_visible(leaf.cluster, cluster)
+                        && self.cluster_areas[cluster as usize]
+                            .iter()
+                            .any(|&a| areas.get(a as usize).cloned().unwrap_or(true))
+                })
+                .collect(),
+        )
+    }
+
+    /// How muffled a sound at `emitter` should be for a listener at `listener`, from `0.0` for a
+    /// clear path to `1.0` for a sound that can't reach the listener at all. `waypoints` is the
+    /// map's `WaypointGraph`, whose portal paths stand in for the way a sound travels. Sounds
+    /// between areas separated by closed portals, or with no path between them, are fully
+    /// occluded, sounds from clusters the listener's cluster can't see are half occluded, and
+    /// the detour the sound takes around walls adds occlusion until `OCCLUSION_DETOUR` map units
+    /// of it occlude as much again. Points are in map space, and points outside the map are never
+    /// occluded.
+    pub fn occlusion(
+        &self,
+        waypoints: &WaypointGraph,
+        listener: [f32; 3],
+        emitter: [f32; 3],
+    ) -> f32 {
+        let leaf = |point| {
+            self.leaf_at(point)
+                .and_then(|leaf| self.leaves.get(leaf))
+                .filter(|leaf| leaf.cluster >= 0)
+        };
+
+        let (from, to) = match (leaf(listener), leaf(emitter)) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return 0.0,
+        };
+
+        if from.area >= 0
+            && to.area >= 0
+            && self.connected_areas(from.area).get(to.area as usize) == Some(&false)
+        {
+            return 1.0;
+        }
+
+        let path = match waypoints.portal_distance((listener, from.cluster), (emitter, to.cluster))
+        {
+            Some(path) => path,
+            None => return 1.0,
+        };
+
+        let pvs = if self.cluster_visible(from.cluster, to.cluster) {
+            0.0
+        } else {
+            0.5
+        };
+
+        let delta = [
+            emitter[0] - listener[0],
+            emitter[1] - listener[1],
+            emitter[2] - listener[2],
+        ];
+        let detour = (path - dot(delta, delta).sqrt()).max(0.0);
+
+        (pvs + detour / (OCCLUSION_DETOUR * self.scale) * 0.5).min(1.0)
+    }
+}
+
+/// A world space point in the space of the map whose root has the global transform `root`,
+/// since maps can be moved and scaled through their root.
+pub(crate) fn to_map_space(root: Option<&GlobalTransform>, [x, y, z]: [f32; 3]) -> [f32; 3] {
+    match root.and_then(|root| root.0.try_inverse()) {
+        Some(inverse) => inverse.transform_point(&Point3::new(x, y, z)).coords.into(),
+        None => [x, y, z],
+    }
+}
+
+/// Hides clusters that cannot be seen from the camera.
+#[derive(Default)]
+pub struct VisibilitySystem;
+
+impl<'a> System<'a> for VisibilitySystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, MapVis>,
+        ReadStorage<'a, Cluster>,
+        ReadStorage<'a, Parent>,
+        WriteStorage<'a, HiddenPropagate>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, cameras, globals, maps, clusters, parents, mut hidden): Self::SystemData,
+    ) {
+        let camera = match (&cameras, &globals).join().next() {
+            Some((_, global)) => [global.0[(0, 3)], global.0[(1, 3)], global.0[(2, 3)]],
+            None => return,
+        };
+
+        let visible = (&entities, &maps)
+            .join()
+            .map(|(map, vis)| {
+                let camera = to_map_space(globals.get(map), camera);
+                (map, vis.visible_clusters(camera))
+            })
+            .collect::<Vec<_>>();
+
+        for (entity, cluster, parent) in (&entities, &clusters, &parents).join() {
+            let map_visible = match visible.iter().find(|(map, _)| *map == parent.entity) {
+                Some((_, visible)) => visible,
+                None => continue,
+            };
+
+            let is_visible = match map_visible {
+                Some(visible) => cluster.id < 0 || visible.get(cluster.id as usize) != Some(&false),
+                None => true,
+            };
+
+            if is_visible {
+                hidden.remove(entity);
+            } else if !hidden.contains(entity) {
+                // Inserting can only fail for dead entities, which `join` never yields.
+                let _ = hidden.insert(entity, HiddenPropagate);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::waypoints::Waypoint;
+
+    fn portal(a: i32, b: i32, name: &str) -> AreaPortal {
+        AreaPortal {
+            targetname: Some(name.to_string()),
+            areas: [a, b],
+            open: true,
+        }
+    }
+
+    #[test]
+    fn closed_portals_split_areas() {
+        let mut vis = MapVis {
+            portals: vec![portal(0, 1, "door1"), portal(1, 2, "door2")],
+            num_areas: 3,
+            ..Default::default()
+        };
+
+        assert_eq!(vis.connected_areas(0), vec![true, true, true]);
+
+        vis.set_portal_open_by_name("door2", false);
+        assert_eq!(vis.connected_areas(0), vec![true, true, false]);
+        assert_eq!(vis.connected_areas(2), vec![false, false, true]);
+    }
+
+    #[test]
+    fn occludes_clusters_out_of_sight() {
+        // Two clusters either side of x = 128 in BSP space, neither of which can see the other.
+        let vis = MapVis {
+            planes: vec![Plane {
+                normal: [1.0, 0.0, 0.0],
+                dist: 128.0,
+            }],
+            nodes: vec![VisNode {
+                plane: 0,
+                children: [-2, -1],
+            }],
+            leaves: vec![
+                VisLeaf {
+                    cluster: 0,
+                    area: 0,
+                    mins: [0.0; 3],
+                    maxs: [128.0; 3],
+                },
+                VisLeaf {
+                    cluster: 1,
+                    area: 0,
+                    mins: [128.0, 0.0, 0.0],
+                    maxs: [256.0, 128.0, 128.0],
+                },
+            ],
+            cluster_bytes: 1,
+            vis: vec![0b01, 0b10],
+            num_areas: 1,
+            cluster_areas: vec![vec![0], vec![0]],
+            ..Default::default()
+        };
+
+        // The only way between the clusters bends around a pillar at the middle of x = 128.
+        let waypoints = WaypointGraph {
+            waypoints: vec![Waypoint {
+                position: [128.0, 48.0, 0.0],
+                clusters: [0, 1],
+            }],
+            edges: vec![vec![]],
+        };
+
+        assert_eq!(
+            vis.occlusion(&waypoints, [32.0, 0.0, 0.0], [96.0, 0.0, 0.0]),
+            0.0
+        );
+        assert_eq!(
+            vis.occlusion(&waypoints, [64.0, 0.0, 0.0], [192.0, 0.0, 0.0]),
+            0.5 + 32.0 / OCCLUSION_DETOUR * 0.5
+        );
+        assert_eq!(
+            vis.occlusion(
+                &WaypointGraph::default(),
+                [64.0, 0.0, 0.0],
+                [192.0, 0.0, 0.0]
+            ),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    brushes::{brush_hull, brush_sides, model_brushes},
+    entities::MapEntity,
+    flags::{self, SURF_LADDER},
+    geometry::{bounds_of, dot},
+    handler::EntityContext,
+    scale::Scale,
+    to_world_space,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use bsp::Bsp;
+use serde::{Deserialize, Serialize};
+
+/// A climbable volume, from a world brush with a `SURF_LADDER` side. Bounds are in world space,
+/// and `normal` points out of the climbable face, towards the player using it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct Ladder {
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Component for Ladder {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Scale for Ladder {
+    fn scale(&mut self, scale: f32) {
+        self.mins.scale(scale);
+        self.maxs.scale(scale);
+    }
+}
+
+impl Ladder {
+    pub(crate) fn extract(bsp: &Bsp) -> Vec<Ladder> {
+        let world = match bsp.models().next() {
+            Some(world) => world,
+            None => return vec![],
+        };
+
+        model_brushes(bsp, &world)
+            .iter()
+            .filter_map(|brush| {
+                let normals = brush_sides(bsp, brush)
+                    .iter()
+                    .filter(|side| {
+                        bsp.texture(side.texture as usize)
+                            .map_or(false, |t| flags::surface_flags(t) & SURF_LADDER != 0)
+                    })
+                    .filter_map(|side| bsp.planes.get(side.plane as usize))
+                    .map(|plane| to_world_space(plane.normal))
+                    .collect::<Vec<_>>();
+
+                if normals.is_empty() {
+                    return None;
+                }
+
+                let hull = brush_hull(bsp, brush)?;
+
+                // Ladder brushes are usually thin along the direction they face, so use the
+                // ladder side that points along the thinnest horizontal axis. Y is up.
+                let size = hull.size();
+                let axis = if size[0] <= size[2] {
+                    [1.0, 0.0, 0.0]
+                } else {
+                    [0.0, 0.0, 1.0]
+                };
+                let normal = normals.iter().cloned().fold(normals[0], |best, n| {
+                    if dot(n, axis).abs() > dot(best, axis).abs() {
+                        n
+                    } else {
+                        best
+                    }
+                });
+
+                Some(Ladder {
+                    mins: hull.mins,
+                    maxs: hull.maxs,
+                    normal,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A `trigger_hurt`, damaging anything inside its bounds (in world space).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct DamageVolume {
+    /// Damage per second. Quake 3 applies `dmg` every 100ms, or every second with `SLOW`.
+    pub dps: f32,
+    /// The entity's `spawnflags`, see the associated constants.
+    pub flags: u32,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+impl Component for DamageVolume {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl DamageVolume {
+    pub const START_OFF: u32 = 1;
+    pub const TOGGLE: u32 = 2;
+    pub const SILENT: u32 = 4;
+    pub const NO_PROTECTION: u32 = 8;
+    pub const SLOW: u32 = 16;
+
+    pub(crate) fn from_entity(entity: &MapEntity, ctx: &EntityContext) -> Option<Self> {
+        let model = ctx.bsp.models().nth(ctx.model?)?;
+        let (mut mins, mut maxs) =
+            bounds_of(vec![to_world_space(model.mins), to_world_space(model.maxs)])?;
+        mins.scale(ctx.scale);
+        maxs.scale(ctx.scale);
+
+        let flags = entity.spawnflags();
+        let damage = entity.get_f32("dmg").unwrap_or(5.0);
+        let per_second = if flags & Self::SLOW != 0 { 1.0 } else { 10.0 };
+
+        Some(DamageVolume {
+            dps: damage * per_second,
+            flags,
+            mins,
+            maxs,
+        })
+    }
+
+    pub fn is_active_at_start(&self) -> bool {
+        self.flags & Self::START_OFF == 0
+    }
+
+    pub fn contains(&self, point: [f32; 3]) -> bool {
+        (0..3).all(|i| self.mins[i] <= point[i] && point[i] <= self.maxs[i])
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    deform::{Deform, VertexDeform},
+    flags::{self, CONTENTS_LAVA, CONTENTS_SLIME, CONTENTS_WATER},
+    mesh::FaceGroup,
+    shader::Shader,
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    derive::PrefabData,
+    ecs::{Component, DenseVecStorage, Entity, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Liquid {
+    Water,
+    Slime,
+    Lava,
+}
+
+impl Liquid {
+    pub fn from_contents(contents: u32) -> Option<Self> {
+        if contents & CONTENTS_LAVA != 0 {
+            Some(Liquid::Lava)
+        } else if contents & CONTENTS_SLIME != 0 {
+            Some(Liquid::Slime)
+        } else if contents & CONTENTS_WATER != 0 {
+            Some(Liquid::Water)
+        } else {
+            None
+        }
+    }
+
+    fn from_shader(shader: &Shader) -> Option<Self> {
+        if shader.has_surfaceparm("lava") {
+            Some(Liquid::Lava)
+        } else if shader.has_surfaceparm("slime") {
+            Some(Liquid::Slime)
+        } else if shader.has_surfaceparm("water") {
+            Some(Liquid::Water)
+        } else {
+            None
+        }
+    }
+}
+
+/// The parameters of a `tcMod turb`, which Quake 3 uses to make liquid textures swirl.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Turbulence {
+    pub amplitude: f32,
+    pub phase: f32,
+    /// Cycles per second.
+    pub frequency: f32,
+}
+
+impl Turbulence {
+    /// The first `tcMod turb` of any stage. Its base is ignored, as in Quake 3.
+    fn from_shader(shader: &Shader) -> Option<Self> {
+        shader
+            .stages
+            .iter()
+            .flat_map(|stage| stage.directive("tcmod"))
+            .filter(|d| {
+                d.args
+                    .first()
+                    .map_or(false, |a| a.eq_ignore_ascii_case("turb"))
+            })
+            .find_map(|d| {
+                Some(Turbulence {
+                    amplitude: d.arg_f32(2)?,
+                    phase: d.arg_f32(3)?,
+                    frequency: d.arg_f32(4)?,
+                })
+            })
+    }
+}
+
+/// A face group of liquid, with the shader parameters a renderer needs to ripple it. `waves`
+/// are the shader's `deformVertexes wave`, which `VertexDeform` also has. The bounds are in the
+/// space of the entity's `GlobalTransform`, or of the map if it has none.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct WaterSurface {
+    pub liquid: Liquid,
+    pub waves: Vec<Deform>,
+    pub turbulence: Option<Turbulence>,
+    pub mins: [f32; 3],
+    pub maxs: [f32; 3],
+}
+
+impl Component for WaterSurface {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl WaterSurface {
+    /// Faces are liquid if their shader has a liquid `surfaceparm`, or if their contents say so.
+    pub(crate) fn new(
+        group: &FaceGroup,
+        texture: &bsp::Texture,
+        shader: Option<&Shader>,
+    ) -> Option<Self> {
+        let liquid = shader
+            .and_then(Liquid::from_shader)
+            .or_else(|| Liquid::from_contents(flags::contents(texture)))?;
+        let (mins, maxs) = group.bounds()?;
+
+        let waves = shader
+            .and_then(VertexDeform::from_shader)
+            .map(|deform| {
+                deform
+                    .deforms
+                    .into_iter()
+                    .filter(|deform| match deform {
+                        Deform::Wave { .. } => true,
+                        _ => false,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let turbulence = shader.and_then(Turbulence::from_shader);
+
+        Some(WaterSurface {
+            liquid,
+            waves,
+            turbulence,
+            mins,
+            maxs,
+        })
+    }
+
+    /// Whether `point` is under the surface: within its horizontal bounds and below its top.
+    /// This doesn't know how deep the liquid is, so `BspAsset::liquid_at` should be used where
+    /// the map is at hand.
+    pub fn submerges(&self, point: [f32; 3]) -> bool {
+        point[0] >= self.mins[0]
+            && point[0] <= self.maxs[0]
+            && point[2] >= self.mins[2]
+            && point[2] <= self.maxs[2]
+            && point[1] < self.maxs[1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderLibrary;
+
+    #[test]
+    fn reads_liquid_shaders() {
+        let library = ShaderLibrary::new().with_script(
+            r#"
+textures/liquids/pool
+{
+    surfaceparm water
+    deformVertexes wave 64 sin 0 2 0 0.5
+    {
+        map textures/liquids/pool.tga
+        tcMod turb 0 0.1 0 0.05
+    }
+}
+"#,
+        );
+        let shader = library.get("textures/liquids/pool").unwrap();
+
+        assert_eq!(Liquid::from_shader(shader), Some(Liquid::Water));
+        assert_eq!(
+            Turbulence::from_shader(shader),
+            Some(Turbulence {
+                amplitude: 0.1,
+                phase: 0.0,
+                frequency: 0.05,
+            })
+        );
+    }
+}
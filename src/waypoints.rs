@@ -0,0 +1,402 @@
+use crate::{
+    geometry::bounds_overlap,
+    scale::Scale,
+    to_world_space,
+    vis::{self, MapVis, VisLeaf},
+};
+use amethyst::{
+    assets::{PrefabData, ProgressCounter},
+    core::{nalgebra::Vector3, GlobalTransform},
+    ecs::{Entity, Write},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BinaryHeap, HashMap},
+};
+
+/// A point where two clusters meet.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Waypoint {
+    pub position: [f32; 3],
+    pub clusters: [i32; 2],
+}
+
+/// A coarse navigation graph with a waypoint between every pair of touching clusters, linked to
+/// the other waypoints of both clusters. It knows nothing about steps, gaps or ledges, so it is
+/// only a first approximation of where an agent can go.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct WaypointGraph {
+    pub waypoints: Vec<Waypoint>,
+    /// The neighbours of each waypoint, along with the distance to them.
+    pub edges: Vec<Vec<(usize, f32)>>,
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3)
+        .map(|i| (a[i] - b[i]) * (a[i] - b[i]))
+        .sum::<f32>()
+        .sqrt()
+}
+
+#[derive(PartialEq)]
+struct Visit {
+    cost: f32,
+    waypoint: usize,
+}
+
+impl Eq for Visit {}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .partial_cmp(&self.cost)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Scale for WaypointGraph {
+    fn scale(&mut self, scale: f32) {
+        for waypoint in &mut self.waypoints {
+            waypoint.position.scale(scale);
+        }
+        for edges in &mut self.edges {
+            for (_, distance) in edges {
+                *distance *= scale;
+            }
+        }
+    }
+}
+
+impl WaypointGraph {
+    pub(crate) fn new(leaves: &[VisLeaf]) -> Self {
+        // Leaves that share a face have bounds that touch, so this is used as a stand-in for the
+        // portals that Quake 3 doesn't keep in the BSP.
+        const TOUCHING: f32 = 0.5;
+
+        let mut portals = BTreeMap::<(i32, i32), ([f32; 3], usize)>::new();
+
+        for (i, a) in leaves.iter().enumerate().filter(|(_, l)| l.cluster >= 0) {
+            let grown = |v: [f32; 3], d: f32| [v[0] + d, v[1] + d, v[2] + d];
+            let bounds = (grown(a.mins, -TOUCHING), grown(a.maxs, TOUCHING));
+
+            for b in leaves[i + 1..]
+                .iter()
+                .filter(|b| b.cluster >= 0 && b.cluster != a.cluster)
+            {
+                if !bounds_overlap(bounds, (b.mins, b.maxs)) {
+                    continue;
+                }
+
+                let mut centre = [0.0; 3];
+                for axis in 0..3 {
+                    let min = a.mins[axis].max(b.mins[axis]);
+                    let max = a.maxs[axis].min(b.maxs[axis]);
+                    centre[axis] = (min + max) / 2.0;
+                }
+
+                let key = (a.cluster.min(b.cluster), a.cluster.max(b.cluster));
+                let (sum, count) = portals.entry(key).or_insert(([0.0; 3], 0));
+                for axis in 0..3 {
+                    sum[axis] += centre[axis];
+                }
+                *count += 1;
+            }
+        }
+
+        let waypoints = portals
+            .into_iter()
+            .map(|((a, b), (sum, count))| {
+                let n = count as f32;
+                Waypoint {
+                    position: to_world_space([sum[0] / n, sum[1] / n, sum[2] / n]),
+                    clusters: [a, b],
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let edges = waypoints
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                waypoints
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, b)| j != i && a.clusters.iter().any(|c| b.clusters.contains(c)))
+                    .map(|(j, b)| (j, distance(a.position, b.position)))
+                    .collect()
+            })
+            .collect();
+
+        WaypointGraph { waypoints, edges }
+    }
+
+    /// The waypoint closest to a point, ignoring walls.
+    pub fn nearest(&self, point: [f32; 3]) -> Option<usize> {
+        self.waypoints
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                distance(a.position, point)
+                    .partial_cmp(&distance(b.position, point))
+                    .unwrap_or(Ordering::Equal)
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// The shortest path between two waypoints, including both ends.
+    pub fn path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let mut costs = vec![std::f32::INFINITY; self.waypoints.len()];
+        let mut previous = vec![None; self.waypoints.len()];
+        let mut queue = BinaryHeap::new();
+
+        *costs.get_mut(from)? = 0.0;
+        queue.push(Visit {
+            cost: 0.0,
+            waypoint: from,
+        });
+
+        while let Some(Visit { cost, waypoint }) = queue.pop() {
+            if waypoint == to {
+                let mut path = vec![to];
+                while let Some(prev) = previous[*path.last()?] {
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            if cost > costs[waypoint] {
+                continue;
+            }
+
+            for &(next, length) in &self.edges[waypoint] {
+                let cost = cost + length;
+                if cost < costs[next] {
+                    costs[next] = cost;
+                    previous[next] = Some(waypoint);
+                    queue.push(Visit {
+                        cost,
+                        waypoint: next,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Roughly how far a sound travels from `from` to `to` around walls, as the shortest path
+    /// through the waypoints between clusters, for attenuating sounds heard around corners.
+    /// Points in the same cluster are the straight line apart. Returns `None` for points outside
+    /// the map or in solid, and for points in areas separated by closed portals, which sound
+    /// can't get through.
+    ///
+    /// `from` and `to` are in world space, and `root` is the `GlobalTransform` of the map's root,
+    /// so the distance is in world units however the map has been moved or scaled.
+    pub fn acoustic_distance(
+        &self,
+        vis: &MapVis,
+        root: Option<&GlobalTransform>,
+        from: [f32; 3],
+        to: [f32; 3],
+    ) -> Option<f32> {
+        let (from, to) = (vis::to_map_space(root, from), vis::to_map_space(root, to));
+        // `ImportOptions::scale` is already applied to the graph, so this is only whatever
+        // uniform scale the game has put on the root.
+        let scale = root.map_or(1.0, |root| root.0.transform_vector(&Vector3::x()).norm());
+
+        let leaf = |point| {
+            vis.leaf_at(point)
+                .and_then(|leaf| vis.leaves.get(leaf))
+                .filter(|leaf| leaf.cluster >= 0)
+        };
+        let (start, end) = (leaf(from)?, leaf(to)?);
+
+        if start.area >= 0
+            && end.area >= 0
+            && vis.connected_areas(start.area).get(end.area as usize) == Some(&false)
+        {
+            return None;
+        }
+
+        self.portal_distance((from, start.cluster), (to, end.cluster))
+            .map(|distance| distance * scale)
+    }
+
+    /// The shortest path from `from` in cluster `start` to `to` in cluster `end` through the
+    /// waypoints, in map units.
+    pub(crate) fn portal_distance(
+        &self,
+        (from, start): ([f32; 3], i32),
+        (to, end): ([f32; 3], i32),
+    ) -> Option<f32> {
+        if start == end {
+            return Some(distance(from, to));
+        }
+
+        let mut costs = vec![std::f32::INFINITY; self.waypoints.len()];
+        let mut queue = BinaryHeap::new();
+        for (i, waypoint) in self.waypoints.iter().enumerate() {
+            if waypoint.clusters.contains(&start) {
+                costs[i] = distance(from, waypoint.position);
+                queue.push(Visit {
+                    cost: costs[i],
+                    waypoint: i,
+                });
+            }
+        }
+
+        let mut best = None::<f32>;
+        while let Some(Visit { cost, waypoint }) = queue.pop() {
+            // The last leg only adds to the cost, so nothing left can beat the best path.
+            if best.map_or(false, |best| cost >= best) {
+                break;
+            }
+            if cost > costs[waypoint] {
+                continue;
+            }
+
+            let position = self.waypoints[waypoint].position;
+            if self.waypoints[waypoint].clusters.contains(&end) {
+                let total = cost + distance(position, to);
+                best = Some(best.map_or(total, |best| best.min(total)));
+            }
+
+            for &(next, length) in &self.edges[waypoint] {
+                let cost = cost + length;
+                if cost < costs[next] {
+                    costs[next] = cost;
+                    queue.push(Visit {
+                        cost,
+                        waypoint: next,
+                    });
+                }
+            }
+        }
+
+        best
+    }
+}
+
+/// The waypoint graph of each map imported with `ImportOptions::waypoints`, keyed by the root
+/// entity of the map.
+#[derive(Default)]
+pub struct Waypoints {
+    pub maps: HashMap<Entity, WaypointGraph>,
+}
+
+impl<'a> PrefabData<'a> for WaypointGraph {
+    type SystemData = Write<'a, Waypoints>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        waypoints: &mut Self::SystemData,
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        waypoints.maps.insert(entity, self.clone());
+        Ok(())
+    }
+
+    fn load_sub_assets(
+        &mut self,
+        _: &mut ProgressCounter,
+        _: &mut Self::SystemData,
+    ) -> Result<bool, Error> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaf(cluster: i32, x: f32) -> VisLeaf {
+        VisLeaf {
+            cluster,
+            area: 0,
+            mins: [x, 0.0, 0.0],
+            maxs: [x + 64.0, 64.0, 64.0],
+        }
+    }
+
+    #[test]
+    fn links_touching_clusters() {
+        // Three rooms in a row, plus a solid leaf that should be ignored.
+        let graph =
+            WaypointGraph::new(&[leaf(0, 0.0), leaf(1, 64.0), leaf(2, 128.0), leaf(-1, 192.0)]);
+
+        assert_eq!(graph.waypoints.len(), 2);
+        assert_eq!(graph.waypoints[0].clusters, [0, 1]);
+        assert_eq!(graph.waypoints[0].position[0], 64.0);
+        assert_eq!(graph.path(0, 1), Some(vec![0, 1]));
+        assert_eq!(graph.nearest([200.0, 0.0, 0.0]), Some(1));
+    }
+
+    #[test]
+    fn measures_sound_around_corners() {
+        // An L of three rooms, where sound from the first to the last goes through the corner.
+        let graph = WaypointGraph {
+            waypoints: vec![
+                Waypoint {
+                    position: [64.0, 0.0, 0.0],
+                    clusters: [0, 1],
+                },
+                Waypoint {
+                    position: [64.0, 0.0, 64.0],
+                    clusters: [1, 2],
+                },
+            ],
+            edges: vec![vec![(1, 64.0)], vec![(0, 64.0)]],
+        };
+
+        let from = ([0.0, 0.0, 0.0], 0);
+        assert_eq!(
+            graph.portal_distance(from, ([32.0, 0.0, 0.0], 0)),
+            Some(32.0)
+        );
+        assert_eq!(
+            graph.portal_distance(from, ([32.0, 0.0, 64.0], 2)),
+            Some(64.0 + 64.0 + 32.0)
+        );
+        assert_eq!(graph.portal_distance(from, ([0.0; 3], 3)), None);
+    }
+
+    #[cfg(feature = "test_support")]
+    #[test]
+    fn measures_sound_in_scaled_maps() {
+        use crate::{entities, test_support};
+        use amethyst::core::nalgebra::Matrix4;
+        use bsp::Bsp;
+        use std::io::Cursor;
+
+        let bsp = Bsp::read(Cursor::new(test_support::two_cluster_map())).unwrap();
+        let vis = MapVis::new(
+            &bsp,
+            &entities::parse_entities(entities::entity_string(&bsp)),
+        );
+        let graph = WaypointGraph::new(&vis.leaves);
+
+        // The player starts of the fixture, one in each cluster.
+        let (from, to) = ([64.0, 24.0, -64.0], [192.0, 24.0, -64.0]);
+        let unscaled = graph.acoustic_distance(&vis, None, from, to).unwrap();
+        assert!(unscaled >= distance(from, to));
+
+        let root = GlobalTransform(Matrix4::new_scaling(2.0));
+        let scaled = |[x, y, z]: [f32; 3]| [x * 2.0, y * 2.0, z * 2.0];
+        let heard = graph
+            .acoustic_distance(&vis, Some(&root), scaled(from), scaled(to))
+            .unwrap();
+        assert!((heard - unscaled * 2.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,64 @@
+use amethyst_bsp::{
+    bsp::Bsp, face_groups, parse_entities, test_support, validate, BspAsset, BspExtensions,
+    BspFormat, ImportOptions, LightStyles,
+};
+use std::io::Cursor;
+
+fn fixture() -> BspAsset {
+    let bytes = test_support::two_cluster_map();
+    let extensions = BspExtensions::read(&mut Cursor::new(&bytes)).unwrap();
+    BspAsset(Bsp::read(Cursor::new(bytes)).unwrap(), extensions)
+}
+
+#[test]
+fn fixture_is_valid() {
+    let asset = fixture();
+
+    assert!(!validate(&asset.0).has_errors());
+    assert_eq!(parse_entities(asset.entities_str()).len(), 4);
+}
+
+#[test]
+fn groups_faces_per_cluster() {
+    let asset = fixture();
+    let groups = face_groups(&asset.0, &asset.1, &ImportOptions::<()>::default());
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].texture_name, "textures/base/floor");
+    assert_eq!(groups[0].vertex_count(), 6);
+    assert!(groups.iter().all(|g| g.lightmap_page.is_none()));
+}
+
+#[test]
+fn groups_faces_deterministically() {
+    let asset = fixture();
+    let options = ImportOptions::<()>::default();
+    let groups = face_groups(&asset.0, &asset.1, &options);
+
+    assert_eq!(groups, face_groups(&fixture().0, &fixture().1, &options));
+    assert_eq!(
+        groups.iter().map(|g| g.cluster).collect::<Vec<_>>(),
+        vec![Some(0), Some(1)]
+    );
+}
+
+#[test]
+fn reads_face_styles_from_lmstyle() {
+    let map = test_support::FixtureBuilder::new()
+        .with_bspx_lump("LMSTYLE", &[0, 2, 255, 255, 0, 255, 255, 255])
+        .build();
+    let prefab = BspFormat
+        .import_reader(Cursor::new(map), ImportOptions::<()>::default())
+        .unwrap();
+
+    let styles = prefab
+        .entities()
+        .filter_map(|entity| entity.data()?.light_styles)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        styles,
+        vec![LightStyles {
+            styles: [0, 2, 255, 255]
+        }]
+    );
+}